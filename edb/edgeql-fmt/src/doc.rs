@@ -0,0 +1,150 @@
+//! Layout documents and the printer
+//!
+//! This is a variant of Wadler's "prettier printer": a `Group` is printed
+//! on a single line if it fits into the remaining width, otherwise every
+//! `Line` directly inside it is turned into a line break.
+
+
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// Space, or line break if the enclosing group is broken
+    Line,
+    /// Nothing, or line break if the enclosing group is broken
+    SoftLine,
+    /// Line break which also breaks all the enclosing groups
+    HardLine,
+    /// Text printed just before the next line break, i.e. a trailing
+    /// comment. Enclosing groups are broken.
+    LineSuffix(String),
+    /// Increases indentation of the line breaks inside
+    Indent(Vec<Doc>),
+    Group(Vec<Doc>),
+    Concat(Vec<Doc>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+type Command<'a> = (usize, Mode, &'a Doc);
+
+impl Doc {
+    pub fn text<S: Into<String>>(s: S) -> Doc {
+        Doc::Text(s.into())
+    }
+}
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// Checks whether `next` fits in `width` printed flat
+///
+/// Commands from the `rest` are also checked up to the first line break,
+/// so the closing bracket or a comma is not pushed over the limit.
+fn fits(next: Command, rest: &[Command], width: usize) -> bool {
+    let mut left = width as isize;
+    let mut rest_idx = rest.len();
+    let mut cmds = vec![next];
+    while left >= 0 {
+        let (indent, mode, doc) = match cmds.pop() {
+            Some(cmd) => cmd,
+            None if rest_idx == 0 => return true,
+            None => {
+                rest_idx -= 1;
+                rest[rest_idx]
+            }
+        };
+        match doc {
+            Doc::Text(s) => left -= self::width(s) as isize,
+            Doc::Line if mode == Mode::Flat => left -= 1,
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine => return true,
+            Doc::HardLine => return mode == Mode::Break,
+            Doc::LineSuffix(_) if mode == Mode::Flat => return false,
+            Doc::LineSuffix(_) => {}
+            Doc::Indent(items) | Doc::Group(items) | Doc::Concat(items) => {
+                for item in items.iter().rev() {
+                    cmds.push((indent, mode, item));
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Renders the document
+///
+/// Trailing whitespace is stripped from every line, and the result always
+/// ends with a newline unless it's empty.
+pub fn print(doc: &Doc, indent_width: usize, max_width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut suffix: Vec<&str> = Vec::new();
+    let mut cmds: Vec<Command> = vec![(0, Mode::Break, doc)];
+    while let Some((indent, mode, doc)) = cmds.pop() {
+        match doc {
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += width(s);
+            }
+            Doc::Line if mode == Mode::Flat => {
+                out.push(' ');
+                column += 1;
+            }
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                newline(&mut out, &mut suffix, indent);
+                column = indent;
+            }
+            Doc::LineSuffix(s) => suffix.push(s),
+            Doc::Indent(items) => {
+                for item in items.iter().rev() {
+                    cmds.push((indent + indent_width, mode, item));
+                }
+            }
+            Doc::Group(items) => {
+                let mode = if mode == Mode::Flat
+                    || fits((indent, Mode::Flat, doc), &cmds,
+                            max_width.saturating_sub(column))
+                {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                for item in items.iter().rev() {
+                    cmds.push((indent, mode, item));
+                }
+            }
+            Doc::Concat(items) => {
+                for item in items.iter().rev() {
+                    cmds.push((indent, mode, item));
+                }
+            }
+        }
+    }
+    for s in suffix.drain(..) {
+        out.push_str(s);
+    }
+    let len = out.trim_end().len();
+    out.truncate(len);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+fn newline(out: &mut String, suffix: &mut Vec<&str>, indent: usize) {
+    for s in suffix.drain(..) {
+        out.push_str(s);
+    }
+    let len = out.trim_end_matches(' ').len();
+    out.truncate(len);
+    out.push('\n');
+    for _ in 0..indent {
+        out.push(' ');
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt;
+use std::error::Error as StdError;
+
+use edgeql_parser::position::Pos;
+
+
+/// Error for text that can't be formatted without changing its meaning
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub message: String,
+    pub position: Pos,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.position)
+    }
+}
+
+impl StdError for Error {}
@@ -0,0 +1,602 @@
+//! Layout rules for EdgeQL queries, DDL and SDL
+use edgeql_parser::keywords::UNRESERVED_KEYWORDS;
+use edgeql_parser::position::Pos;
+use edgeql_parser::tokenizer::{Kind, TokenStream};
+
+use crate::doc::{self, Doc};
+use crate::error::Error;
+use crate::tree::{self, Item, Node, Tok};
+
+
+/// Statements laid out clause by clause
+const QUERY_WORDS: &[&str] = &[
+    "with", "select", "for", "insert", "update", "delete", "group",
+];
+
+/// Keywords starting a new clause in a query
+const CLAUSE_WORDS: &[&str] = &[
+    "with", "select", "filter", "order", "limit", "offset", "insert",
+    "update", "set", "delete", "for", "union", "group", "using", "into",
+];
+
+/// Starts of DDL and SDL statements, such a statement may end with a
+/// block instead of a semicolon
+const SCHEMA_WORDS: &[&str] = &[
+    "module", "type", "abstract", "scalar", "final", "function", "alias",
+    "annotation", "constraint", "link", "property", "required", "optional",
+    "single", "multi", "overloaded", "index", "delegated", "inheritable",
+    "create", "alter", "drop", "start", "populate", "commit", "abort",
+    "configure", "set", "reset", "rename", "using", "on",
+];
+
+/// Unreserved keywords commonly followed by a parenthesized expression
+const PAREN_WORDS: &[&str] = &["using", "on"];
+
+/// Unreserved keywords of the ORDER BY clause, recased when they follow
+/// an expression (and `FIRST` and `LAST` when they follow `EMPTY`)
+const ORDER_WORDS: &[&str] = &["asc", "desc", "then"];
+
+/// Types which are followed by subtypes in angle brackets
+const COLLECTIONS: &[&str] = &["array", "tuple", "enum"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeywordCase {
+    Upper,
+    Lower,
+    Preserve,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Number of spaces per indentation level
+    pub indent: usize,
+    /// Lines longer than this are wrapped where possible
+    pub max_width: usize,
+    /// Case of reserved keywords, and of unreserved ones where they can't
+    /// be identifiers (`BY`, `ASC`, `DESC`, `THEN`, `FIRST` and `LAST` of
+    /// ORDER BY, and words of DDL and SDL statements), other words are
+    /// left intact
+    pub keyword_case: KeywordCase,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            indent: 4,
+            max_width: 79,
+            keyword_case: KeywordCase::Upper,
+        }
+    }
+}
+
+/// Formats EdgeQL queries, DDL commands or SDL schema
+///
+/// The output is tokenized again and compared to the input, if anything
+/// except whitespace, comments and keyword case differs, an error is
+/// returned instead, so the output can be safely written over the input.
+pub fn format(text: &str, config: &Config) -> Result<String, Error> {
+    let items = tree::parse(text)?;
+    let formatter = Formatter { config };
+    let doc = formatter.statements(&items);
+    let out = doc::print(&doc, config.indent, config.max_width);
+    check_tokens(text, &out)?;
+    Ok(out)
+}
+
+/// Makes sure that formatting changed only whitespace, comments, empty
+/// statements and case of keywords, so the output has the same meaning
+/// as the input
+fn check_tokens(text: &str, out: &str) -> Result<(), Error> {
+    // the input was tokenized by `tree::parse` already
+    let before = significant(text).unwrap_or_default();
+    let after = significant(out).map_err(|position| Error {
+        message: "formatter produced invalid tokens".into(),
+        position,
+    })?;
+    for (idx, old) in before.iter().enumerate() {
+        let same = match after.get(idx) {
+            Some(new) => new.kind == old.kind && match old.kind {
+                Kind::Keyword => new.value.eq_ignore_ascii_case(old.value),
+                Kind::Ident => new.value == old.value
+                    || new.value.eq_ignore_ascii_case(old.value)
+                    && UNRESERVED_KEYWORDS.contains(
+                        &&old.value.to_ascii_lowercase()[..]),
+                _ => new.value == old.value,
+            },
+            None => false,
+        };
+        if !same {
+            return Err(Error {
+                message: format!("formatter changed the meaning of `{}`",
+                                 old.value),
+                position: old.start,
+            });
+        }
+    }
+    if let Some(new) = after.get(before.len()) {
+        return Err(Error {
+            message: format!("formatter added `{}`", new.value),
+            position: new.start,
+        });
+    }
+    Ok(())
+}
+
+struct SigToken<'a> {
+    kind: Kind,
+    value: &'a str,
+    start: Pos,
+}
+
+/// Tokens except whitespace, comments and semicolons of empty statements
+fn significant(text: &str) -> Result<Vec<SigToken<'_>>, Pos> {
+    let mut stream = TokenStream::new(text);
+    let mut tokens = Vec::new();
+    let mut empty = true;
+    while let Some(tok) = (&mut stream).next() {
+        let tok = tok.map_err(|_| stream.current_pos())?;
+        let semicolon = tok.token.kind == Kind::Semicolon;
+        if !(semicolon && empty) {
+            tokens.push(SigToken {
+                kind: tok.token.kind,
+                value: tok.token.value,
+                start: tok.start,
+            });
+        }
+        empty = semicolon;
+    }
+    Ok(tokens)
+}
+
+/// Role of the previous token in a run, defines spacing of the next one
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prev {
+    Start,
+    Operand,
+    Name,
+    CastClose,
+    Unary,
+    Glue,
+    Word,
+    Other,
+}
+
+enum Boundary {
+    None,
+    Separator,
+    After,
+}
+
+/// Part of a sequence split by separators
+struct Part<'i, 'a> {
+    items: Vec<&'i Item<'a>>,
+    separator: Option<Tok<'a>>,
+    /// Comments on the same line after the separator
+    trailing: Vec<&'a str>,
+}
+
+struct Formatter<'c> {
+    config: &'c Config,
+}
+
+/// Mutable state of `Formatter::run`
+struct Run {
+    /// Unreserved keywords are recased in DDL and SDL
+    schema: bool,
+    /// Colon is a part of a slice, rather than a type or a shape element
+    slice: bool,
+    docs: Vec<Doc>,
+    prev: Prev,
+    expect_operand: bool,
+    /// Open angle brackets, `true` for type casts
+    angles: Vec<bool>,
+    after_assign: bool,
+    after_colon: bool,
+    prev_keyword: Option<String>,
+    /// Inside of the ORDER BY clause
+    order_by: bool,
+}
+
+fn is_word_in(item: &Item, words: &[&str]) -> bool {
+    words.iter().any(|w| item.node.is_word(w))
+}
+
+fn first_code<'i, 'a>(items: &[&'i Item<'a>]) -> Option<&'i Item<'a>> {
+    items.iter().find(|i| !i.node.is_comment()).copied()
+}
+
+fn is_schema(items: &[&Item]) -> bool {
+    first_code(items).map(|i| is_word_in(i, SCHEMA_WORDS)).unwrap_or(false)
+}
+
+fn split<'i, 'a, F>(items: &'i [Item<'a>], mut boundary: F)
+    -> Vec<Part<'i, 'a>>
+    where F: FnMut(&[&'i Item<'a>], &'i Item<'a>, Option<&'i Item<'a>>)
+             -> Boundary
+{
+    let mut parts: Vec<Part> = Vec::new();
+    let mut cur = Vec::new();
+    for (idx, item) in items.iter().enumerate() {
+        if let Node::Comment { text, own_line: false } = item.node {
+            if cur.is_empty() {
+                if let Some(last) = parts.last_mut() {
+                    last.trailing.push(text);
+                    continue;
+                }
+            }
+        }
+        let next = items[idx+1..].iter().find(|i| !i.node.is_comment());
+        match boundary(&cur, item, next) {
+            Boundary::None => cur.push(item),
+            Boundary::Separator => parts.push(Part {
+                items: std::mem::take(&mut cur),
+                separator: item.node.token(),
+                trailing: Vec::new(),
+            }),
+            Boundary::After => {
+                cur.push(item);
+                parts.push(Part {
+                    items: std::mem::take(&mut cur),
+                    separator: None,
+                    trailing: Vec::new(),
+                });
+            }
+        }
+    }
+    if !cur.is_empty() {
+        parts.push(Part { items: cur, separator: None, trailing: Vec::new() });
+    }
+    parts
+}
+
+impl<'c> Formatter<'c> {
+    fn keyword(&self, value: &str) -> String {
+        match self.config.keyword_case {
+            KeywordCase::Upper => value.to_ascii_uppercase(),
+            KeywordCase::Lower => value.to_ascii_lowercase(),
+            KeywordCase::Preserve => value.to_string(),
+        }
+    }
+
+    /// Statements of a file or a DDL/SDL block, one per line
+    fn statements(&self, items: &[Item]) -> Doc {
+        let parts = split(items, |cur, item, next| {
+            if item.node.is_kind(Kind::Semicolon) {
+                return Boundary::Separator;
+            }
+            let is_block = match item.node {
+                Node::Bracket { open, .. } => open.kind == Kind::OpenBrace,
+                _ => false,
+            };
+            if is_block && is_schema(cur)
+                && next.map(|n| is_word_in(n, SCHEMA_WORDS)).unwrap_or(false)
+            {
+                return Boundary::After;
+            }
+            Boundary::None
+        });
+        let mut docs = Vec::new();
+        for part in &parts {
+            let blank = part.items.first().map(|i| i.blank_before);
+            if part.items.is_empty() && part.trailing.is_empty() {
+                // empty statement
+                continue;
+            }
+            if !docs.is_empty() {
+                docs.push(Doc::HardLine);
+                if blank == Some(true) {
+                    docs.push(Doc::HardLine);
+                }
+            }
+            if !part.items.is_empty() {
+                docs.push(self.statement(&part.items, false));
+                if let Some(sep) = part.separator {
+                    docs.push(Doc::text(sep.value));
+                }
+            }
+            for comment in &part.trailing {
+                docs.push(Doc::LineSuffix(format!("  {}", comment)));
+            }
+        }
+        Doc::Concat(docs)
+    }
+
+    /// Statement or an expression, queries are split into clauses
+    fn statement(&self, items: &[&Item], slice: bool) -> Doc {
+        let schema = is_schema(items);
+        let query = first_code(items)
+            .map(|i| is_word_in(i, QUERY_WORDS))
+            .unwrap_or(false);
+        if !query {
+            return self.run(items, schema, slice);
+        }
+        let mut clauses = Vec::new();
+        let mut cur: Vec<&Item> = Vec::new();
+        for &item in items {
+            let starts_clause = is_word_in(item, CLAUSE_WORDS)
+                && cur.iter().any(|i| !i.node.is_comment());
+            if starts_clause {
+                // comments just above the keyword belong to the clause
+                let mut idx = cur.len();
+                while idx > 0 && matches!(cur[idx-1].node,
+                                          Node::Comment { own_line: true, .. })
+                {
+                    idx -= 1;
+                }
+                let comments = cur.split_off(idx);
+                clauses.push(std::mem::replace(&mut cur, comments));
+            }
+            cur.push(item);
+        }
+        clauses.push(cur);
+        let mut docs = Vec::new();
+        for clause in &clauses {
+            if !docs.is_empty() {
+                docs.push(Doc::Line);
+            }
+            docs.push(self.run(clause, false, slice));
+        }
+        Doc::Group(docs)
+    }
+
+    /// Sequence of tokens and brackets with spacing between them
+    fn run(&self, items: &[&Item], schema: bool, slice: bool) -> Doc {
+        let mut run = Run {
+            schema,
+            slice,
+            docs: Vec::new(),
+            prev: Prev::Start,
+            expect_operand: true,
+            angles: Vec::new(),
+            after_assign: false,
+            after_colon: false,
+            prev_keyword: None,
+            order_by: false,
+        };
+        for (idx, item) in items.iter().enumerate() {
+            match &item.node {
+                Node::Comment { text, own_line: false } => {
+                    run.docs.push(Doc::LineSuffix(format!("  {}", text)));
+                }
+                Node::Comment { text, own_line: true } => {
+                    if !run.docs.is_empty() {
+                        run.docs.push(Doc::HardLine);
+                    }
+                    run.docs.push(Doc::text(*text));
+                    if idx + 1 < items.len() {
+                        run.docs.push(Doc::HardLine);
+                    }
+                    run.prev = Prev::Start;
+                }
+                Node::Token(tok) => {
+                    let next = items[idx+1..].iter()
+                        .find(|i| !i.node.is_comment())
+                        .map(|i| &i.node);
+                    self.token(&mut run, *tok, next);
+                }
+                Node::Bracket { open, items, close } => {
+                    let space = match open.kind {
+                        Kind::OpenParen => !matches!(run.prev,
+                            Prev::Start | Prev::Glue | Prev::Unary
+                            | Prev::CastClose | Prev::Name),
+                        Kind::OpenBracket => !matches!(run.prev,
+                            Prev::Start | Prev::Glue | Prev::Unary
+                            | Prev::CastClose | Prev::Name | Prev::Operand),
+                        _ => !matches!(run.prev,
+                            Prev::Start | Prev::Glue | Prev::Unary
+                            | Prev::CastClose),
+                    };
+                    if space {
+                        run.docs.push(Doc::text(" "));
+                    }
+                    let block = open.kind == Kind::OpenBrace && (
+                        schema && !run.after_assign
+                        || items.iter()
+                            .any(|i| i.node.is_kind(Kind::Semicolon))
+                    );
+                    let set_like = run.expect_operand && !run.after_colon
+                        && !items.iter().any(|i| i.node.is_kind(Kind::Assign));
+                    run.docs.push(
+                        self.bracket(*open, items, *close, block, set_like));
+                    run.prev = Prev::Operand;
+                    run.expect_operand = false;
+                    run.after_colon = false;
+                    run.prev_keyword = None;
+                }
+            }
+        }
+        Doc::Group(run.docs)
+    }
+
+    fn token(&self, run: &mut Run, tok: Tok, next: Option<&Node>) {
+        use Kind::*;
+
+        let after_glue = run.prev == Prev::Glue;
+        let mut space = !matches!(run.prev,
+            Prev::Start | Prev::Glue | Prev::Unary | Prev::CastClose);
+        let mut value = tok.value.to_string();
+        let mut keyword = None;
+        let role = match tok.kind {
+            Comma | Semicolon => {
+                space = false;
+                Prev::Other
+            }
+            Colon => {
+                space = false;
+                if run.slice { Prev::Glue } else { Prev::Other }
+            }
+            Dot | ForwardLink | BackwardLink | At | Namespace => {
+                space = space && !matches!(run.prev,
+                                           Prev::Operand | Prev::Name);
+                Prev::Glue
+            }
+            Less if run.expect_operand || !run.angles.is_empty()
+                || run.prev == Prev::Name && COLLECTIONS.iter()
+                    .any(|c| run.prev_keyword.as_deref() == Some(c))
+            => {
+                let cast = run.angles.is_empty() && run.expect_operand;
+                if !cast {
+                    space = false;
+                }
+                run.angles.push(cast);
+                Prev::Glue
+            }
+            Greater if !run.angles.is_empty() => {
+                space = false;
+                let cast = run.angles.pop() == Some(true);
+                if cast { Prev::CastClose } else { Prev::Operand }
+            }
+            Add | Sub if run.expect_operand => Prev::Unary,
+            Assign => {
+                run.after_assign = true;
+                Prev::Other
+            }
+            Ident if after_glue => Prev::Name,
+            Ident => {
+                let lower = tok.value.to_ascii_lowercase();
+                let paren_word = PAREN_WORDS.contains(&&lower[..]);
+                let is_keyword = if run.schema {
+                    // a keyword is followed by either another word or
+                    // an expression, while a name is followed by
+                    // punctuation
+                    match next {
+                        Some(Node::Token(t)) => matches!(t.kind,
+                            Keyword | Ident | BacktickName)
+                            && matches!(run.prev, Prev::Start | Prev::Word)
+                            && UNRESERVED_KEYWORDS.contains(&&lower[..]),
+                        Some(Node::Bracket { open, .. }) => paren_word
+                            && open.kind == OpenParen,
+                        _ => false,
+                    }
+                } else if lower == "by"
+                    && run.prev_keyword.as_deref() == Some("order")
+                {
+                    run.order_by = true;
+                    true
+                } else if run.order_by {
+                    let prev = run.prev_keyword.as_deref();
+                    ORDER_WORDS.contains(&&lower[..])
+                        && matches!(run.prev, Prev::Operand | Prev::Name)
+                    || lower == "then" && matches!(prev,
+                        Some("asc") | Some("desc")
+                        | Some("first") | Some("last"))
+                    || (lower == "first" || lower == "last")
+                        && prev == Some("empty")
+                } else {
+                    false
+                };
+                let role = if is_keyword {
+                    value = self.keyword(tok.value);
+                    Prev::Word
+                } else if paren_word {
+                    Prev::Word
+                } else {
+                    Prev::Name
+                };
+                keyword = Some(lower);
+                role
+            }
+            Keyword => {
+                let lower = tok.value.to_ascii_lowercase();
+                if CLAUSE_WORDS.contains(&&lower[..]) {
+                    run.order_by = false;
+                }
+                let role = if lower.starts_with("__") {
+                    Prev::Operand
+                } else if lower == "true" || lower == "false" {
+                    if self.config.keyword_case != KeywordCase::Preserve {
+                        value = lower.clone();
+                    }
+                    Prev::Operand
+                } else {
+                    if !after_glue {
+                        value = self.keyword(tok.value);
+                    }
+                    Prev::Word
+                };
+                if (lower == "and" || lower == "or") && run.angles.is_empty()
+                {
+                    run.docs.push(Doc::Indent(vec![Doc::Line]));
+                    space = false;
+                }
+                keyword = Some(lower);
+                role
+            }
+            BacktickName => Prev::Name,
+            DecimalConst | FloatConst | IntConst | BigIntConst | BinStr
+            | Str | Argument => Prev::Operand,
+            _ => Prev::Other,
+        };
+        if space {
+            run.docs.push(Doc::text(" "));
+        }
+        run.docs.push(Doc::Text(value));
+        run.expect_operand = !matches!(role, Prev::Operand | Prev::Name);
+        run.after_colon = tok.kind == Colon;
+        run.prev = role;
+        run.prev_keyword = keyword;
+    }
+
+    fn bracket(&self, open: Tok, items: &[Item], close: Tok,
+        block: bool, set_like: bool)
+        -> Doc
+    {
+        // comments on the line of the opening bracket stay there
+        let mut open_doc = vec![Doc::text(open.value)];
+        let mut items = items;
+        while let Some((Item { node: Node::Comment { text, own_line: false },
+                               .. }, rest)) = items.split_first()
+        {
+            open_doc.push(Doc::LineSuffix(format!("  {}", text)));
+            items = rest;
+        }
+        if items.is_empty() {
+            if open_doc.len() > 1 {
+                open_doc.push(Doc::HardLine);
+            }
+            open_doc.push(Doc::text(close.value));
+            return Doc::Concat(open_doc);
+        }
+        let open_doc = Doc::Concat(open_doc);
+        if block {
+            return Doc::Concat(vec![
+                open_doc,
+                Doc::Indent(vec![Doc::HardLine, self.statements(items)]),
+                Doc::HardLine,
+                Doc::text(close.value),
+            ]);
+        }
+        let line = if open.kind == Kind::OpenBrace && !set_like {
+            Doc::Line
+        } else {
+            Doc::SoftLine
+        };
+        let slice = open.kind == Kind::OpenBracket;
+        let parts = split(items, |_, item, _| {
+            if item.node.is_kind(Kind::Comma) {
+                Boundary::Separator
+            } else {
+                Boundary::None
+            }
+        });
+        let mut inner = vec![line.clone()];
+        for (idx, part) in parts.iter().enumerate() {
+            if idx > 0 {
+                inner.push(Doc::Line);
+            }
+            inner.push(self.statement(&part.items, slice));
+            if let Some(sep) = part.separator {
+                inner.push(Doc::text(sep.value));
+            }
+            for comment in &part.trailing {
+                inner.push(Doc::LineSuffix(format!("  {}", comment)));
+            }
+        }
+        Doc::Group(vec![
+            open_doc,
+            Doc::Indent(inner),
+            line,
+            Doc::text(close.value),
+        ])
+    }
+}
@@ -0,0 +1,17 @@
+//! Source code formatter for EdgeQL queries, DDL and SDL
+//!
+//! The formatter works on the token level, so it also handles text the
+//! parser doesn't know about yet. Comments are preserved.
+//!
+//! ```
+//! let cfg = edgeql_fmt::Config::default();
+//! let text = edgeql_fmt::format("select User {name} filter .id=1;", &cfg);
+//! assert_eq!(text.unwrap(), "SELECT User { name } FILTER .id = 1;\n");
+//! ```
+mod doc;
+mod error;
+mod format;
+mod tree;
+
+pub use error::Error;
+pub use format::{format, Config, KeywordCase};
@@ -0,0 +1,125 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::exit;
+
+use edgeql_fmt::{format, Config, KeywordCase};
+
+
+const USAGE: &str = "\
+Usage: edgeql-fmt [OPTIONS] [FILE...]
+
+Formats EdgeQL files in place, or stdin to stdout if no files are given.
+
+Options:
+    --check                 Don't write files, exit with 1 if any of them
+                            would be reformatted
+    --indent N              Spaces per indentation level (default 4)
+    --max-width N           Maximum line width (default 79)
+    --keyword-case CASE     upper, lower or preserve (default upper)
+    -h, --help              Print this help
+";
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("edgeql-fmt: {}\n\n{}", message, USAGE);
+    exit(2);
+}
+
+fn number(value: Option<String>, option: &str) -> usize {
+    value.and_then(|v| v.parse().ok()).unwrap_or_else(|| {
+        usage_error(&format!("{} requires a number", option))
+    })
+}
+
+fn main() {
+    let mut config = Config::default();
+    let mut check = false;
+    let mut files = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--check" => check = true,
+            "--indent" => config.indent = number(args.next(), &arg),
+            "--max-width" => config.max_width = number(args.next(), &arg),
+            "--keyword-case" => {
+                config.keyword_case = match args.next().as_deref() {
+                    Some("upper") => KeywordCase::Upper,
+                    Some("lower") => KeywordCase::Lower,
+                    Some("preserve") => KeywordCase::Preserve,
+                    _ => usage_error(
+                        "--keyword-case must be upper, lower or preserve"),
+                };
+            }
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return;
+            }
+            "--" => files.extend(&mut args),
+            _ if arg.starts_with('-') && arg != "-" => {
+                usage_error(&format!("unknown option {}", arg))
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        let mut text = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut text) {
+            eprintln!("<stdin>: {}", e);
+            exit(2);
+        }
+        match format(&text, &config) {
+            Ok(out) if check => {
+                if out != text {
+                    eprintln!("would reformat <stdin>");
+                    exit(1);
+                }
+            }
+            Ok(out) => {
+                io::stdout().write_all(out.as_bytes()).ok();
+            }
+            Err(e) => {
+                eprintln!("<stdin>: {}", e);
+                exit(2);
+            }
+        }
+        return;
+    }
+
+    let mut changed = false;
+    let mut failed = false;
+    for path in &files {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        let out = match format(&text, &config) {
+            Ok(out) => out,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        if out == text {
+            continue;
+        }
+        if check {
+            eprintln!("would reformat {}", path);
+            changed = true;
+        } else if let Err(e) = fs::write(path, out) {
+            eprintln!("{}: {}", path, e);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(2);
+    }
+    if changed {
+        exit(1);
+    }
+}
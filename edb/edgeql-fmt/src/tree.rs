@@ -0,0 +1,144 @@
+//! Token tree: tokens grouped by brackets, with comments kept in place
+use edgeql_parser::position::Pos;
+use edgeql_parser::tokenizer::{TokenStream, Kind};
+
+use crate::error::Error;
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tok<'a> {
+    pub kind: Kind,
+    pub value: &'a str,
+}
+
+#[derive(Debug)]
+pub enum Node<'a> {
+    Token(Tok<'a>),
+    Bracket {
+        open: Tok<'a>,
+        items: Vec<Item<'a>>,
+        close: Tok<'a>,
+    },
+    Comment {
+        text: &'a str,
+        /// Comment is the first thing on its line
+        own_line: bool,
+    },
+}
+
+/// Node with the layout of the original text preceding it
+#[derive(Debug)]
+pub struct Item<'a> {
+    pub node: Node<'a>,
+    /// There is an empty line before the node
+    pub blank_before: bool,
+}
+
+impl<'a> Node<'a> {
+    pub fn token(&self) -> Option<Tok<'a>> {
+        match self {
+            Node::Token(tok) => Some(*tok),
+            _ => None,
+        }
+    }
+    pub fn is_kind(&self, kind: Kind) -> bool {
+        self.token().map(|t| t.kind == kind).unwrap_or(false)
+    }
+    pub fn is_word(&self, word: &str) -> bool {
+        match self.token() {
+            Some(t) => matches!(t.kind, Kind::Keyword | Kind::Ident)
+                && t.value.eq_ignore_ascii_case(word),
+            None => false,
+        }
+    }
+    pub fn is_comment(&self) -> bool {
+        matches!(self, Node::Comment { .. })
+    }
+}
+
+fn closing(kind: Kind) -> Option<Kind> {
+    match kind {
+        Kind::OpenParen => Some(Kind::CloseParen),
+        Kind::OpenBracket => Some(Kind::CloseBracket),
+        Kind::OpenBrace => Some(Kind::CloseBrace),
+        _ => None,
+    }
+}
+
+/// Reads the whole text into a token tree
+///
+/// Fails on tokenizer errors and unbalanced brackets, as there is no
+/// reliable way to lay out such text.
+pub fn parse(text: &str) -> Result<Vec<Item<'_>>, Error> {
+    use combine::easy::Error::Unexpected;
+
+    let mut stream = TokenStream::new_lossless(text);
+    let mut stack: Vec<(Tok, Pos, Vec<Item>, bool)> = Vec::new();
+    let mut items = Vec::new();
+    let mut newlines = 0;
+    let mut at_start = true;
+    loop {
+        let tok = match (&mut stream).next() {
+            Some(Ok(tok)) => tok,
+            Some(Err(e)) => {
+                let message = match e {
+                    Unexpected(s) => s.to_string(),
+                    e => e.to_string(),
+                };
+                return Err(Error { message, position: stream.current_pos() });
+            }
+            None => break,
+        };
+        let kind = tok.token.kind;
+        let value = tok.token.value;
+        if kind == Kind::Whitespace {
+            newlines += value.matches('\n').count();
+            continue;
+        }
+        let blank_before = newlines > 1;
+        let node = if kind == Kind::Comment {
+            Node::Comment { text: value, own_line: at_start || newlines > 0 }
+        } else if closing(kind).is_some() {
+            let parent = std::mem::take(&mut items);
+            stack.push((Tok { kind, value }, tok.start, parent, blank_before));
+            newlines = 0;
+            at_start = false;
+            continue;
+        } else if matches!(kind, Kind::CloseParen | Kind::CloseBracket
+                                 | Kind::CloseBrace) {
+            let (open, parent, blank) = match stack.pop() {
+                Some((open, _, parent, blank))
+                    if closing(open.kind) == Some(kind)
+                => (open, parent, blank),
+                _ => return Err(Error {
+                    message: format!("unexpected `{}`", value),
+                    position: tok.start,
+                }),
+            };
+            let inner = std::mem::replace(&mut items, parent);
+            items.push(Item {
+                node: Node::Bracket {
+                    open,
+                    items: inner,
+                    close: Tok { kind, value },
+                },
+                blank_before: blank,
+            });
+            newlines = 0;
+            at_start = false;
+            continue;
+        } else {
+            Node::Token(Tok { kind, value })
+        };
+        items.push(Item { node, blank_before });
+        newlines = 0;
+        at_start = false;
+    }
+    if let Some((open, pos, _, _)) = stack.pop() {
+        return Err(Error {
+            message: format!("unclosed `{}`", open.value),
+            position: pos,
+        });
+    }
+    Ok(items)
+}
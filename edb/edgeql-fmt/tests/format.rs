@@ -0,0 +1,245 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use edgeql_fmt::{format, Config, KeywordCase};
+
+
+fn fmt(s: &str) -> String {
+    let out = format(s, &Config::default()).unwrap();
+    assert_eq!(format(&out, &Config::default()).unwrap(), out,
+               "formatting is not idempotent");
+    out
+}
+
+fn fmt_err(s: &str) -> String {
+    format(s, &Config::default()).unwrap_err().to_string()
+}
+
+#[test]
+fn keyword_case() {
+    assert_eq!(fmt("select 1"), "SELECT 1\n");
+    assert_eq!(fmt("Select User filter .name = 'x';"),
+               "SELECT User FILTER .name = 'x';\n");
+    assert_eq!(fmt("select true or False"), "SELECT true OR false\n");
+    assert_eq!(fmt("select __std__::len('x')"),
+               "SELECT __std__::len('x')\n");
+    assert_eq!(fmt("select User order by .name desc"),
+               "SELECT User ORDER BY .name DESC\n");
+    assert_eq!(fmt("select User order by .name asc empty first \
+                    then .email desc empty last then desc limit 1"),
+               "SELECT User\n\
+                ORDER BY .name ASC EMPTY FIRST THEN .email DESC EMPTY LAST \
+                THEN desc\n\
+                LIMIT 1\n");
+    assert_eq!(fmt("select desc filter .first order by (asc) then last"),
+               "SELECT desc FILTER .first ORDER BY (asc) THEN last\n");
+    let lower = Config {
+        keyword_case: KeywordCase::Lower,
+        .. Config::default()
+    };
+    assert_eq!(format("SELECT User FILTER .x;", &lower).unwrap(),
+               "select User filter .x;\n");
+    let preserve = Config {
+        keyword_case: KeywordCase::Preserve,
+        .. Config::default()
+    };
+    assert_eq!(format("Select  User;", &preserve).unwrap(),
+               "Select User;\n");
+}
+
+#[test]
+fn operators() {
+    assert_eq!(fmt("select 1+2*-3"), "SELECT 1 + 2 * -3\n");
+    assert_eq!(fmt("select (a:=1,b:=[1,2])"),
+               "SELECT (a := 1, b := [1, 2])\n");
+    assert_eq!(fmt("select .name ++ 'x' ?? 'y'"),
+               "SELECT .name ++ 'x' ?? 'y'\n");
+    assert_eq!(fmt("select User.friends@since"),
+               "SELECT User.friends@since\n");
+    assert_eq!(fmt("select User.<owner[IS Issue]"),
+               "SELECT User.<owner[IS Issue]\n");
+    assert_eq!(fmt("select x[1:2] ++ y[:-1]"),
+               "SELECT x[1:2] ++ y[:-1]\n");
+    assert_eq!(fmt("select a<b and c>d"), "SELECT a < b AND c > d\n");
+    assert_eq!(fmt("select (1,)"), "SELECT (1,)\n");
+    assert_eq!(fmt("select len(  'x' )"), "SELECT len('x')\n");
+}
+
+#[test]
+fn casts() {
+    assert_eq!(fmt("select < str >1"), "SELECT <str>1\n");
+    assert_eq!(fmt("select <array<str>>[]"), "SELECT <array<str>>[]\n");
+    assert_eq!(fmt("select <tuple<int64, str>>(1, 'x')"),
+               "SELECT <tuple<int64, str>>(1, 'x')\n");
+    assert_eq!(fmt("select <str>$0"), "SELECT <str>$0\n");
+}
+
+#[test]
+fn shapes() {
+    assert_eq!(fmt("select User{name,email}"),
+               "SELECT User { name, email }\n");
+    assert_eq!(fmt("select {1,2,3}"), "SELECT {1, 2, 3}\n");
+    assert_eq!(fmt("select {}"), "SELECT {}\n");
+    assert_eq!(fmt("insert User {name:='x'}"),
+               "INSERT User { name := 'x' }\n");
+    assert_eq!(fmt(r###"
+        select User {
+            name, email, friends: { name, email, @since },
+            description := 'a long computed description of the user',
+        } filter .name = 'test' and .email = 'test@example.com';
+    "###), r###"SELECT User {
+    name,
+    email,
+    friends: { name, email, @since },
+    description := 'a long computed description of the user',
+}
+FILTER .name = 'test' AND .email = 'test@example.com';
+"###);
+}
+
+#[test]
+fn long_filter() {
+    assert_eq!(fmt(r###"
+        SELECT User FILTER .name = 'test' AND .email = 'test@example.com'
+            and .active and not .deleted OR .admin
+    "###), r###"SELECT User
+FILTER .name = 'test'
+    AND .email = 'test@example.com'
+    AND .active
+    AND NOT .deleted
+    OR .admin
+"###);
+    assert_eq!(fmt("SELECT User FILTER .active AND .admin"),
+               "SELECT User FILTER .active AND .admin\n");
+}
+
+#[test]
+fn long_call() {
+    let config = Config { max_width: 30, .. Config::default() };
+    assert_eq!(
+        format("select some_function(argument_one, argument_two)",
+               &config).unwrap(),
+        "SELECT some_function(\n    argument_one,\n    argument_two\n)\n");
+}
+
+#[test]
+fn comments() {
+    assert_eq!(fmt("select 1; # one\n# two\nselect 2 # three"),
+               "SELECT 1;  # one\n# two\nSELECT 2  # three\n");
+    assert_eq!(fmt(r###"
+        SELECT User {
+            name,  # the name
+            # friends are shown too
+            friends,
+        }
+    "###), r###"SELECT User {
+    name,  # the name
+    # friends are shown too
+    friends,
+}
+"###);
+    assert_eq!(fmt("SELECT User {  # shape\n name }"),
+               "SELECT User {  # shape\n    name\n}\n");
+    assert_eq!(fmt("SELECT User\n# comment\nFILTER .x"),
+               "SELECT User\n# comment\nFILTER .x\n");
+}
+
+#[test]
+fn blank_lines() {
+    assert_eq!(fmt("select 1;\nselect 2;\n\n\n\nselect 3;"),
+               "SELECT 1;\nSELECT 2;\n\nSELECT 3;\n");
+    assert_eq!(fmt(";;"), "");
+    assert_eq!(fmt("  \n "), "");
+}
+
+#[test]
+fn ddl() {
+    assert_eq!(fmt(r###"
+        create type User extending Named {
+        create required property name -> str {create constraint exclusive;};
+        create multi link friends -> User;
+        };
+        create function inc(x: int64) -> int64 using (select x + 1);
+    "###), r###"CREATE TYPE User EXTENDING Named {
+    CREATE REQUIRED PROPERTY name -> str {
+        CREATE CONSTRAINT exclusive;
+    };
+    CREATE MULTI LINK friends -> User;
+};
+CREATE FUNCTION inc(x: int64) -> int64 USING (SELECT x + 1);
+"###);
+}
+
+#[test]
+fn schema() {
+    assert_eq!(fmt(r###"
+        module default {
+            abstract type Named { required property name -> str }
+            type User extending Named {
+                multi link friends -> User;
+                property tags -> array<str>;
+                property upper := str_upper(.name);
+                constraint exclusive on (.name);
+            }
+            scalar type Color extending enum<Red, Green>;
+        }
+    "###), r###"MODULE default {
+    ABSTRACT TYPE Named {
+        REQUIRED PROPERTY name -> str
+    }
+    TYPE User EXTENDING Named {
+        MULTI LINK friends -> User;
+        PROPERTY tags -> array<str>;
+        PROPERTY upper := str_upper(.name);
+        CONSTRAINT exclusive ON (.name);
+    }
+    SCALAR TYPE Color EXTENDING enum<Red, Green>;
+}
+"###);
+}
+
+#[test]
+fn query_in_parens() {
+    assert_eq!(fmt("with x := (select User filter .a) select x {name}"),
+               "WITH x := (SELECT User FILTER .a) SELECT x { name }\n");
+    assert_eq!(fmt("for x in {1, 2} union (insert Item {num := x})"),
+               "FOR x IN {1, 2} UNION (INSERT Item { num := x })\n");
+}
+
+#[test]
+fn errors() {
+    assert_eq!(fmt_err("select (1]"), "unexpected `]` at 1:10");
+    assert_eq!(fmt_err("select {1, 2"), "unclosed `{` at 1:8");
+    assert_eq!(fmt_err("select 'abc"),
+               "unterminated string, quoted by `'` at 1:8");
+}
+
+fn corpus_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            corpus_files(&path, files);
+        } else if matches!(path.extension().and_then(|e| e.to_str()),
+                           Some("edgeql") | Some("esdl")) {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn corpus() {
+    // `format` fails if the output has different tokens than the input
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../tests/schemas");
+    let mut files = Vec::new();
+    corpus_files(&dir, &mut files);
+    assert!(!files.is_empty());
+    for path in files {
+        let text = fs::read_to_string(&path).unwrap();
+        let out = format(&text, &Config::default()).unwrap_or_else(|e| {
+            panic!("{}: {}", path.display(), e)
+        });
+        assert_eq!(format(&out, &Config::default()).unwrap(), out,
+                   "{}: formatting is not idempotent", path.display());
+    }
+}
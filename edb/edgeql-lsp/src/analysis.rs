@@ -0,0 +1,211 @@
+//! Editor features computed from the text of the document
+//!
+//! All the offsets here are in bytes, conversion to the LSP positions is
+//! done by the server.
+use edgeql_parser::keywords::{CURRENT_RESERVED_KEYWORDS, UNRESERVED_KEYWORDS};
+use edgeql_parser::preparser::{brackets, Bracket};
+use edgeql_parser::tokenizer::{TokenStream, SpannedToken};
+
+
+/// Names of the `TokenType` variants, in the same order
+pub const TOKEN_TYPES: &[&str] = &[
+    "keyword", "variable", "property", "string", "number", "operator",
+    "parameter", "comment",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenType {
+    Keyword,
+    Variable,
+    Property,
+    String,
+    Number,
+    Operator,
+    Parameter,
+    Comment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SemanticToken {
+    pub start: usize,
+    pub end: usize,
+    pub kind: TokenType,
+}
+
+/// Error found in the text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+fn tokens(text: &str) -> Vec<SpannedToken<'_>> {
+    // there are no errors in the recovery mode, except the end of input
+    (&mut TokenStream::new_lossless_recovering(text))
+        .take_while(|t| t.is_ok())
+        .filter_map(|t| t.ok())
+        .collect()
+}
+
+fn is_unreserved(word: &str) -> bool {
+    UNRESERVED_KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(word))
+}
+
+/// Lexical errors and unbalanced brackets
+pub fn diagnostics(text: &str) -> Vec<Problem> {
+    let mut stream = TokenStream::new_recovering(text);
+    for token in &mut stream {
+        if token.is_err() {
+            break;
+        }
+    }
+    let mut problems = stream.take_errors().into_iter()
+        .map(|e| Problem {
+            message: e.message,
+            start: e.start.offset as usize,
+            end: e.end.offset as usize,
+        })
+        .collect::<Vec<_>>();
+    for bracket in brackets(text.as_bytes()) {
+        let (message, offset) = match bracket {
+            Bracket { open: Some(open), close: None } => ("unclosed", open),
+            Bracket { open: None, close: Some(close) } => {
+                ("unexpected", close)
+            }
+            _ => continue,
+        };
+        problems.push(Problem {
+            message: format!("{} `{}`", message, &text[offset..offset+1]),
+            start: offset,
+            end: offset + 1,
+        });
+    }
+    problems.sort_by_key(|p| p.start);
+    problems
+}
+
+/// Tokens to highlight, whitespace and punctuation are skipped
+pub fn semantic_tokens(text: &str) -> Vec<SemanticToken> {
+    use edgeql_parser::tokenizer::Kind::*;
+
+    let tokens = tokens(text);
+    let significant = tokens.iter()
+        .filter(|t| !matches!(t.token.kind, Whitespace | Comment))
+        .collect::<Vec<_>>();
+    let mut result = Vec::new();
+    let mut idx = 0usize;
+    for tok in &tokens {
+        let kind = tok.token.kind;
+        if kind == Whitespace {
+            continue;
+        }
+        let ty = if kind == Comment {
+            Some(TokenType::Comment)
+        } else {
+            let prev = idx.checked_sub(1)
+                .map(|i| significant[i].token.kind);
+            let next = significant.get(idx + 1).map(|t| t.token.kind);
+            idx += 1;
+            match kind {
+                Keyword => Some(TokenType::Keyword),
+                Ident | BacktickName if matches!(prev,
+                    Some(Dot) | Some(ForwardLink) | Some(BackwardLink)
+                    | Some(At))
+                => Some(TokenType::Property),
+                Ident if is_unreserved(tok.token.value)
+                    && prev != Some(Namespace) && next != Some(Namespace)
+                => Some(TokenType::Keyword),
+                Ident | BacktickName => Some(TokenType::Variable),
+                Str | BinStr => Some(TokenType::String),
+                IntConst | FloatConst | BigIntConst | DecimalConst => {
+                    Some(TokenType::Number)
+                }
+                Argument => Some(TokenType::Parameter),
+                Comma | Semicolon | Colon | Dot | OpenParen | CloseParen
+                | OpenBracket | CloseBracket | OpenBrace | CloseBrace
+                | Error | Whitespace | Comment => None,
+                _ => Some(TokenType::Operator),
+            }
+        };
+        if let Some(kind) = ty {
+            result.push(SemanticToken {
+                start: tok.start.offset as usize,
+                end: tok.end.offset as usize,
+                kind,
+            });
+        }
+    }
+    result
+}
+
+/// Keywords starting with the word being typed at `offset`
+///
+/// Keywords are uppercase if the typed prefix is uppercase, lowercase
+/// otherwise.
+pub fn complete(text: &str, offset: usize) -> Vec<String> {
+    use edgeql_parser::tokenizer::Kind::*;
+
+    let offset = offset.min(text.len());
+    let prefix_start = text[..offset]
+        .rfind(|c: char| !c.is_alphanumeric() && c != '_')
+        .map(|idx| idx + 1)
+        .unwrap_or(0);
+    let mut prev = None;
+    for tok in &tokens(text) {
+        let start = tok.start.offset as usize;
+        let end = tok.end.offset as usize;
+        if start >= offset {
+            break;
+        }
+        match tok.token.kind {
+            // inside of a literal or a comment
+            Str | BinStr | BacktickName if offset < end => {
+                return Vec::new();
+            }
+            // unterminated strings are errors too
+            Error | Comment | Argument | IntConst | FloatConst | BigIntConst
+            | DecimalConst if offset <= end => return Vec::new(),
+            Whitespace | Comment => {}
+            kind if end <= prefix_start => prev = Some(kind),
+            _ => {}
+        }
+    }
+    // a path element, not a keyword
+    if matches!(prev, Some(Dot) | Some(ForwardLink) | Some(BackwardLink)
+                      | Some(At) | Some(Namespace))
+    {
+        return Vec::new();
+    }
+    let prefix = &text[prefix_start..offset];
+    let upper = prefix.chars().any(|c| c.is_alphabetic())
+        && !prefix.chars().any(|c| c.is_lowercase());
+    let mut result = CURRENT_RESERVED_KEYWORDS.iter()
+        .chain(UNRESERVED_KEYWORDS)
+        .filter(|k| k.len() >= prefix.len()
+                && k.as_bytes()[..prefix.len()]
+                    .eq_ignore_ascii_case(prefix.as_bytes()))
+        .map(|k| if upper { k.to_ascii_uppercase() } else { k.to_string() })
+        .collect::<Vec<_>>();
+    result.sort();
+    result
+}
+
+/// Offsets of a bracket at or just before the `offset` and its pair
+pub fn matching_bracket(text: &str, offset: usize) -> Option<(usize, usize)>
+{
+    let pairs = brackets(text.as_bytes()).into_iter()
+        .filter_map(|b| match b {
+            Bracket { open: Some(open), close: Some(close) } => {
+                Some((open, close))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    pairs.iter()
+        .find(|&&(open, close)| open == offset || close == offset)
+        .or_else(|| pairs.iter().find(|&&(open, close)| {
+            open + 1 == offset || close + 1 == offset
+        }))
+        .copied()
+}
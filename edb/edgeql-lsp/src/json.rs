@@ -0,0 +1,348 @@
+//! Minimal JSON implementation, enough for the language server protocol
+use std::fmt::{self, Write};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Keys are kept in the original order
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub message: &'static str,
+    pub offset: usize,
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(pairs) => {
+                pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Value {
+        Value::Number(n.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Value {
+        Value::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Builds an object from the list of pairs
+pub fn object(pairs: Vec<(&str, Value)>) -> Value {
+    Value::Object(pairs.into_iter().map(|(k, v)| (k.into(), v)).collect())
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                write!(f, "{}", *n as i64)
+            }
+            Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Value::Number(_) => f.write_str("null"),
+            Value::String(s) => write_str(f, s),
+            Value::Array(items) => {
+                f.write_char('[')?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(pairs) => {
+                f.write_char('{')?;
+                for (idx, (key, value)) in pairs.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    off: usize,
+}
+
+/// Parses a single JSON value, surrounded by optional whitespace
+pub fn parse(text: &str) -> Result<Value, Error> {
+    let mut parser = Parser { data: text.as_bytes(), off: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.off != parser.data.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &'static str) -> Error {
+        Error { message, offset: self.off }
+    }
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r')
+            = self.data.get(self.off)
+        {
+            self.off += 1;
+        }
+    }
+    fn expect(&mut self, word: &str) -> Result<(), Error> {
+        if self.data[self.off..].starts_with(word.as_bytes()) {
+            self.off += word.len();
+            Ok(())
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.data.get(self.off) {
+            Some(b'n') => self.expect("null").map(|()| Value::Null),
+            Some(b't') => self.expect("true").map(|()| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|()| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                self.off += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.data.get(self.off) == Some(&b']') {
+                    self.off += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.data.get(self.off) {
+                        Some(b',') => self.off += 1,
+                        Some(b']') => {
+                            self.off += 1;
+                            return Ok(Value::Array(items));
+                        }
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.off += 1;
+                let mut pairs = Vec::new();
+                self.skip_whitespace();
+                if self.data.get(self.off) == Some(&b'}') {
+                    self.off += 1;
+                    return Ok(Value::Object(pairs));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.data.get(self.off) != Some(&b'"') {
+                        return Err(self.error("expected string key"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.data.get(self.off) != Some(&b':') {
+                        return Err(self.error("expected `:`"));
+                    }
+                    self.off += 1;
+                    pairs.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.data.get(self.off) {
+                        Some(b',') => self.off += 1,
+                        Some(b'}') => {
+                            self.off += 1;
+                            return Ok(Value::Object(pairs));
+                        }
+                        _ => return Err(self.error("expected `,` or `}`")),
+                    }
+                }
+            }
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of data")),
+        }
+    }
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.off;
+        while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e')
+            | Some(b'E') | Some(b'0'..=b'9') = self.data.get(self.off)
+        {
+            self.off += 1;
+        }
+        std::str::from_utf8(&self.data[start..self.off]).ok()
+            .and_then(|s| s.parse().ok())
+            .map(Value::Number)
+            .ok_or(Error { message: "invalid number", offset: start })
+    }
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let hex = self.data.get(self.off..self.off+4)
+            .and_then(|s| std::str::from_utf8(s).ok())
+            .and_then(|s| u32::from_str_radix(s, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.off += 4;
+        Ok(hex)
+    }
+    fn string(&mut self) -> Result<String, Error> {
+        // skip the opening quote
+        self.off += 1;
+        let mut result = String::new();
+        loop {
+            let start = self.off;
+            while let Some(&b) = self.data.get(self.off) {
+                if b == b'"' || b == b'\\' {
+                    break;
+                }
+                self.off += 1;
+            }
+            // we only split on ASCII characters so it's a valid UTF-8
+            result.push_str(std::str::from_utf8(&self.data[start..self.off])
+                .map_err(|_| self.error("invalid utf-8"))?);
+            match self.data.get(self.off) {
+                Some(b'"') => {
+                    self.off += 1;
+                    return Ok(result);
+                }
+                Some(b'\\') => {
+                    self.off += 1;
+                    let c = match self.data.get(self.off) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{0008}',
+                        Some(b'f') => '\u{000c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.off += 1;
+                            let mut code = self.hex4()?;
+                            if (0xD800..0xDC00).contains(&code)
+                                && self.data[self.off..].starts_with(b"\\u")
+                            {
+                                self.off += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xD800) << 10)
+                                    + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            result.push(std::char::from_u32(code)
+                                .unwrap_or('\u{FFFD}'));
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.off += 1;
+                    result.push(c);
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, object, Value};
+
+    #[test]
+    fn roundtrip() {
+        let text = r#"{"a":[1,-2.5,true,null],"b":"x\"\\\n\u0001y"}"#;
+        assert_eq!(parse(text).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r#" "é😀\/" "#).unwrap(),
+                   Value::String("é😀/".into()));
+        assert_eq!(object(vec![("k", "тест".into())]).to_string(),
+                   r#"{"k":"тест"}"#);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("[1,]").unwrap_err().offset, 3);
+        assert_eq!(parse("{\"a\" 1}").unwrap_err().message, "expected `:`");
+        assert_eq!(parse("\"abc").unwrap_err().message,
+                   "unterminated string");
+        assert_eq!(parse("1 2").unwrap_err().message,
+                   "trailing characters");
+    }
+}
@@ -0,0 +1,12 @@
+//! Language server for EdgeQL
+//!
+//! Supports semantic highlighting, diagnostics for lexical errors and
+//! unbalanced brackets, highlighting of matching brackets and keyword
+//! completion. Documents are synchronized incrementally.
+mod json;
+mod line_index;
+mod server;
+
+pub mod analysis;
+
+pub use server::run;
@@ -0,0 +1,50 @@
+//! Conversion between byte offsets and LSP positions
+//!
+//! LSP positions are zero-based line numbers and columns in UTF-16 code
+//! units, while the tokenizer works with byte offsets.
+
+
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// Byte offset of the start of each line
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> LineIndex<'a> {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(idx, _)| idx + 1));
+        LineIndex { text, starts }
+    }
+
+    /// Returns line and UTF-16 column of the byte offset
+    pub fn position(&self, offset: usize) -> (u32, u32) {
+        let offset = offset.min(self.text.len());
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = self.text[self.starts[line]..offset]
+            .chars().map(char::len_utf16).sum::<usize>();
+        (line as u32, column as u32)
+    }
+
+    /// Returns byte offset of the position, clamped to the line length
+    pub fn offset(&self, line: u32, column: u32) -> usize {
+        let line = line as usize;
+        if line >= self.starts.len() {
+            return self.text.len();
+        }
+        let start = self.starts[line];
+        let end = self.starts.get(line + 1).copied()
+            .unwrap_or(self.text.len());
+        let mut units = 0;
+        for (idx, c) in self.text[start..end].char_indices() {
+            if units >= column as usize || c == '\n' {
+                return start + idx;
+            }
+            units += c.len_utf16();
+        }
+        end
+    }
+}
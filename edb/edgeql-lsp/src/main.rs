@@ -0,0 +1,17 @@
+use std::io;
+use std::process::exit;
+
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match edgeql_lsp::run(stdin.lock(), stdout.lock()) {
+        Ok(true) => {}
+        // exit without shutdown request
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("edgeql-lsp: {}", e);
+            exit(2);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! Language server protocol over a pair of streams
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::analysis;
+use crate::json::{self, object, Value};
+use crate::line_index::LineIndex;
+
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+const SEVERITY_ERROR: u32 = 1;
+const COMPLETION_KEYWORD: u32 = 14;
+const SYNC_INCREMENTAL: u32 = 2;
+
+/// Largest message body we accept, so the header can't make us allocate
+/// an arbitrary amount of memory
+const MAX_MESSAGE: usize = 64 << 20;
+
+type RequestError = (i64, String);
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+/// Runs the server until the `exit` notification or the end of input
+///
+/// Returns `true` if the client requested `shutdown` before exiting, so
+/// the process should exit successfully.
+pub fn run<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<bool>
+{
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    while let Some(text) = read_message(&mut input)? {
+        let message = match json::parse(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                let error = format!("{} at {}", e.message, e.offset);
+                server.send_error(Value::Null, (PARSE_ERROR, error))?;
+                continue;
+            }
+        };
+        let method = message.get("method").and_then(Value::as_str);
+        let params = message.get("params").unwrap_or(&Value::Null);
+        match (method, message.get("id")) {
+            (Some("exit"), _) => return Ok(server.shutdown),
+            (Some(method), Some(id)) => {
+                server.request(method, id.clone(), params)?
+            }
+            (Some(method), None) => server.notification(method, params)?,
+            // responses, we never send requests to the client
+            (None, _) => {}
+        }
+    }
+    Ok(server.shutdown)
+}
+
+/// Reads a message body, returns `None` at the end of input
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let mut pair = line.splitn(2, ':');
+        if pair.next().unwrap().eq_ignore_ascii_case("content-length") {
+            length = pair.next().and_then(|v| v.trim().parse().ok());
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       "missing Content-Length header")
+    })?;
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("Content-Length {} exceeds the limit of {} bytes",
+                    length, MAX_MESSAGE)));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn capabilities() -> Value {
+    object(vec![
+        ("capabilities", object(vec![
+            ("textDocumentSync", SYNC_INCREMENTAL.into()),
+            ("semanticTokensProvider", object(vec![
+                ("legend", object(vec![
+                    ("tokenTypes", analysis::TOKEN_TYPES.to_vec().into()),
+                    ("tokenModifiers", Value::Array(Vec::new())),
+                ])),
+                ("full", true.into()),
+            ])),
+            ("completionProvider", object(Vec::new())),
+            ("documentHighlightProvider", true.into()),
+        ])),
+        ("serverInfo", object(vec![
+            ("name", "edgeql-lsp".into()),
+            ("version", env!("CARGO_PKG_VERSION").into()),
+        ])),
+    ])
+}
+
+fn position(index: &LineIndex, offset: usize) -> Value {
+    let (line, character) = index.position(offset);
+    object(vec![
+        ("line", line.into()),
+        ("character", character.into()),
+    ])
+}
+
+fn range(index: &LineIndex, start: usize, end: usize) -> Value {
+    object(vec![
+        ("start", position(index, start)),
+        ("end", position(index, end)),
+    ])
+}
+
+fn invalid(what: &str) -> RequestError {
+    (INVALID_PARAMS, format!("missing or invalid {}", what))
+}
+
+/// Converts LSP position to the byte offset in the text
+fn offset(index: &LineIndex, pos: &Value) -> Result<usize, RequestError> {
+    let line = pos.get("line").and_then(Value::as_u64)
+        .ok_or_else(|| invalid("line"))?;
+    let character = pos.get("character").and_then(Value::as_u64)
+        .ok_or_else(|| invalid("character"))?;
+    Ok(index.offset(line as u32, character as u32))
+}
+
+fn uri(params: &Value) -> Result<&str, RequestError> {
+    params.get("textDocument")
+        .and_then(|d| d.get("uri"))
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("textDocument.uri"))
+}
+
+/// Encodes tokens as relative positions, one token per line
+fn semantic_tokens(text: &str) -> Value {
+    let index = LineIndex::new(text);
+    let mut data = Vec::new();
+    let mut last = (0, 0);
+    for token in analysis::semantic_tokens(text) {
+        let mut start = token.start;
+        for piece in text[token.start..token.end].split('\n') {
+            let end = start + piece.trim_end_matches('\r').len();
+            if end > start {
+                let (line, column) = index.position(start);
+                let (_, end_column) = index.position(end);
+                let delta_column = if line == last.0 {
+                    column - last.1
+                } else {
+                    column
+                };
+                data.extend_from_slice(&[
+                    line - last.0,
+                    delta_column,
+                    end_column - column,
+                    token.kind as u32,
+                    0,
+                ]);
+                last = (line, column);
+            }
+            start += piece.len() + 1;
+        }
+    }
+    object(vec![("data", data.into())])
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+
+    fn send_error(&mut self, id: Value, (code, message): RequestError)
+        -> io::Result<()>
+    {
+        self.send(object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id),
+            ("error", object(vec![
+                ("code", code.into()),
+                ("message", message.into()),
+            ])),
+        ]))
+    }
+
+    fn document(&self, params: &Value) -> Result<&str, RequestError> {
+        let uri = uri(params)?;
+        self.documents.get(uri).map(|s| &s[..]).ok_or_else(|| {
+            (INVALID_PARAMS, format!("unknown document {}", uri))
+        })
+    }
+
+    fn request(&mut self, method: &str, id: Value, params: &Value)
+        -> io::Result<()>
+    {
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ if self.shutdown => {
+                Err((INVALID_REQUEST, "server is shutting down".into()))
+            }
+            "textDocument/semanticTokens/full" => {
+                self.document(params).map(semantic_tokens)
+            }
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentHighlight" => self.highlight(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        };
+        match result {
+            Ok(result) => self.send(object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("id", id),
+                ("result", result),
+            ])),
+            Err(error) => self.send_error(id, error),
+        }
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value, RequestError> {
+        let text = self.document(params)?;
+        let index = LineIndex::new(text);
+        let pos = params.get("position").ok_or_else(|| invalid("position"))?;
+        let items = analysis::complete(text, offset(&index, pos)?)
+            .into_iter()
+            .map(|keyword| object(vec![
+                ("label", keyword.into()),
+                ("kind", COMPLETION_KEYWORD.into()),
+            ]))
+            .collect::<Vec<_>>();
+        Ok(Value::Array(items))
+    }
+
+    fn highlight(&self, params: &Value) -> Result<Value, RequestError> {
+        let text = self.document(params)?;
+        let index = LineIndex::new(text);
+        let pos = params.get("position").ok_or_else(|| invalid("position"))?;
+        let pair = analysis::matching_bracket(text, offset(&index, pos)?);
+        Ok(match pair {
+            Some((open, close)) => Value::Array(vec![
+                object(vec![("range", range(&index, open, open + 1))]),
+                object(vec![("range", range(&index, close, close + 1))]),
+            ]),
+            None => Value::Null,
+        })
+    }
+
+    fn notification(&mut self, method: &str, params: &Value)
+        -> io::Result<()>
+    {
+        let uri = match uri(params) {
+            Ok(uri) => uri.to_string(),
+            // no way to report errors for notifications
+            Err(_) => return Ok(()),
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument")
+                    .and_then(|d| d.get("text"))
+                    .and_then(Value::as_str)
+                    .unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didChange" => {
+                let text = match self.documents.get_mut(&uri) {
+                    Some(text) => text,
+                    None => return Ok(()),
+                };
+                let changes = params.get("contentChanges")
+                    .and_then(Value::as_array)
+                    .unwrap_or(&[]);
+                for change in changes {
+                    apply_change(text, change);
+                }
+                self.publish_diagnostics(&uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let text = self.documents.get(uri).map(|s| &s[..]).unwrap_or("");
+        let index = LineIndex::new(text);
+        let diagnostics = analysis::diagnostics(text).into_iter()
+            .map(|p| object(vec![
+                ("range", range(&index, p.start, p.end)),
+                ("severity", SEVERITY_ERROR.into()),
+                ("source", "edgeql".into()),
+                ("message", p.message.into()),
+            ]))
+            .collect::<Vec<_>>();
+        self.send(object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", object(vec![
+                ("uri", uri.into()),
+                ("diagnostics", Value::Array(diagnostics)),
+            ])),
+        ]))
+    }
+}
+
+/// Applies either a ranged or a full text change
+fn apply_change(text: &mut String, change: &Value) {
+    let new = change.get("text").and_then(Value::as_str).unwrap_or("");
+    let range = match change.get("range") {
+        Some(range) => range,
+        None => {
+            *text = new.to_string();
+            return;
+        }
+    };
+    let index = LineIndex::new(text);
+    let start = range.get("start").and_then(|p| offset(&index, p).ok());
+    let end = range.get("end").and_then(|p| offset(&index, p).ok());
+    if let (Some(start), Some(end)) = (start, end) {
+        if start <= end {
+            text.replace_range(start..end, new);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use edgeql_lsp::analysis::{semantic_tokens, diagnostics, complete};
+use edgeql_lsp::analysis::{matching_bracket, TokenType};
+use edgeql_lsp::analysis::TokenType::{Keyword, Variable, Property};
+use edgeql_lsp::analysis::TokenType::{Number, Operator, Parameter, Comment};
+
+
+fn highlight(text: &str) -> Vec<(&str, TokenType)> {
+    semantic_tokens(text).into_iter()
+        .map(|t| (&text[t.start..t.end], t.kind))
+        .collect()
+}
+
+fn problems(text: &str) -> Vec<(String, &str)> {
+    diagnostics(text).into_iter()
+        .map(|p| (p.message, &text[p.start..p.end]))
+        .collect()
+}
+
+#[test]
+fn highlighting() {
+    assert_eq!(highlight("SELECT User { name } FILTER .id = <uuid>$id;"), [
+        ("SELECT", Keyword), ("User", Variable), ("name", Variable),
+        ("FILTER", Keyword), ("id", Property), ("=", Operator),
+        ("<", Operator), ("uuid", Variable), (">", Operator),
+        ("$id", Parameter),
+    ]);
+    assert_eq!(highlight("select 'a' ++ 1.5 # c\n# d"), [
+        ("select", Keyword), ("'a'", TokenType::String), ("++", Operator),
+        ("1.5", Number), ("# c", Comment), ("# d", Comment),
+    ]);
+    assert_eq!(highlight("type default::User { link x -> y@type }"), [
+        ("type", Keyword), ("default", Variable), ("::", Operator),
+        ("User", Variable), ("link", Keyword), ("x", Variable),
+        ("->", Operator), ("y", Variable), ("@", Operator),
+        ("type", Property),
+    ]);
+    assert_eq!(highlight("SELECT ! 1"), [
+        ("SELECT", Keyword), ("1", Number),
+    ]);
+}
+
+#[test]
+fn problems_found() {
+    assert_eq!(problems("SELECT 1"), []);
+    assert_eq!(problems("SELECT (1, 'a) + 2"), [
+        ("unclosed `(`".into(), "("),
+        ("unterminated string, quoted by `'`".into(), "'a) + 2"),
+    ]);
+    assert_eq!(problems("SELECT 1 ! 2)"), [
+        ("Bare `!` is not an operator, did you mean `!=`?".into(), "!"),
+        ("unexpected `)`".into(), ")"),
+    ]);
+}
+
+#[test]
+fn completion() {
+    let all = complete("", 0);
+    assert!(all.contains(&"select".to_string()));
+    assert!(all.contains(&"extending".to_string()));
+    assert_eq!(complete("SEL", 3), ["SELECT"]);
+    assert_eq!(complete("select User fil", 15), ["filter"]);
+    assert_eq!(complete("SELECT Fil", 10), ["filter"]);
+    assert_eq!(complete("SELECT 'fil", 11), Vec::<String>::new());
+    assert_eq!(complete("SELECT .fil", 11), Vec::<String>::new());
+    assert_eq!(complete("SELECT 1 # fil", 14), Vec::<String>::new());
+    assert_eq!(complete("SELECT 1 # fil\nFIL", 18), ["FILTER"]);
+    assert_eq!(complete("SELECT (", 8).len(), all.len());
+}
+
+#[test]
+fn brackets() {
+    let text = "SELECT {(1, [2]), 3}";
+    assert_eq!(matching_bracket(text, 7), Some((7, 19)));
+    assert_eq!(matching_bracket(text, 20), Some((7, 19)));
+    assert_eq!(matching_bracket(text, 8), Some((8, 15)));
+    assert_eq!(matching_bracket(text, 16), Some((8, 15)));
+    assert_eq!(matching_bracket(text, 13), Some((12, 14)));
+    assert_eq!(matching_bracket(text, 0), None);
+    assert_eq!(matching_bracket("SELECT (1", 7), None);
+}
@@ -0,0 +1,151 @@
+use edgeql_lsp::run;
+
+
+fn frame(messages: &[&str]) -> Vec<u8> {
+    messages.iter()
+        .map(|m| format!("Content-Length: {}\r\n\r\n{}", m.len(), m))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Runs the server and returns bodies of the messages it sent
+fn session(messages: &[&str]) -> (bool, Vec<String>) {
+    let input = frame(messages);
+    let mut output = Vec::new();
+    let clean = run(&input[..], &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let bodies = output.split("Content-Length: ").skip(1)
+        .map(|chunk| {
+            let (len, body) = chunk.split_at(chunk.find("\r\n\r\n").unwrap());
+            let body = &body[4..];
+            assert_eq!(len.parse::<usize>().unwrap(), body.len());
+            body.to_string()
+        })
+        .collect();
+    (clean, bodies)
+}
+
+const OPEN: &str = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen",
+    "params":{"textDocument":{"uri":"file:///a.edgeql","languageId":"edgeql",
+    "version":1,"text":"SELECT (1,\n  'тест' ! 2"}}}"#;
+
+#[test]
+fn lifecycle() {
+    let (clean, out) = session(&[
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]);
+    assert!(clean);
+    assert_eq!(out.len(), 2);
+    assert!(out[0].starts_with(r#"{"jsonrpc":"2.0","id":1,"result":{"#));
+    assert!(out[0].contains(r#""textDocumentSync":2"#));
+    assert!(out[0].contains(r#""tokenTypes":["keyword","variable","#));
+    assert_eq!(out[1], r#"{"jsonrpc":"2.0","id":2,"result":null}"#);
+
+    let (clean, _) = session(&[r#"{"jsonrpc":"2.0","method":"exit"}"#]);
+    assert!(!clean);
+}
+
+#[test]
+fn errors() {
+    let (_, out) = session(&[
+        "{not json",
+        r#"{"jsonrpc":"2.0","id":"x","method":"workspace/symbol"}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/completion",
+            "params":{"textDocument":{"uri":"file:///none"},
+                      "position":{"line":0,"character":0}}}"#,
+    ]);
+    assert_eq!(out, [
+        r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"#.to_string()
+            + r#""message":"expected string key at 1"}}"#,
+        r#"{"jsonrpc":"2.0","id":"x","error":{"code":-32601,"#.to_string()
+            + r#""message":"unknown method workspace/symbol"}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"error":{"code":-32602,"#.to_string()
+            + r#""message":"unknown document file:///none"}}"#,
+    ]);
+}
+
+#[test]
+fn message_too_large() {
+    let input = b"Content-Length: 1000000000000\r\n\r\n{}";
+    let err = run(&input[..], Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "Content-Length 1000000000000 exceeds \
+                                 the limit of 67108864 bytes");
+}
+
+#[test]
+fn diagnostics() {
+    let (_, out) = session(&[OPEN]);
+    assert_eq!(out, [
+        r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","#
+            .to_string()
+        + r#""params":{"uri":"file:///a.edgeql","diagnostics":["#
+        + r#"{"range":{"start":{"line":0,"character":7},"#
+        + r#""end":{"line":0,"character":8}},"severity":1,"#
+        + r#""source":"edgeql","message":"unclosed `(`"},"#
+        + r#"{"range":{"start":{"line":1,"character":9},"#
+        + r#""end":{"line":1,"character":10}},"severity":1,"#
+        + r#""source":"edgeql","#
+        + r#""message":"Bare `!` is not an operator, did you mean `!=`?"}"#
+        + r#"]}}"#,
+    ]);
+}
+
+#[test]
+fn incremental_change() {
+    let (_, out) = session(&[
+        OPEN,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange",
+            "params":{"textDocument":{"uri":"file:///a.edgeql","version":2},
+            "contentChanges":[
+                {"range":{"start":{"line":1,"character":9},
+                          "end":{"line":1,"character":10}},
+                 "text":"+"},
+                {"range":{"start":{"line":1,"character":12},
+                          "end":{"line":1,"character":12}},
+                 "text":")"}
+            ]}}"#,
+        r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/documentHighlight",
+            "params":{"textDocument":{"uri":"file:///a.edgeql"},
+                      "position":{"line":1,"character":13}}}"#,
+    ]);
+    assert_eq!(out.len(), 3);
+    assert!(out[1].ends_with(r#""diagnostics":[]}}"#));
+    assert_eq!(out[2], r#"{"jsonrpc":"2.0","id":1,"result":["#.to_string()
+        + r#"{"range":{"start":{"line":0,"character":7},"#
+        + r#""end":{"line":0,"character":8}}},"#
+        + r#"{"range":{"start":{"line":1,"character":12},"#
+        + r#""end":{"line":1,"character":13}}}]}"#);
+}
+
+#[test]
+fn semantic_tokens() {
+    let (_, out) = session(&[
+        OPEN,
+        r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/semanticTokens/full",
+            "params":{"textDocument":{"uri":"file:///a.edgeql"}}}"#,
+    ]);
+    // SELECT, 1, 'тест' on the next line, 2 (`!` is an error)
+    assert_eq!(out[1], r#"{"jsonrpc":"2.0","id":1,"result":{"data":["#
+        .to_string()
+        + "0,0,6,0,0,"
+        + "0,8,1,4,0,"
+        + "1,2,6,3,0,"
+        + "0,9,1,4,0"
+        + "]}}");
+}
+
+#[test]
+fn completion() {
+    let (_, out) = session(&[
+        OPEN,
+        r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/completion",
+            "params":{"textDocument":{"uri":"file:///a.edgeql"},
+                      "position":{"line":0,"character":3}}}"#,
+    ]);
+    assert_eq!(out[1], r#"{"jsonrpc":"2.0","id":1,"result":["#.to_string()
+        + r#"{"label":"SELECT","kind":14}]}"#);
+}
@@ -0,0 +1,790 @@
+//! Typed AST for EdgeQL statements and expressions
+//!
+//! Node names follow `edb.edgeql.ast` where possible. Every node carries
+//! `start` and `end` positions of the source text it was parsed from.
+use crate::position::Pos;
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Select(SelectQuery),
+    Group(GroupQuery),
+    Insert(InsertQuery),
+    Update(UpdateQuery),
+    Delete(DeleteQuery),
+    For(ForQuery),
+    Ddl(DdlStatement),
+    Migration(MigrationCommand),
+}
+
+/// Declaration in a `WITH` block
+#[derive(Debug, PartialEq, Clone)]
+pub enum AliasDecl {
+    /// `MODULE name` or `alias AS MODULE name`
+    Module {
+        alias: Option<String>,
+        module: String,
+        start: Pos,
+        end: Pos,
+    },
+    /// `alias := expr`
+    Expr(AliasedExpr),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AliasedExpr {
+    pub alias: String,
+    pub expr: Expr,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SelectQuery {
+    pub aliases: Vec<AliasDecl>,
+    pub result_alias: Option<String>,
+    pub result: Expr,
+    pub filter: Option<Expr>,
+    pub order_by: Vec<SortExpr>,
+    pub offset: Option<Expr>,
+    pub limit: Option<Expr>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupQuery {
+    pub aliases: Vec<AliasDecl>,
+    pub subject_alias: Option<String>,
+    pub subject: Expr,
+    pub using: Vec<AliasedExpr>,
+    pub by: Vec<Expr>,
+    pub into: String,
+    pub result_alias: Option<String>,
+    pub result: Expr,
+    pub filter: Option<Expr>,
+    pub order_by: Vec<SortExpr>,
+    pub offset: Option<Expr>,
+    pub limit: Option<Expr>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct InsertQuery {
+    pub aliases: Vec<AliasDecl>,
+    pub subject_alias: Option<String>,
+    pub subject: Path,
+    pub shape: Vec<ShapeElement>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct UpdateQuery {
+    pub aliases: Vec<AliasDecl>,
+    pub subject_alias: Option<String>,
+    pub subject: Expr,
+    pub filter: Option<Expr>,
+    pub shape: Vec<ShapeElement>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeleteQuery {
+    pub aliases: Vec<AliasDecl>,
+    pub subject_alias: Option<String>,
+    pub subject: Expr,
+    pub filter: Option<Expr>,
+    pub order_by: Vec<SortExpr>,
+    pub offset: Option<Expr>,
+    pub limit: Option<Expr>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ForQuery {
+    pub aliases: Vec<AliasDecl>,
+    pub iterator_alias: String,
+    pub iterator: Expr,
+    pub result_alias: Option<String>,
+    pub result: Expr,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortDirection {
+    Default,
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NonesOrder {
+    First,
+    Last,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SortExpr {
+    pub path: Expr,
+    pub direction: SortDirection,
+    pub nones_order: Option<NonesOrder>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
+    Path(Path),
+    Shape(Shape),
+    Constant(Constant),
+    /// Query argument, name is stored without the `$` prefix
+    Parameter(String),
+    Set(Vec<Expr>),
+    Tuple(Vec<Expr>),
+    NamedTuple(Vec<TupleElement>),
+    Array(Vec<Expr>),
+    Indirection {
+        arg: Box<Expr>,
+        indirection: Vec<Indirection>,
+    },
+    FunctionCall(FunctionCall),
+    UnaryOp {
+        op: UnaryOperator,
+        operand: Box<Expr>,
+    },
+    BinOp {
+        left: Box<Expr>,
+        op: BinaryOperator,
+        right: Box<Expr>,
+    },
+    IsOp {
+        left: Box<Expr>,
+        negated: bool,
+        right: TypeExpr,
+    },
+    TypeCast {
+        type_: TypeExpr,
+        cardinality_mod: Option<CardinalityModifier>,
+        expr: Box<Expr>,
+    },
+    IfElse {
+        condition: Box<Expr>,
+        if_expr: Box<Expr>,
+        else_expr: Box<Expr>,
+    },
+    Detached(Box<Expr>),
+    Introspect(TypeExpr),
+    Statement(Box<Statement>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Constant {
+    /// Unquoted string value
+    Str(String),
+    /// Unquoted bytes value
+    Bytes(Vec<u8>),
+    /// Integer literal as written in the source (including underscores)
+    Int(String),
+    Float(String),
+    /// Bigint literal without the `n` suffix
+    BigInt(String),
+    /// Decimal literal without the `n` suffix
+    Decimal(String),
+    Bool(bool),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnaryOperator {
+    Plus,
+    Minus,
+    Not,
+    Exists,
+    Distinct,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOperator {
+    Union,
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    NotDistinctFrom,
+    DistinctFrom,
+    Like,
+    NotLike,
+    ILike,
+    NotILike,
+    In,
+    NotIn,
+    Add,
+    Sub,
+    Concat,
+    Mul,
+    Div,
+    FloorDiv,
+    Modulo,
+    Coalesce,
+    Pow,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CardinalityModifier {
+    Optional,
+    Required,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TupleElement {
+    pub name: String,
+    pub value: Expr,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Indirection {
+    Index(Expr),
+    Slice {
+        start: Option<Expr>,
+        stop: Option<Expr>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionCall {
+    pub func: ObjectRef,
+    pub args: Vec<FuncArg>,
+    pub kwargs: Vec<(String, FuncArg)>,
+}
+
+/// Function argument with optional `FILTER` and `ORDER BY` clauses
+#[derive(Debug, PartialEq, Clone)]
+pub struct FuncArg {
+    pub value: Expr,
+    pub filter: Option<Expr>,
+    pub order_by: Vec<SortExpr>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// Possibly module-qualified name
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObjectRef {
+    pub module: Option<String>,
+    pub name: String,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Path {
+    pub steps: Vec<PathStep>,
+    /// Path starts with a dot (or `@`) and refers to the implicit subject
+    pub partial: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PathStep {
+    ObjectRef(ObjectRef),
+    Ptr(Ptr),
+    TypeIntersection(TypeExpr),
+    /// `__source__`
+    Source { start: Pos, end: Pos },
+    /// `__subject__`
+    Subject { start: Pos, end: Pos },
+    /// Arbitrary expression a path was started from, e.g. `(a, b).0`
+    Expr(Box<Expr>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PointerDirection {
+    Outbound,
+    Inbound,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ptr {
+    pub name: String,
+    pub direction: PointerDirection,
+    /// Link property reference (`@name`)
+    pub property: bool,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Shape {
+    pub expr: Box<Expr>,
+    pub elements: Vec<ShapeElement>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ShapeOp {
+    Assign,
+    Append,
+    Subtract,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Cardinality {
+    One,
+    Many,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ShapeElement {
+    pub expr: Path,
+    pub elements: Vec<ShapeElement>,
+    pub filter: Option<Expr>,
+    pub order_by: Vec<SortExpr>,
+    pub offset: Option<Expr>,
+    pub limit: Option<Expr>,
+    pub compexpr: Option<Expr>,
+    pub operation: Option<ShapeOp>,
+    pub required: Option<bool>,
+    pub cardinality: Option<Cardinality>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TypeOperator {
+    Or,
+    And,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeExprKind {
+    Name(TypeName),
+    TypeOf(Box<Expr>),
+    TypeOp {
+        left: Box<TypeExpr>,
+        op: TypeOperator,
+        right: Box<TypeExpr>,
+    },
+    /// String literal used as a subtype, e.g. `enum<'a', 'b'>`
+    Literal(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeName {
+    /// Element name for named tuple subtypes
+    pub name: Option<String>,
+    pub maintype: ObjectRef,
+    /// `None` for non-collection types
+    pub subtypes: Option<Vec<TypeExpr>>,
+}
+
+impl Statement {
+    pub fn start(&self) -> Pos {
+        match self {
+            Statement::Select(q) => q.start,
+            Statement::Group(q) => q.start,
+            Statement::Insert(q) => q.start,
+            Statement::Update(q) => q.start,
+            Statement::Delete(q) => q.start,
+            Statement::For(q) => q.start,
+            Statement::Ddl(q) => q.start,
+            Statement::Migration(q) => q.start,
+        }
+    }
+    pub fn end(&self) -> Pos {
+        match self {
+            Statement::Select(q) => q.end,
+            Statement::Group(q) => q.end,
+            Statement::Insert(q) => q.end,
+            Statement::Update(q) => q.end,
+            Statement::Delete(q) => q.end,
+            Statement::For(q) => q.end,
+            Statement::Ddl(q) => q.end,
+            Statement::Migration(q) => q.end,
+        }
+    }
+}
+
+/// DDL command, optionally prefixed by a `WITH` block
+#[derive(Debug, PartialEq, Clone)]
+pub struct DdlStatement {
+    pub aliases: Vec<AliasDecl>,
+    pub command: DdlCommand,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// SDL schema document or the target of `START MIGRATION TO`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Schema {
+    pub declarations: Vec<Declaration>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// Kind of the schema object referred to by `ALTER` and `DROP`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ObjectKind {
+    Module,
+    ScalarType,
+    ObjectType,
+    Alias,
+    Annotation,
+    Constraint,
+    Link,
+    Property,
+    Function,
+    Index,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DdlCommand {
+    pub kind: DdlCommandKind,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DdlCommandKind {
+    Create(Declaration),
+    Alter(AlterObject),
+    Drop(DropObject),
+    /// `SET name := value` in DDL or `name := value` in SDL
+    SetField {
+        name: String,
+        value: Expr,
+    },
+    /// `CREATE ANNOTATION name := value` or `ALTER ANNOTATION ...`
+    SetAnnotation {
+        name: ObjectRef,
+        value: Expr,
+    },
+    DropAnnotation(ObjectRef),
+    Rename(ObjectRef),
+    /// `USING (expr)`
+    Using(Expr),
+    /// Function body other than `USING (expr)`
+    FunctionCode(FunctionCode),
+    SetType(TypeExpr),
+    SetRequired(bool),
+    SetCardinality(Cardinality),
+    SetAbstract(bool),
+    SetFinal(bool),
+    SetDelegated(bool),
+    SetOwned(bool),
+    Extending {
+        bases: Vec<TypeExpr>,
+        position: Option<InheritPosition>,
+    },
+    DropExtending(Vec<TypeExpr>),
+    OnTargetDelete(TargetDeleteAction),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AlterObject {
+    pub kind: ObjectKind,
+    pub is_abstract: bool,
+    /// Indexes have no name, `idx` is used like in `edb.edgeql.ast`
+    pub name: ObjectRef,
+    /// Function parameters
+    pub params: Option<Vec<FuncParam>>,
+    /// Arguments of a concrete constraint
+    pub args: Vec<Expr>,
+    /// `ON (expr)` of an index or a concrete constraint
+    pub subject: Option<Expr>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DropObject {
+    pub kind: ObjectKind,
+    pub is_abstract: bool,
+    pub name: ObjectRef,
+    pub params: Option<Vec<FuncParam>>,
+    pub args: Vec<Expr>,
+    pub subject: Option<Expr>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum InheritPosition {
+    Before(ObjectRef),
+    After(ObjectRef),
+    First,
+    Last,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TargetDeleteAction {
+    Restrict,
+    DeleteSource,
+    Allow,
+    DeferredRestrict,
+}
+
+/// Schema object definition
+///
+/// This is both an SDL declaration and the subject of a `CREATE` command.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Declaration {
+    Module(ModuleDeclaration),
+    ScalarType(ScalarTypeDeclaration),
+    ObjectType(ObjectTypeDeclaration),
+    Alias(AliasDeclaration),
+    Annotation(AnnotationDeclaration),
+    Constraint(ConstraintDeclaration),
+    ConcreteConstraint(ConcreteConstraint),
+    Link(PointerDeclaration),
+    Property(PointerDeclaration),
+    ConcreteLink(ConcretePointer),
+    ConcreteProperty(ConcretePointer),
+    Index(IndexDeclaration),
+    Function(FunctionDeclaration),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModuleDeclaration {
+    pub name: String,
+    /// `CREATE MODULE name IF NOT EXISTS`
+    pub if_not_exists: bool,
+    /// Declarations of an SDL `module` block
+    pub declarations: Vec<Declaration>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScalarTypeDeclaration {
+    pub name: ObjectRef,
+    pub is_abstract: bool,
+    pub is_final: bool,
+    pub bases: Vec<TypeExpr>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObjectTypeDeclaration {
+    pub name: ObjectRef,
+    pub is_abstract: bool,
+    pub bases: Vec<TypeExpr>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AliasDeclaration {
+    pub name: ObjectRef,
+    pub expr: Expr,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// `ABSTRACT [INHERITABLE] ANNOTATION`
+#[derive(Debug, PartialEq, Clone)]
+pub struct AnnotationDeclaration {
+    pub name: ObjectRef,
+    pub inheritable: bool,
+    pub bases: Vec<TypeExpr>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// `ABSTRACT CONSTRAINT`
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConstraintDeclaration {
+    pub name: ObjectRef,
+    pub params: Vec<FuncParam>,
+    pub subject: Option<Expr>,
+    pub bases: Vec<TypeExpr>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConcreteConstraint {
+    pub name: ObjectRef,
+    pub delegated: bool,
+    pub args: Vec<Expr>,
+    pub subject: Option<Expr>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// `ABSTRACT LINK` or `ABSTRACT PROPERTY`
+#[derive(Debug, PartialEq, Clone)]
+pub struct PointerDeclaration {
+    pub name: ObjectRef,
+    pub bases: Vec<TypeExpr>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// Link or property of an object type (or property of a link)
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConcretePointer {
+    pub name: ObjectRef,
+    pub overloaded: bool,
+    pub required: Option<bool>,
+    pub cardinality: Option<Cardinality>,
+    pub bases: Vec<TypeExpr>,
+    /// Only `OVERLOADED` pointers may omit the target
+    pub target: Option<PointerTarget>,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PointerTarget {
+    /// `-> Type`
+    Type(TypeExpr),
+    /// `:= expr` or `USING (expr)`
+    Computable(Expr),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IndexDeclaration {
+    pub expr: Expr,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionDeclaration {
+    pub name: ObjectRef,
+    pub params: Vec<FuncParam>,
+    pub returning: TypeExpr,
+    pub returning_typemod: TypeModifier,
+    pub code: FunctionCode,
+    pub commands: Vec<DdlCommand>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParameterKind {
+    Positional,
+    Variadic,
+    NamedOnly,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TypeModifier {
+    SetOf,
+    Optional,
+    Singleton,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FuncParam {
+    pub name: String,
+    pub kind: ParameterKind,
+    pub typemod: TypeModifier,
+    pub type_: TypeExpr,
+    pub default: Option<Expr>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Language {
+    EdgeQL,
+    Sql,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FunctionCode {
+    /// `USING (expr)`
+    Expr(Expr),
+    /// `USING language 'code'`
+    Code {
+        language: Language,
+        code: String,
+    },
+    /// `USING SQL FUNCTION 'name'`
+    SqlFunction(String),
+    /// `USING SQL EXPRESSION`
+    SqlExpression,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MigrationCommand {
+    pub kind: MigrationCommandKind,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum MigrationCommandKind {
+    Create {
+        name: Option<String>,
+        parent: Option<String>,
+        message: Option<Expr>,
+        commands: Vec<Statement>,
+    },
+    Start {
+        target: Schema,
+    },
+    Populate,
+    Commit,
+    Abort,
+    Alter {
+        name: String,
+        commands: Vec<DdlCommand>,
+    },
+    Drop {
+        name: String,
+    },
+}
+
+impl Declaration {
+    /// Name of the declared object, modules and indexes have none
+    pub fn name(&self) -> Option<&ObjectRef> {
+        use Declaration::*;
+        match self {
+            Module(_) | Index(_) => None,
+            ScalarType(d) => Some(&d.name),
+            ObjectType(d) => Some(&d.name),
+            Alias(d) => Some(&d.name),
+            Annotation(d) => Some(&d.name),
+            Constraint(d) => Some(&d.name),
+            ConcreteConstraint(d) => Some(&d.name),
+            Link(d) | Property(d) => Some(&d.name),
+            ConcreteLink(d) | ConcreteProperty(d) => Some(&d.name),
+            Function(d) => Some(&d.name),
+        }
+    }
+}
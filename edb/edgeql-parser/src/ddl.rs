@@ -0,0 +1,1016 @@
+//! Parser for DDL commands and SDL schema documents
+//!
+//! Roles, databases, operators and casts are not supported yet.
+use crate::ast::*;
+use crate::helpers::unquote_string;
+use crate::parser::{Parser, ParseError};
+use crate::position::Pos;
+use crate::tokenizer::Kind;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Commands are prefixed by a verb: `CREATE PROPERTY`, `SET x := 1`
+    Ddl,
+    /// Bare declarations: `property`, `x := 1`
+    Sdl,
+}
+
+const QUALIFIERS: &[&str] = &[
+    "abstract", "final", "inheritable", "delegated", "overloaded",
+    "required", "optional", "single", "multi",
+];
+
+/// Qualifiers preceding the kind of a declared object
+struct Qualifiers {
+    words: Vec<(String, Pos, Pos)>,
+}
+
+impl Qualifiers {
+    fn has(&self, word: &str) -> bool {
+        self.words.iter().any(|(w, _, _)| w == word)
+    }
+    fn check(&self, parser: &Parser, allowed: &[&str])
+        -> Result<(), ParseError>
+    {
+        for (word, start, end) in &self.words {
+            if !allowed.contains(&&word[..]) {
+                return Err(parser.error_at(
+                    format!("unexpected `{}`", word.to_uppercase()),
+                    *start, *end));
+            }
+        }
+        Ok(())
+    }
+    fn required(&self) -> Option<bool> {
+        if self.has("required") {
+            Some(true)
+        } else if self.has("optional") {
+            Some(false)
+        } else {
+            None
+        }
+    }
+    fn cardinality(&self) -> Option<Cardinality> {
+        if self.has("single") {
+            Some(Cardinality::One)
+        } else if self.has("multi") {
+            Some(Cardinality::Many)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Parses SDL declarations up to the end of input
+    pub fn schema(&mut self) -> Result<Schema, ParseError> {
+        let start = self.next_pos();
+        let declarations = self.items(Mode::Sdl, false, |p| {
+            let start = p.next_pos();
+            p.declaration(Mode::Sdl, start)
+        })?;
+        self.check_toplevel(&declarations)?;
+        Ok(Schema { declarations, start, end: self.prev_end() })
+    }
+
+    pub(crate) fn ddl_statement(&mut self, start: Pos,
+        aliases: Vec<AliasDecl>)
+        -> Result<Statement, ParseError>
+    {
+        let command = self.ddl_command()?;
+        Ok(Statement::Ddl(DdlStatement {
+            aliases, command,
+            start, end: self.prev_end(),
+        }))
+    }
+
+    pub(crate) fn migration(&mut self, start: Pos)
+        -> Result<Statement, ParseError>
+    {
+        let verb = self.bump().token.value.to_ascii_lowercase();
+        self.expect_word("migration")?;
+        let kind = match &verb[..] {
+            "create" => {
+                let name = if self.at_ident() {
+                    Some(self.ident()?)
+                } else {
+                    None
+                };
+                let parent = if name.is_some() && self.eat_word("onto") {
+                    Some(self.ident()?)
+                } else {
+                    None
+                };
+                let mut message = None;
+                let mut commands = Vec::new();
+                if self.at_kind(Kind::OpenBrace) {
+                    let items = self.items(Mode::Ddl, true, |p| {
+                        if !p.at_word("set")
+                            || p.peek_kind_at(2) != Some(Kind::Assign)
+                        {
+                            return p.statement().map(Some);
+                        }
+                        let start = p.bump().start;
+                        let name = p.ident()?;
+                        p.bump();
+                        let value = p.expr()?;
+                        if name != "message" {
+                            return Err(p.error_at(
+                                format!("unexpected field: {:?}", name),
+                                start, p.prev_end()));
+                        }
+                        message = Some(value);
+                        Ok(None)
+                    })?;
+                    commands.extend(items.into_iter().flatten());
+                }
+                MigrationCommandKind::Create {
+                    name, parent, message, commands,
+                }
+            }
+            "start" => {
+                self.expect_word("to")?;
+                let schema_start = self.next_pos();
+                let declarations = self.items(Mode::Sdl, true, |p| {
+                    let start = p.next_pos();
+                    p.declaration(Mode::Sdl, start)
+                })?;
+                self.check_toplevel(&declarations)?;
+                MigrationCommandKind::Start {
+                    target: Schema {
+                        declarations,
+                        start: schema_start,
+                        end: self.prev_end(),
+                    },
+                }
+            }
+            "populate" => MigrationCommandKind::Populate,
+            "commit" => MigrationCommandKind::Commit,
+            "abort" => MigrationCommandKind::Abort,
+            "alter" => {
+                let name = self.ident()?;
+                let commands = self.alter_block()?;
+                MigrationCommandKind::Alter { name, commands }
+            }
+            "drop" => MigrationCommandKind::Drop { name: self.ident()? },
+            _ => unreachable!("migration verb"),
+        };
+        Ok(Statement::Migration(MigrationCommand {
+            kind,
+            start, end: self.prev_end(),
+        }))
+    }
+
+    /// Parses `{ item; item }` or, if not `braced`, items up to the end
+    ///
+    /// In SDL semicolon may be omitted after an item ending with a block.
+    fn items<T, F>(&mut self, mode: Mode, braced: bool, mut item: F)
+        -> Result<Vec<T>, ParseError>
+        where F: FnMut(&mut Parser<'a>) -> Result<T, ParseError>
+    {
+        let close = if braced {
+            self.expect_kind(Kind::OpenBrace, "`{`")?;
+            Some(Kind::CloseBrace)
+        } else {
+            None
+        };
+        let mut items = Vec::new();
+        loop {
+            while self.eat_kind(Kind::Semicolon) {}
+            if self.peek_kind_at(0) == close {
+                break;
+            }
+            items.push(self.nested(&mut item)?);
+            if self.peek_kind_at(0) == close {
+                break;
+            }
+            let after_block = mode == Mode::Sdl
+                && self.prev_kind() == Some(Kind::CloseBrace);
+            if !self.eat_kind(Kind::Semicolon) && !after_block {
+                return Err(self.unexpected("`;`"));
+            }
+        }
+        if braced {
+            self.expect_kind(Kind::CloseBrace, "`}`")?;
+        }
+        Ok(items)
+    }
+
+    /// Optional block of a `CREATE` command or an SDL declaration
+    fn create_block(&mut self, mode: Mode)
+        -> Result<Vec<DdlCommand>, ParseError>
+    {
+        if !self.at_kind(Kind::OpenBrace) {
+            return Ok(Vec::new());
+        }
+        match mode {
+            Mode::Ddl => self.items(mode, true, |p| p.ddl_command()),
+            Mode::Sdl => self.items(mode, true, |p| p.sdl_command()),
+        }
+    }
+
+    /// Block of an `ALTER` command, braces may be omitted for one command
+    fn alter_block(&mut self) -> Result<Vec<DdlCommand>, ParseError> {
+        if self.at_kind(Kind::OpenBrace) {
+            self.items(Mode::Ddl, true, |p| p.ddl_command())
+        } else {
+            Ok(vec![self.nested(|p| p.ddl_command())?])
+        }
+    }
+
+    fn ddl_command(&mut self) -> Result<DdlCommand, ParseError> {
+        let start = self.next_pos();
+        let kind = if self.eat_word("create") {
+            if self.eat_word("annotation") {
+                self.annotation_value()?
+            } else {
+                DdlCommandKind::Create(self.declaration(Mode::Ddl, start)?)
+            }
+        } else if self.eat_word("alter") {
+            if self.eat_word("annotation") {
+                self.annotation_value()?
+            } else {
+                DdlCommandKind::Alter(self.alter_object(start)?)
+            }
+        } else if self.eat_word("drop") {
+            self.drop_command(start)?
+        } else if self.eat_word("set") {
+            self.set_command()?
+        } else if self.eat_word("rename") {
+            self.expect_word("to")?;
+            DdlCommandKind::Rename(self.node_name()?)
+        } else if self.eat_word("extending") {
+            let bases = self.type_list()?;
+            let position = if self.eat_word("before") {
+                Some(InheritPosition::Before(self.node_name()?))
+            } else if self.eat_word("after") {
+                Some(InheritPosition::After(self.node_name()?))
+            } else if self.eat_word("first") {
+                Some(InheritPosition::First)
+            } else if self.eat_word("last") {
+                Some(InheritPosition::Last)
+            } else {
+                None
+            };
+            DdlCommandKind::Extending { bases, position }
+        } else if self.at_word("using") {
+            self.using_clause()?
+        } else if self.at_word("on") {
+            self.on_target_delete()?
+        } else {
+            return Err(self.unexpected("DDL command"));
+        };
+        Ok(DdlCommand { kind, start, end: self.prev_end() })
+    }
+
+    fn sdl_command(&mut self) -> Result<DdlCommand, ParseError> {
+        let start = self.next_pos();
+        let kind = if self.at_ident()
+            && self.peek_kind_at(1) == Some(Kind::Assign)
+        {
+            let name = self.ident()?;
+            self.bump();
+            DdlCommandKind::SetField { name, value: self.expr()? }
+        } else if self.eat_word("annotation") {
+            self.annotation_value()?
+        } else if self.at_word("using") {
+            self.using_clause()?
+        } else if self.at_word("on") {
+            self.on_target_delete()?
+        } else {
+            DdlCommandKind::Create(self.declaration(Mode::Sdl, start)?)
+        };
+        Ok(DdlCommand { kind, start, end: self.prev_end() })
+    }
+
+    fn annotation_value(&mut self) -> Result<DdlCommandKind, ParseError> {
+        let name = self.node_name()?;
+        self.expect_kind(Kind::Assign, "`:=`")?;
+        let value = self.expr()?;
+        Ok(DdlCommandKind::SetAnnotation { name, value })
+    }
+
+    /// Next token ends a command, so a preceding word is not a prefix
+    fn at_command_end(&self, n: usize) -> bool {
+        matches!(self.peek_kind_at(n),
+                 None | Some(Kind::Semicolon) | Some(Kind::CloseBrace))
+    }
+
+    fn drop_command(&mut self, start: Pos)
+        -> Result<DdlCommandKind, ParseError>
+    {
+        use DdlCommandKind::*;
+
+        if self.eat_word("annotation") {
+            return Ok(DropAnnotation(self.node_name()?));
+        }
+        if self.eat_word("extending") {
+            return Ok(DropExtending(self.type_list()?));
+        }
+        if self.at_command_end(1) {
+            let flag = if self.at_word("required") {
+                Some(SetRequired(false))
+            } else if self.at_word("abstract") {
+                Some(SetAbstract(false))
+            } else if self.at_word("final") {
+                Some(SetFinal(false))
+            } else if self.at_word("delegated") {
+                Some(SetDelegated(false))
+            } else if self.at_word("owned") {
+                Some(SetOwned(false))
+            } else {
+                None
+            };
+            if let Some(flag) = flag {
+                self.bump();
+                return Ok(flag);
+            }
+        }
+        Ok(Drop(self.drop_object(start)?))
+    }
+
+    fn set_command(&mut self) -> Result<DdlCommandKind, ParseError> {
+        use DdlCommandKind::*;
+
+        if self.peek_kind_at(1) == Some(Kind::Assign) {
+            let name = self.ident()?;
+            self.bump();
+            return Ok(SetField { name, value: self.expr()? });
+        }
+        let kind = if self.eat_word("type") {
+            SetType(self.type_expr(true)?)
+        } else if self.eat_word("required") {
+            SetRequired(true)
+        } else if self.eat_word("single") {
+            SetCardinality(Cardinality::One)
+        } else if self.eat_word("multi") {
+            SetCardinality(Cardinality::Many)
+        } else if self.eat_word("abstract") {
+            SetAbstract(true)
+        } else if self.eat_word("final") {
+            SetFinal(true)
+        } else if self.eat_word("delegated") {
+            SetDelegated(true)
+        } else if self.eat_word("owned") {
+            SetOwned(true)
+        } else {
+            return Err(self.unexpected("field name"));
+        };
+        Ok(kind)
+    }
+
+    fn using_clause(&mut self) -> Result<DdlCommandKind, ParseError> {
+        self.expect_word("using")?;
+        if self.at_kind(Kind::OpenParen) {
+            return Ok(DdlCommandKind::Using(self.paren_expr()?));
+        }
+        let lang_start = self.next_pos();
+        let lang_name = self.ident()?;
+        let language = match &lang_name.to_ascii_lowercase()[..] {
+            "edgeql" => Language::EdgeQL,
+            "sql" => Language::Sql,
+            _ => return Err(self.error_at(
+                format!("{} is not a valid language", lang_name),
+                lang_start, self.prev_end())),
+        };
+        let sql_only = |p: &Parser, clause: &str| {
+            if language == Language::Sql {
+                Ok(())
+            } else {
+                Err(p.error_at(
+                    format!("{} language is not supported in {} clause",
+                            lang_name, clause),
+                    lang_start, p.prev_end()))
+            }
+        };
+        let code = if self.eat_word("function") {
+            sql_only(self, "USING FUNCTION")?;
+            FunctionCode::SqlFunction(self.string_literal()?)
+        } else if self.eat_word("expression") {
+            sql_only(self, "USING")?;
+            FunctionCode::SqlExpression
+        } else {
+            FunctionCode::Code { language, code: self.string_literal()? }
+        };
+        Ok(DdlCommandKind::FunctionCode(code))
+    }
+
+    fn string_literal(&mut self) -> Result<String, ParseError> {
+        let tok = self.expect_kind(Kind::Str, "string literal")?;
+        unquote_string(tok.token.value)
+            .map(|s| s.into())
+            .map_err(|e| self.error_at(e.to_string(), tok.start, tok.end))
+    }
+
+    fn on_target_delete(&mut self) -> Result<DdlCommandKind, ParseError> {
+        self.expect_word("on")?;
+        self.expect_word("target")?;
+        self.expect_word("delete")?;
+        let action = if self.eat_word("restrict") {
+            TargetDeleteAction::Restrict
+        } else if self.eat_word("delete") {
+            self.expect_word("source")?;
+            TargetDeleteAction::DeleteSource
+        } else if self.eat_word("allow") {
+            TargetDeleteAction::Allow
+        } else if self.eat_word("deferred") {
+            self.expect_word("restrict")?;
+            TargetDeleteAction::DeferredRestrict
+        } else {
+            return Err(self.unexpected("link target delete action"));
+        };
+        Ok(DdlCommandKind::OnTargetDelete(action))
+    }
+
+    fn type_list(&mut self) -> Result<Vec<TypeExpr>, ParseError> {
+        let mut types = vec![self.type_expr(true)?];
+        while self.eat_kind(Kind::Comma) {
+            types.push(self.type_expr(true)?);
+        }
+        Ok(types)
+    }
+
+    fn opt_extending(&mut self) -> Result<Vec<TypeExpr>, ParseError> {
+        if self.eat_word("extending") {
+            self.type_list()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn required_paren_expr(&mut self) -> Result<Expr, ParseError> {
+        if !self.at_kind(Kind::OpenParen) {
+            return Err(self.unexpected("`(`"));
+        }
+        self.paren_expr()
+    }
+
+    fn opt_on(&mut self) -> Result<Option<Expr>, ParseError> {
+        if self.eat_word("on") {
+            Ok(Some(self.required_paren_expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn constraint_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        if !self.eat_kind(Kind::OpenParen) {
+            return Ok(Vec::new());
+        }
+        let args = self.expr_list(Kind::CloseParen)?;
+        self.expect_kind(Kind::CloseParen, "`)`")?;
+        Ok(args)
+    }
+
+    fn qualifiers(&mut self) -> Result<Qualifiers, ParseError> {
+        let mut quals = Qualifiers { words: Vec::new() };
+        while let Some(tok) = self.peek() {
+            if !matches!(tok.token.kind, Kind::Keyword | Kind::Ident) {
+                break;
+            }
+            let word = tok.token.value.to_ascii_lowercase();
+            if !QUALIFIERS.contains(&&word[..]) {
+                break;
+            }
+            let tok = self.bump();
+            let conflicting = match &word[..] {
+                "required" => "optional",
+                "optional" => "required",
+                "single" => "multi",
+                "multi" => "single",
+                _ => "",
+            };
+            if quals.has(&word) || quals.has(conflicting) {
+                return Err(self.error_at(
+                    format!("unexpected `{}`", word.to_uppercase()),
+                    tok.start, tok.end));
+            }
+            quals.words.push((word, tok.start, tok.end));
+        }
+        Ok(quals)
+    }
+
+    /// Parses a schema object definition, `CREATE` is already consumed
+    fn declaration(&mut self, mode: Mode, start: Pos)
+        -> Result<Declaration, ParseError>
+    {
+        let quals = self.qualifiers()?;
+        let word = match self.peek() {
+            Some(tok) if matches!(tok.token.kind, Kind::Keyword | Kind::Ident)
+            => tok.token.value.to_ascii_lowercase(),
+            _ => return Err(self.unexpected("schema object kind")),
+        };
+        let is_abstract = quals.has("abstract");
+        let decl = match &word[..] {
+            "module" => {
+                quals.check(self, &[])?;
+                self.bump();
+                let name = self.module_name()?;
+                let mut if_not_exists = false;
+                let mut declarations = Vec::new();
+                match mode {
+                    Mode::Ddl => {
+                        if self.eat_word("if") {
+                            self.expect_word("not")?;
+                            self.expect_word("exists")?;
+                            if_not_exists = true;
+                        }
+                    }
+                    Mode::Sdl => {
+                        declarations = self.items(Mode::Sdl, true, |p| {
+                            let start = p.next_pos();
+                            p.declaration(Mode::Sdl, start)
+                        })?;
+                        self.check_module(&declarations)?;
+                    }
+                }
+                Declaration::Module(ModuleDeclaration {
+                    name, if_not_exists, declarations,
+                    start, end: self.prev_end(),
+                })
+            }
+            "scalar" => {
+                quals.check(self, &["abstract", "final"])?;
+                self.bump();
+                self.expect_word("type")?;
+                let name = self.node_name()?;
+                let bases = self.opt_extending()?;
+                let commands = self.create_block(mode)?;
+                Declaration::ScalarType(ScalarTypeDeclaration {
+                    name, is_abstract,
+                    is_final: quals.has("final"),
+                    bases, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            "type" => {
+                quals.check(self, &["abstract"])?;
+                self.bump();
+                let name = self.node_name()?;
+                let bases = self.opt_extending()?;
+                let commands = self.create_block(mode)?;
+                Declaration::ObjectType(ObjectTypeDeclaration {
+                    name, is_abstract, bases, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            "alias" => {
+                quals.check(self, &[])?;
+                self.bump();
+                let name = self.node_name()?;
+                let (expr, commands) = if self.eat_kind(Kind::Assign) {
+                    (self.expr()?, Vec::new())
+                } else {
+                    if !self.at_kind(Kind::OpenBrace) {
+                        return Err(self.unexpected("`:=` or `{`"));
+                    }
+                    let commands = self.create_block(mode)?;
+                    let (code, commands) = self.take_code(commands)?;
+                    match code {
+                        Some(FunctionCode::Expr(expr)) => (expr, commands),
+                        _ => return Err(self.error_at(
+                            "missing a USING clause".into(),
+                            start, self.prev_end())),
+                    }
+                };
+                Declaration::Alias(AliasDeclaration {
+                    name, expr, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            "annotation" => {
+                quals.check(self, &["abstract", "inheritable"])?;
+                if !is_abstract {
+                    return Err(self.unexpected("`:=`"));
+                }
+                self.bump();
+                let name = self.node_name()?;
+                let bases = self.opt_extending()?;
+                let commands = self.create_block(mode)?;
+                Declaration::Annotation(AnnotationDeclaration {
+                    name,
+                    inheritable: quals.has("inheritable"),
+                    bases, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            "constraint" if is_abstract => {
+                quals.check(self, &["abstract"])?;
+                self.bump();
+                let name = self.node_name()?;
+                let params = if self.at_kind(Kind::OpenParen) {
+                    self.func_params()?
+                } else {
+                    Vec::new()
+                };
+                let subject = self.opt_on()?;
+                let bases = self.opt_extending()?;
+                let commands = self.create_block(mode)?;
+                Declaration::Constraint(ConstraintDeclaration {
+                    name, params, subject, bases, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            "constraint" => {
+                quals.check(self, &["delegated"])?;
+                self.bump();
+                let name = self.node_name()?;
+                let args = self.constraint_args()?;
+                let subject = self.opt_on()?;
+                let commands = self.create_block(mode)?;
+                Declaration::ConcreteConstraint(ConcreteConstraint {
+                    name,
+                    delegated: quals.has("delegated"),
+                    args, subject, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            "link" | "property" if is_abstract => {
+                quals.check(self, &["abstract"])?;
+                self.bump();
+                let decl = PointerDeclaration {
+                    name: self.node_name()?,
+                    bases: self.opt_extending()?,
+                    commands: self.create_block(mode)?,
+                    start, end: self.prev_end(),
+                };
+                if word == "link" {
+                    Declaration::Link(decl)
+                } else {
+                    Declaration::Property(decl)
+                }
+            }
+            "link" | "property" => {
+                quals.check(self, &["overloaded", "required", "optional",
+                                    "single", "multi"])?;
+                self.bump();
+                let decl = self.concrete_pointer(mode, &quals, start)?;
+                if word == "link" {
+                    Declaration::ConcreteLink(decl)
+                } else {
+                    Declaration::ConcreteProperty(decl)
+                }
+            }
+            "index" => {
+                quals.check(self, &[])?;
+                self.bump();
+                self.expect_word("on")?;
+                let expr = self.required_paren_expr()?;
+                let commands = self.create_block(mode)?;
+                Declaration::Index(IndexDeclaration {
+                    expr, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            "function" => {
+                quals.check(self, &[])?;
+                self.bump();
+                let name = self.node_name()?;
+                if !self.at_kind(Kind::OpenParen) {
+                    return Err(self.unexpected("`(`"));
+                }
+                let params = self.func_params()?;
+                self.expect_kind(Kind::Arrow, "`->`")?;
+                let returning_typemod = self.type_modifier();
+                let returning = self.type_expr(true)?;
+                let commands = if self.at_kind(Kind::OpenBrace) {
+                    self.create_block(mode)?
+                } else {
+                    match mode {
+                        Mode::Ddl => vec![self.ddl_command()?],
+                        Mode::Sdl => vec![self.sdl_command()?],
+                    }
+                };
+                let (code, commands) = self.take_code(commands)?;
+                let code = code.ok_or_else(|| self.error_at(
+                    "missing a USING clause".into(),
+                    start, self.prev_end()))?;
+                Declaration::Function(FunctionDeclaration {
+                    name, params, returning, returning_typemod,
+                    code, commands,
+                    start, end: self.prev_end(),
+                })
+            }
+            _ => return Err(self.unexpected("schema object kind")),
+        };
+        Ok(decl)
+    }
+
+    fn concrete_pointer(&mut self, mode: Mode, quals: &Qualifiers,
+        start: Pos)
+        -> Result<ConcretePointer, ParseError>
+    {
+        let name_start = self.next_pos();
+        let name = ObjectRef {
+            module: None,
+            name: self.ident()?,
+            start: name_start,
+            end: self.prev_end(),
+        };
+        let bases = self.opt_extending()?;
+        let mut target = None;
+        let mut commands = Vec::new();
+        if self.eat_kind(Kind::Arrow) {
+            target = Some(PointerTarget::Type(self.type_expr(true)?));
+            commands = self.create_block(mode)?;
+        } else if bases.is_empty() && self.eat_kind(Kind::Assign) {
+            target = Some(PointerTarget::Computable(self.expr()?));
+        } else {
+            commands = self.create_block(mode)?;
+        }
+        if target.is_none() && !quals.has("overloaded") {
+            let (code, rest) = self.take_code(commands)?;
+            commands = rest;
+            match code {
+                Some(FunctionCode::Expr(expr)) => {
+                    target = Some(PointerTarget::Computable(expr));
+                }
+                _ => return Err(self.error_at(
+                    format!("missing target of `{}`", name.name),
+                    start, self.prev_end())),
+            }
+        }
+        Ok(ConcretePointer {
+            name,
+            overloaded: quals.has("overloaded"),
+            required: quals.required(),
+            cardinality: quals.cardinality(),
+            bases, target, commands,
+            start, end: self.prev_end(),
+        })
+    }
+
+    /// Extracts a single `USING` clause from the commands
+    fn take_code(&self, commands: Vec<DdlCommand>)
+        -> Result<(Option<FunctionCode>, Vec<DdlCommand>), ParseError>
+    {
+        let mut code = None;
+        let mut rest = Vec::new();
+        for DdlCommand { kind, start, end } in commands {
+            let item = match kind {
+                DdlCommandKind::Using(expr) => FunctionCode::Expr(expr),
+                DdlCommandKind::FunctionCode(code) => code,
+                kind => {
+                    rest.push(DdlCommand { kind, start, end });
+                    continue;
+                }
+            };
+            if code.is_some() {
+                return Err(self.error_at(
+                    "more than one USING clause".into(), start, end));
+            }
+            code = Some(item);
+        }
+        Ok((code, rest))
+    }
+
+    fn type_modifier(&mut self) -> TypeModifier {
+        if self.at_word("set") && self.at_word_at(1, "of") {
+            self.bump();
+            self.bump();
+            TypeModifier::SetOf
+        } else if self.eat_word("optional") {
+            TypeModifier::Optional
+        } else {
+            TypeModifier::Singleton
+        }
+    }
+
+    fn func_params(&mut self) -> Result<Vec<FuncParam>, ParseError> {
+        self.expect_kind(Kind::OpenParen, "`(`")?;
+        let mut params = Vec::new();
+        while !self.at_kind(Kind::CloseParen) {
+            params.push(self.func_param()?);
+            if !self.eat_kind(Kind::Comma) {
+                break;
+            }
+        }
+        self.expect_kind(Kind::CloseParen, "`)`")?;
+        self.check_params(&params)?;
+        Ok(params)
+    }
+
+    fn func_param(&mut self) -> Result<FuncParam, ParseError> {
+        let start = self.next_pos();
+        let kind = if self.eat_word("variadic") {
+            ParameterKind::Variadic
+        } else if self.at_word("named") && self.at_word_at(1, "only") {
+            self.bump();
+            self.bump();
+            ParameterKind::NamedOnly
+        } else {
+            ParameterKind::Positional
+        };
+        if self.at_kind(Kind::Argument) {
+            let tok = self.bump();
+            let name = &tok.token.value[1..];
+            let message = if name.starts_with(|c: char| c.is_ascii_digit()) {
+                "numeric parameters are not supported".into()
+            } else {
+                format!("function parameters do not need a $ prefix, \
+                         rewrite as '{}'", name)
+            };
+            return Err(self.error_at(message, tok.start, tok.end));
+        }
+        let name = self.ident()?;
+        if !self.eat_kind(Kind::Colon) {
+            return Err(self.error_at(
+                format!("missing type declaration for the `{}` parameter",
+                        name),
+                start, self.prev_end()));
+        }
+        let typemod = self.type_modifier();
+        let type_ = self.type_expr(true)?;
+        let default = if self.eat_kind(Kind::Eq) {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        Ok(FuncParam {
+            name, kind, typemod, type_, default,
+            start, end: self.prev_end(),
+        })
+    }
+
+    fn check_params(&self, params: &[FuncParam]) -> Result<(), ParseError> {
+        let mut last_pos_default: Option<&FuncParam> = None;
+        let mut last_named: Option<&FuncParam> = None;
+        let mut variadic: Option<&FuncParam> = None;
+        for (idx, param) in params.iter().enumerate() {
+            let err = |message: String| {
+                Err(self.error_at(message, param.start, param.end))
+            };
+            if params[..idx].iter().any(|p| p.name == param.name) {
+                return err(format!("duplicate parameter name `{}`",
+                                   param.name));
+            }
+            match param.kind {
+                ParameterKind::Variadic => {
+                    if variadic.is_some() {
+                        return err("more than one variadic argument".into());
+                    }
+                    if let Some(named) = last_named {
+                        return err(format!("NAMED ONLY argument `{}` \
+                            before VARIADIC argument `{}`",
+                            named.name, param.name));
+                    }
+                    if param.default.is_some() {
+                        return err(format!("VARIADIC argument `{}` \
+                            cannot have a default value", param.name));
+                    }
+                    variadic = Some(param);
+                }
+                ParameterKind::NamedOnly => {
+                    last_named = Some(param);
+                }
+                ParameterKind::Positional => {
+                    if let Some(named) = last_named {
+                        return err(format!("positional argument `{}` \
+                            follows NAMED ONLY argument `{}`",
+                            param.name, named.name));
+                    }
+                    if let Some(var) = variadic {
+                        return err(format!("positional argument `{}` \
+                            follows VARIADIC argument `{}`",
+                            param.name, var.name));
+                    }
+                    if param.default.is_some() {
+                        last_pos_default = Some(param);
+                    } else if let Some(prev) = last_pos_default {
+                        return err(format!("positional argument `{}` \
+                            without default follows positional argument \
+                            `{}` with default", param.name, prev.name));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn object_kind(&mut self) -> Result<(ObjectKind, bool), ParseError> {
+        let is_abstract = self.eat_word("abstract");
+        let kind = if self.eat_word("module") {
+            ObjectKind::Module
+        } else if self.eat_word("scalar") {
+            self.expect_word("type")?;
+            ObjectKind::ScalarType
+        } else if self.eat_word("type") {
+            ObjectKind::ObjectType
+        } else if self.eat_word("alias") {
+            ObjectKind::Alias
+        } else if self.eat_word("annotation") {
+            ObjectKind::Annotation
+        } else if self.eat_word("constraint") {
+            ObjectKind::Constraint
+        } else if self.eat_word("link") {
+            ObjectKind::Link
+        } else if self.eat_word("property") {
+            ObjectKind::Property
+        } else if self.eat_word("function") {
+            ObjectKind::Function
+        } else if self.eat_word("index") {
+            ObjectKind::Index
+        } else {
+            return Err(self.unexpected("schema object kind"));
+        };
+        Ok((kind, is_abstract))
+    }
+
+    fn drop_object(&mut self, start: Pos) -> Result<DropObject, ParseError> {
+        let (kind, is_abstract) = self.object_kind()?;
+        let mut params = None;
+        let mut args = Vec::new();
+        let mut subject = None;
+        let name_start = self.next_pos();
+        let name = match kind {
+            ObjectKind::Module => ObjectRef {
+                module: None,
+                name: self.module_name()?,
+                start: name_start,
+                end: self.prev_end(),
+            },
+            ObjectKind::Index => {
+                self.expect_word("on")?;
+                subject = Some(self.required_paren_expr()?);
+                ObjectRef {
+                    module: None,
+                    name: "idx".into(),
+                    start: name_start,
+                    end: self.prev_end(),
+                }
+            }
+            ObjectKind::Function => {
+                let name = self.node_name()?;
+                if !self.at_kind(Kind::OpenParen) {
+                    return Err(self.unexpected("`(`"));
+                }
+                params = Some(self.func_params()?);
+                name
+            }
+            ObjectKind::Constraint if !is_abstract => {
+                let name = self.node_name()?;
+                args = self.constraint_args()?;
+                subject = self.opt_on()?;
+                name
+            }
+            _ => self.node_name()?,
+        };
+        Ok(DropObject {
+            kind, is_abstract, name, params, args, subject,
+            start, end: self.prev_end(),
+        })
+    }
+
+    fn alter_object(&mut self, start: Pos)
+        -> Result<AlterObject, ParseError>
+    {
+        let DropObject {
+            kind, is_abstract, name, params, args, subject, ..
+        } = self.drop_object(start)?;
+        let commands = self.alter_block()?;
+        Ok(AlterObject {
+            kind, is_abstract, name, params, args, subject, commands,
+            start, end: self.prev_end(),
+        })
+    }
+
+    fn check_toplevel(&self, declarations: &[Declaration])
+        -> Result<(), ParseError>
+    {
+        for decl in declarations {
+            match decl.name() {
+                Some(name) if name.module.is_none() => {
+                    return Err(self.error_at(
+                        "only fully-qualified name is allowed in \
+                         top-level declaration".into(),
+                        name.start, name.end));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_module(&self, declarations: &[Declaration])
+        -> Result<(), ParseError>
+    {
+        for decl in declarations {
+            if let Declaration::Module(module) = decl {
+                return Err(self.error_at(
+                    "nested module declaration is not allowed".into(),
+                    module.start, module.end));
+            }
+            match decl.name() {
+                Some(name) if name.module.is_some() => {
+                    return Err(self.error_at(
+                        "fully-qualified name is not allowed in \
+                         a module declaration".into(),
+                        name.start, name.end));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
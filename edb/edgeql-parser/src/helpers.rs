@@ -5,7 +5,7 @@ use std::char;
 
 use crate::tokenizer::is_keyword;
 
-/// Error returned from `unquote_string` function
+/// Error returned from `unquote_string` and `unquote_bytes` functions
 ///
 /// Opaque for now
 #[derive(Debug)]
@@ -40,15 +40,19 @@ pub fn quote_string(s: &str) -> String {
     buf.push('"');
     for c in s.chars() {
         match c {
-            '"' => {
+            '"' | '\\' => {
                 buf.push('\\');
-                buf.push('"');
+                buf.push(c);
             }
             '\x00'..='\x08' | '\x0B' | '\x0C' | '\x0E'..='\x1F' |
-            '\u{007F}' | '\u{0080}'..='\u{009F}'
+            '\u{007F}'
             => {
                 write!(buf, "\\x{:02x}", c as u32).unwrap();
             }
+            // only ascii is allowed in `\x` escapes
+            '\u{0080}'..='\u{009F}' => {
+                write!(buf, "\\u{:04x}", c as u32).unwrap();
+            }
             c => buf.push(c),
         }
     }
@@ -56,6 +60,31 @@ pub fn quote_string(s: &str) -> String {
     return buf;
 }
 
+/// Converts bytes into edgeql bytes literal
+///
+/// # Examples
+/// ```
+/// use edgeql_parser::helpers::quote_bytes;
+/// assert_eq!(quote_bytes(b"abc"), r#"b"abc""#);
+/// assert_eq!(quote_bytes(b"\"\\\n\xff"), r#"b"\"\\\x0a\xff""#);
+/// ```
+pub fn quote_bytes(s: &[u8]) -> String {
+    let mut buf = String::with_capacity(s.len() + 3);
+    buf.push_str("b\"");
+    for &b in s {
+        match b {
+            b'"' | b'\\' => {
+                buf.push('\\');
+                buf.push(b as char);
+            }
+            0x20..=0x7E => buf.push(b as char),
+            _ => write!(buf, "\\x{:02x}", b).unwrap(),
+        }
+    }
+    buf.push('"');
+    return buf;
+}
+
 pub fn unquote_string<'a>(value: &'a str) -> Result<Cow<'a, str>, UnquoteError>
 {
     if value.starts_with('r') {
@@ -194,6 +223,110 @@ fn complex_strings() {
         "\u{62}:\u{2665}:\u{25C6}");
 }
 
+pub fn unquote_bytes(value: &str) -> Result<Vec<u8>, UnquoteError> {
+    _unquote_bytes(&value[2..value.len()-1]).map_err(UnquoteError)
+}
+
+fn _unquote_bytes<'a>(s: &'a str) -> Result<Vec<u8>, String> {
+    let mut res = Vec::with_capacity(s.len());
+    let mut bytes = s.as_bytes().iter();
+    while let Some(&c) = bytes.next() {
+        match c {
+            b'\\' => {
+                match *bytes.next().expect("slash cant be at the end") {
+                    c@b'"' | c@b'\\' | c@b'/' | c@b'\'' => res.push(c),
+                    b'b' => res.push(b'\x10'),
+                    b'f' => res.push(b'\x0C'),
+                    b'n' => res.push(b'\n'),
+                    b'r' => res.push(b'\r'),
+                    b't' => res.push(b'\t'),
+                    b'x' => {
+                        let tail = &s[s.len() - bytes.as_slice().len()..];
+                        let hex = tail.get(0..2);
+                        let code = hex.and_then(|s| {
+                            u8::from_str_radix(s, 16).ok()
+                        }).ok_or_else(|| {
+                            format!("invalid bytes literal: \
+                                invalid escape sequence '\\x{}'",
+                                hex.unwrap_or_else(|| tail).escape_debug())
+                        })?;
+                        res.push(code);
+                        bytes.nth(1);
+                    }
+                    b'\r' | b'\n' => {
+                        let nskip = bytes.as_slice()
+                            .iter()
+                            .take_while(|&&x| x.is_ascii_whitespace())
+                            .count();
+                        if nskip > 0 {
+                            bytes.nth(nskip-1);
+                        }
+                    }
+                    c => {
+                        let ch = if c < 0x7f {
+                            c as char
+                        } else {
+                            // recover the unicode byte
+                            s[s.len()-bytes.as_slice().len()-1..]
+                            .chars().next().unwrap()
+                        };
+                        return Err(format!("invalid bytes literal: \
+                            invalid escape sequence '\\{}'",
+                           ch.escape_debug()));
+                    }
+                }
+            }
+            c => res.push(c),
+        }
+    }
+
+    Ok(res)
+}
+
+#[test]
+fn simple_bytes() {
+    assert_eq!(_unquote_bytes(r#"\x09"#).unwrap(), b"\x09");
+    assert_eq!(_unquote_bytes(r#"\x0A"#).unwrap(), b"\x0A");
+    assert_eq!(_unquote_bytes(r#"\x0D"#).unwrap(), b"\x0D");
+    assert_eq!(_unquote_bytes(r#"\x20"#).unwrap(), b"\x20");
+}
+
+#[test]
+fn newline_escaping_bytes() {
+    assert_eq!(_unquote_bytes(r"hello \
+                                world").unwrap(), b"hello world");
+
+    assert_eq!(_unquote_bytes(r"bb\
+aa \
+            bb").unwrap(), b"bbaa bb");
+    assert_eq!(_unquote_bytes(r"bb\
+
+        aa").unwrap(), b"bbaa");
+    assert_eq!(_unquote_bytes(r"bb\
+        \
+        aa").unwrap(), b"bbaa");
+    assert_eq!(_unquote_bytes("bb\\\r   aa").unwrap(), b"bbaa");
+    assert_eq!(_unquote_bytes("bb\\\r\n   aa").unwrap(), b"bbaa");
+}
+
+#[test]
+fn complex_bytes() {
+    assert_eq!(_unquote_bytes(r#"\x09 hello \x0A there"#).unwrap(),
+        b"\x09 hello \x0A there");
+}
+
+#[test]
+fn quote_roundtrip() {
+    for s in &["", "simple", "\"quoted\"", "back\\slash \\n", "\x01\u{85}",
+               "multi\nline", "юникод"]
+    {
+        assert_eq!(unquote_string(&quote_string(s)).unwrap(), *s);
+    }
+    for s in &[&b""[..], b"simple", b"\"\\n\\", b"\x00\x7f\xff\n"] {
+        assert_eq!(unquote_bytes(&quote_bytes(s)).unwrap(), *s);
+    }
+}
+
 impl fmt::Display for UnquoteError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(f)
@@ -0,0 +1,165 @@
+//! Incremental re-tokenization of edited text
+//!
+//! Only the region affected by an edit is lexed again, tokens after it are
+//! reused with their positions shifted. This is meant for editors, which
+//! change a few characters of a large file at a time.
+use std::ops::Range;
+
+use crate::position::Pos;
+use crate::tokenizer::{TokenStream, SpannedToken, Token, Kind, Diagnostic};
+
+
+/// Replacement of a byte range of the previous text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edit<'a> {
+    /// Byte offset of the start of the replaced range in the previous text
+    pub start: usize,
+    /// Byte offset of the end of the replaced range in the previous text
+    pub end: usize,
+    /// Text inserted instead of the range
+    pub text: &'a str,
+}
+
+#[derive(Debug)]
+pub struct Retokenized<'a> {
+    /// Tokens of the whole new text
+    pub tokens: Vec<SpannedToken<'a>>,
+    /// Range of `tokens` which differ from the previous tokens
+    pub changed: Range<usize>,
+    /// Range of the previous tokens replaced by the `changed` ones
+    pub replaced: Range<usize>,
+    /// Lexical errors of the `Kind::Error` tokens in the `changed` range
+    ///
+    /// Errors of the other tokens are the same as for the previous text.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn offset(pos: Pos) -> usize {
+    pos.offset as usize
+}
+
+/// Token after a dot is lexed differently (`.1` is a tuple element)
+fn sets_dot(kind: Kind) -> bool {
+    matches!(kind, Kind::Dot | Kind::ForwardLink)
+}
+
+fn same(a: &SpannedToken, b: &SpannedToken) -> bool {
+    a.token == b.token && a.start == b.start && a.end == b.end
+}
+
+/// Tokenizes `text` which is the previous text with the `edit` applied
+///
+/// `old` must be the complete output of `TokenStream::new_recovering()`
+/// for the previous text. The text is lexed in the same mode, so invalid
+/// parts of it, like an unclosed quote while it's being typed, become
+/// `Kind::Error` tokens rather than failing the whole edit. Tokenizing
+/// stops as soon as the lexer reaches a token that starts at the same
+/// place as one of the `old` tokens after the edit, because everything
+/// after that point is known to be the same.
+///
+/// # Panics
+///
+/// If `old` tokens or `edit` don't match `text`.
+pub fn retokenize<'a>(old: &[SpannedToken], text: &'a str, edit: &Edit)
+    -> Retokenized<'a>
+{
+    let new_end = edit.start + edit.text.len();
+    let delta = new_end as i64 - edit.end as i64;
+
+    // The token ending right at the edit might be extended by it, so we
+    // start a token earlier. And never right after a dot.
+    let mut restart = old.iter()
+        .position(|t| offset(t.end) >= edit.start)
+        .unwrap_or(old.len())
+        .saturating_sub(1);
+    while restart > 0 && sets_dot(old[restart-1].token.kind) {
+        restart -= 1;
+    }
+    let start_pos = match restart {
+        0 => Pos { line: 1, column: 1, offset: 0 },
+        _ => old[restart-1].end,
+    };
+
+    let mut tokens = old[..restart].iter().map(|t| SpannedToken {
+        token: Token {
+            kind: t.token.kind,
+            value: &text[offset(t.start)..offset(t.end)],
+        },
+        start: t.start,
+        end: t.end,
+    }).collect::<Vec<_>>();
+
+    let mut stream = TokenStream::new_at_recovering(
+        &text[offset(start_pos)..], start_pos);
+    let mut old_idx = restart;
+    let mut dot = false;
+    let mut anchor = None;
+    loop {
+        let tok = match (&mut stream).next() {
+            Some(Ok(tok)) => tok,
+            // only the end of input is an error in the recovery mode
+            Some(Err(_)) | None => {
+                old_idx = old.len();
+                break;
+            }
+        };
+        if offset(tok.start) >= new_end {
+            let old_start = (tok.start.offset as i64 - delta) as u64;
+            while old_idx < old.len() && old[old_idx].start.offset < old_start
+            {
+                old_idx += 1;
+            }
+            if let Some(prev) = old.get(old_idx) {
+                let prev_dot = old_idx > 0
+                    && sets_dot(old[old_idx-1].token.kind);
+                if prev.start.offset == old_start && prev_dot == dot {
+                    anchor = Some((prev.start, tok.start));
+                    break;
+                }
+            }
+        }
+        dot = sets_dot(tok.token.kind);
+        tokens.push(tok);
+    }
+    let mut changed = restart..tokens.len();
+    let mut replaced = restart..old_idx;
+    while !changed.is_empty() && !replaced.is_empty()
+        && same(&tokens[changed.start], &old[replaced.start])
+    {
+        changed.start += 1;
+        replaced.start += 1;
+    }
+    // the lexer could also read the first token after the changed ones
+    let changed_tokens = &tokens[changed.clone()];
+    let diagnostics = stream.take_errors().into_iter()
+        .filter(|d| changed_tokens.iter().any(|t| t.start == d.start))
+        .collect();
+
+    if let Some((old_pos, new_pos)) = anchor {
+        // Columns change only on the line where tokens are joined
+        let lines = new_pos.line as i64 - old_pos.line as i64;
+        let columns = new_pos.column as i64 - old_pos.column as i64;
+        let shift = |pos: Pos| Pos {
+            line: (pos.line as i64 + lines) as usize,
+            column: if pos.line == old_pos.line {
+                (pos.column as i64 + columns) as usize
+            } else {
+                pos.column
+            },
+            offset: (pos.offset as i64 + delta) as u64,
+        };
+        tokens.extend(old[old_idx..].iter().map(|t| {
+            let start = shift(t.start);
+            let end = shift(t.end);
+            SpannedToken {
+                token: Token {
+                    kind: t.token.kind,
+                    value: &text[offset(start)..offset(end)],
+                },
+                start,
+                end,
+            }
+        }));
+    }
+    Retokenized { tokens, changed, replaced, diagnostics }
+}
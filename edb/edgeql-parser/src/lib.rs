@@ -1,5 +1,9 @@
 pub mod preparser;
 pub mod position;
 pub mod tokenizer;
+pub mod incremental;
 pub mod helpers;
 pub mod keywords;
+pub mod ast;
+pub mod parser;
+mod ddl;
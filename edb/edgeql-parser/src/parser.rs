@@ -0,0 +1,1472 @@
+//! Recursive-descent parser for EdgeQL queries
+//!
+//! Consumes `TokenStream` and produces nodes from the `ast` module. The
+//! grammar and operator precedence mirror `edb/edgeql/parser/grammar`.
+use std::fmt;
+use std::error::Error;
+
+use crate::ast::*;
+use crate::helpers::{unquote_string, unquote_bytes};
+use crate::position::Pos;
+use crate::tokenizer::{TokenStream, SpannedToken, Kind};
+
+
+// Precedence levels, see `edb/edgeql/parser/grammar/precedence.py`
+const PREC_UNION: u8 = 1;
+const PREC_IFELSE: u8 = 2;
+const PREC_OR: u8 = 3;
+const PREC_AND: u8 = 4;
+const PREC_NOT: u8 = 5;
+const PREC_EQUALS: u8 = 6;
+const PREC_ANGBRACKET: u8 = 7;
+const PREC_LIKE: u8 = 8;
+const PREC_IN: u8 = 9;
+const PREC_OP: u8 = 10;
+const PREC_IS: u8 = 11;
+const PREC_ADD: u8 = 12;
+const PREC_MUL: u8 = 13;
+const PREC_COALESCE: u8 = 14;
+const PREC_UNARY: u8 = 15;
+const PREC_POW: u8 = 16;
+const PREC_CAST: u8 = 17;
+
+/// Nesting of expressions, shapes, types and DDL blocks deeper than this is
+/// a syntax error rather than a stack overflow
+const MAX_DEPTH: usize = 256;
+
+/// Syntax error with the span of the offending token
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+pub struct Parser<'a> {
+    tokens: Vec<SpannedToken<'a>>,
+    index: usize,
+    prev_end: Pos,
+    eof: Pos,
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Associativity {
+    Left,
+    Right,
+    NonAssoc,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Infix {
+    Binary(BinaryOperator),
+    Is { negated: bool },
+    IfElse,
+}
+
+/// Parses a semicolon-separated list of statements
+pub fn parse_block(text: &str) -> Result<Vec<Statement>, ParseError> {
+    let mut parser = Parser::new(TokenStream::new(text))?;
+    parser.block()
+}
+
+/// Parses a single statement optionally terminated by semicolon
+pub fn parse_statement(text: &str) -> Result<Statement, ParseError> {
+    let mut parser = Parser::new(TokenStream::new(text))?;
+    let stmt = parser.statement()?;
+    parser.eat_kind(Kind::Semicolon);
+    parser.end()?;
+    Ok(stmt)
+}
+
+/// Parses an SDL schema document
+pub fn parse_schema(text: &str) -> Result<Schema, ParseError> {
+    let mut parser = Parser::new(TokenStream::new(text))?;
+    parser.schema()
+}
+
+/// Parses a standalone expression
+pub fn parse_expression(text: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser::new(TokenStream::new(text))?;
+    let expr = parser.expr()?;
+    parser.end()?;
+    Ok(expr)
+}
+
+fn is_word(tok: &SpannedToken, word: &str) -> bool {
+    matches!(tok.token.kind, Kind::Keyword | Kind::Ident)
+        && tok.token.value.eq_ignore_ascii_case(word)
+}
+
+fn is_dunder(s: &str) -> bool {
+    s.len() > 4 && s.starts_with("__") && s.ends_with("__")
+}
+
+fn unquote_backtick(s: &str) -> String {
+    s[1..s.len()-1].replace("``", "`")
+}
+
+fn append_step(expr: Expr, step: PathStep, end: Pos) -> Expr {
+    let start = expr.start;
+    match expr.kind {
+        ExprKind::Path(mut path) => {
+            path.steps.push(step);
+            Expr { kind: ExprKind::Path(path), start, end }
+        }
+        kind => {
+            let inner = Expr { kind, start, end: expr.end };
+            Expr {
+                kind: ExprKind::Path(Path {
+                    steps: vec![PathStep::Expr(Box::new(inner)), step],
+                    partial: false,
+                }),
+                start,
+                end,
+            }
+        }
+    }
+}
+
+fn append_indirection(expr: Expr, item: Indirection, end: Pos) -> Expr {
+    let start = expr.start;
+    match expr.kind {
+        ExprKind::Indirection { arg, mut indirection } => {
+            indirection.push(item);
+            Expr {
+                kind: ExprKind::Indirection { arg, indirection },
+                start, end,
+            }
+        }
+        kind => {
+            let arg = Expr { kind, start, end: expr.end };
+            Expr {
+                kind: ExprKind::Indirection {
+                    arg: Box::new(arg),
+                    indirection: vec![item],
+                },
+                start, end,
+            }
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Reads all tokens from the stream
+    ///
+    /// Tokenizer errors are reported as `ParseError` at the position where
+    /// the tokenizer stopped.
+    pub fn new(mut stream: TokenStream<'a>) -> Result<Parser<'a>, ParseError>
+    {
+        use combine::easy::Error::Unexpected;
+
+        let start = stream.current_pos();
+        let mut tokens = Vec::new();
+        loop {
+            match (&mut stream).next() {
+                Some(Ok(t)) => tokens.push(t),
+                Some(Err(e)) => {
+                    let pos = stream.current_pos();
+                    let message = match e {
+                        Unexpected(s) => s.to_string(),
+                        e => e.to_string(),
+                    };
+                    return Err(ParseError { message, start: pos, end: pos });
+                }
+                None => break,
+            }
+        }
+        Ok(Parser {
+            tokens,
+            index: 0,
+            prev_end: start,
+            eof: stream.current_pos(),
+            depth: 0,
+        })
+    }
+
+    /// Returns an error if there are unparsed tokens left
+    pub fn end(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(_) => Err(self.unexpected("end of input")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn block(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let mut result = Vec::new();
+        loop {
+            while self.eat_kind(Kind::Semicolon) {}
+            if self.peek().is_none() {
+                break;
+            }
+            result.push(self.statement()?);
+            if self.peek().is_none() {
+                break;
+            }
+            self.expect_kind(Kind::Semicolon, "`;`")?;
+        }
+        Ok(result)
+    }
+
+    pub fn statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.next_pos();
+        let aliases = if self.eat_word("with") {
+            self.with_block()?
+        } else {
+            Vec::new()
+        };
+        let word = match self.peek() {
+            Some(tok) if tok.token.kind == Kind::Keyword
+            => tok.token.value.to_ascii_lowercase(),
+            _ => return Err(self.unexpected("statement")),
+        };
+        match &word[..] {
+            "select" => self.select(start, aliases),
+            "group" => self.group(start, aliases),
+            "insert" => self.insert(start, aliases),
+            "update" => self.update(start, aliases),
+            "delete" => self.delete(start, aliases),
+            "for" => self.for_query(start, aliases),
+            "create" | "alter" | "drop" | "start" | "populate" | "commit"
+            | "abort"
+            if self.at_word_at(1, "migration") => {
+                if !aliases.is_empty() {
+                    return Err(self.error_at(
+                        "WITH block is not allowed for migration commands"
+                        .into(), start, self.prev_end));
+                }
+                self.migration(start)
+            }
+            "create" | "alter" | "drop" => self.ddl_statement(start, aliases),
+            _ => Err(self.unexpected("statement")),
+        }
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, ParseError> {
+        self.expr_bp(0)
+    }
+
+    pub(crate) fn peek(&self) -> Option<&SpannedToken<'a>> {
+        self.tokens.get(self.index)
+    }
+
+    pub(crate) fn peek_at(&self, n: usize) -> Option<&SpannedToken<'a>> {
+        self.tokens.get(self.index + n)
+    }
+
+    pub(crate) fn peek_kind_at(&self, n: usize) -> Option<Kind> {
+        self.peek_at(n).map(|t| t.token.kind)
+    }
+
+    pub(crate) fn at_kind(&self, kind: Kind) -> bool {
+        self.peek_kind_at(0) == Some(kind)
+    }
+
+    pub(crate) fn at_word(&self, word: &str) -> bool {
+        self.at_word_at(0, word)
+    }
+
+    pub(crate) fn at_word_at(&self, n: usize, word: &str) -> bool {
+        self.peek_at(n).map(|t| is_word(t, word)).unwrap_or(false)
+    }
+
+    pub(crate) fn at_ident(&self) -> bool {
+        matches!(self.peek_kind_at(0),
+                 Some(Kind::Ident) | Some(Kind::BacktickName))
+    }
+
+    /// Start of the next token or end of input
+    pub(crate) fn next_pos(&self) -> Pos {
+        self.peek().map(|t| t.start).unwrap_or(self.eof)
+    }
+
+    /// End of the last consumed token
+    pub(crate) fn prev_end(&self) -> Pos {
+        self.prev_end
+    }
+
+    /// Kind of the last consumed token
+    pub(crate) fn prev_kind(&self) -> Option<Kind> {
+        self.tokens[..self.index].last().map(|t| t.token.kind)
+    }
+
+    pub(crate) fn bump(&mut self) -> SpannedToken<'a> {
+        let tok = self.tokens[self.index].clone();
+        self.index += 1;
+        self.prev_end = tok.end;
+        tok
+    }
+
+    pub(crate) fn eat_kind(&mut self, kind: Kind) -> bool {
+        if self.at_kind(kind) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn eat_word(&mut self, word: &str) -> bool {
+        if self.at_word(word) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn expect_kind(&mut self, kind: Kind, descr: &str)
+        -> Result<SpannedToken<'a>, ParseError>
+    {
+        if self.at_kind(kind) {
+            Ok(self.bump())
+        } else {
+            Err(self.unexpected(descr))
+        }
+    }
+
+    pub(crate) fn expect_word(&mut self, word: &str)
+        -> Result<SpannedToken<'a>, ParseError>
+    {
+        if self.at_word(word) {
+            Ok(self.bump())
+        } else {
+            Err(self.unexpected(&format!("`{}`", word.to_uppercase())))
+        }
+    }
+
+    pub(crate) fn error_at(&self, message: String, start: Pos, end: Pos)
+        -> ParseError
+    {
+        ParseError { message, start, end }
+    }
+
+    /// Error for the next token, `expected` describes what should be there
+    pub(crate) fn unexpected(&self, expected: &str) -> ParseError {
+        match self.peek() {
+            Some(tok) => ParseError {
+                message: format!("Unexpected {:?}, expected {}",
+                    tok.token.value, expected),
+                start: tok.start,
+                end: tok.end,
+            },
+            None => ParseError {
+                message: format!("Unexpected end of input, expected {}",
+                    expected),
+                start: self.eof,
+                end: self.eof,
+            },
+        }
+    }
+
+    /// Runs `f` one nesting level deeper, fails if nesting is too deep
+    pub(crate) fn nested<T, F>(&mut self, f: F) -> Result<T, ParseError>
+        where F: FnOnce(&mut Parser<'a>) -> Result<T, ParseError>
+    {
+        if self.depth >= MAX_DEPTH {
+            let (start, end) = self.peek().map(|t| (t.start, t.end))
+                .unwrap_or((self.eof, self.eof));
+            return Err(self.error_at(
+                format!("Nesting is deeper than {} levels", MAX_DEPTH),
+                start, end));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Identifier, including unreserved keywords
+    pub(crate) fn ident(&mut self) -> Result<String, ParseError> {
+        match self.peek_kind_at(0) {
+            Some(Kind::Ident) => Ok(self.bump().token.value.to_string()),
+            Some(Kind::BacktickName) => {
+                Ok(unquote_backtick(self.bump().token.value))
+            }
+            _ => Err(self.unexpected("identifier")),
+        }
+    }
+
+    /// Identifier or any keyword except `__dunder__` ones
+    pub(crate) fn any_ident(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(tok) if tok.token.kind == Kind::Keyword => {
+                if is_dunder(tok.token.value) {
+                    return Err(self.error_at(
+                        "identifiers surrounded by double underscores \
+                         are forbidden".into(),
+                        tok.start, tok.end));
+                }
+                Ok(self.bump().token.value.to_string())
+            }
+            _ => self.ident(),
+        }
+    }
+
+    pub(crate) fn node_name(&mut self) -> Result<ObjectRef, ParseError> {
+        let start = self.next_pos();
+        let (module, name) = if self.at_word("__std__") {
+            self.bump();
+            self.expect_kind(Kind::Namespace, "`::`")?;
+            (Some("__std__".to_string()), self.any_ident()?)
+        } else {
+            let first = self.ident()?;
+            if self.eat_kind(Kind::Namespace) {
+                (Some(first), self.any_ident()?)
+            } else {
+                (None, first)
+            }
+        };
+        Ok(ObjectRef { module, name, start, end: self.prev_end })
+    }
+
+    pub(crate) fn module_name(&mut self) -> Result<String, ParseError> {
+        let mut name = self.any_ident()?;
+        while self.eat_kind(Kind::Dot) {
+            name.push('.');
+            name.push_str(&self.any_ident()?);
+        }
+        Ok(name)
+    }
+
+    fn at_statement_start(&self) -> bool {
+        match self.peek() {
+            Some(tok) if tok.token.kind == Kind::Keyword => {
+                ["select", "group", "insert", "update", "delete", "for",
+                 "with"]
+                .iter().any(|w| tok.token.value.eq_ignore_ascii_case(w))
+            }
+            _ => false,
+        }
+    }
+
+    fn with_block(&mut self) -> Result<Vec<AliasDecl>, ParseError> {
+        let mut aliases = Vec::new();
+        loop {
+            let start = self.next_pos();
+            if self.eat_word("module") {
+                let module = self.module_name()?;
+                aliases.push(AliasDecl::Module {
+                    alias: None,
+                    module,
+                    start, end: self.prev_end,
+                });
+            } else if self.at_ident() && self.at_word_at(1, "as")
+                && self.at_word_at(2, "module")
+            {
+                let alias = self.ident()?;
+                self.bump();
+                self.bump();
+                let module = self.module_name()?;
+                aliases.push(AliasDecl::Module {
+                    alias: Some(alias),
+                    module,
+                    start, end: self.prev_end,
+                });
+            } else {
+                aliases.push(AliasDecl::Expr(self.aliased_expr()?));
+            }
+            if !self.eat_kind(Kind::Comma) || self.at_statement_start() {
+                break;
+            }
+        }
+        Ok(aliases)
+    }
+
+    fn aliased_expr(&mut self) -> Result<AliasedExpr, ParseError> {
+        let start = self.next_pos();
+        let alias = self.ident()?;
+        self.expect_kind(Kind::Assign, "`:=`")?;
+        let expr = self.expr()?;
+        Ok(AliasedExpr { alias, expr, start, end: self.prev_end })
+    }
+
+    fn opt_aliased_expr(&mut self)
+        -> Result<(Option<String>, Expr), ParseError>
+    {
+        if self.at_ident() && self.peek_kind_at(1) == Some(Kind::Assign) {
+            let alias = self.ident()?;
+            self.bump();
+            Ok((Some(alias), self.expr()?))
+        } else {
+            Ok((None, self.expr()?))
+        }
+    }
+
+    pub(crate) fn opt_filter(&mut self) -> Result<Option<Expr>, ParseError> {
+        if self.eat_word("filter") {
+            Ok(Some(self.expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(crate) fn opt_sort(&mut self) -> Result<Vec<SortExpr>, ParseError> {
+        let mut result = Vec::new();
+        if !self.eat_word("order") {
+            return Ok(result);
+        }
+        self.expect_word("by")?;
+        loop {
+            let start = self.next_pos();
+            let path = self.expr()?;
+            let direction = if self.eat_word("asc") {
+                SortDirection::Asc
+            } else if self.eat_word("desc") {
+                SortDirection::Desc
+            } else {
+                SortDirection::Default
+            };
+            let nones_order = if self.eat_word("empty") {
+                if self.eat_word("first") {
+                    Some(NonesOrder::First)
+                } else if self.eat_word("last") {
+                    Some(NonesOrder::Last)
+                } else {
+                    return Err(self.unexpected("`FIRST` or `LAST`"));
+                }
+            } else {
+                None
+            };
+            result.push(SortExpr {
+                path, direction, nones_order,
+                start, end: self.prev_end,
+            });
+            if !self.eat_word("then") {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    pub(crate) fn opt_limit(&mut self)
+        -> Result<(Option<Expr>, Option<Expr>), ParseError>
+    {
+        let offset = if self.eat_word("offset") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let limit = if self.eat_word("limit") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        Ok((offset, limit))
+    }
+
+    fn select(&mut self, start: Pos, aliases: Vec<AliasDecl>)
+        -> Result<Statement, ParseError>
+    {
+        self.expect_word("select")?;
+        let (result_alias, result) = self.opt_aliased_expr()?;
+        let filter = self.opt_filter()?;
+        let order_by = self.opt_sort()?;
+        let (offset, limit) = self.opt_limit()?;
+        Ok(Statement::Select(SelectQuery {
+            aliases, result_alias, result, filter, order_by, offset, limit,
+            start, end: self.prev_end,
+        }))
+    }
+
+    fn group(&mut self, start: Pos, aliases: Vec<AliasDecl>)
+        -> Result<Statement, ParseError>
+    {
+        self.expect_word("group")?;
+        let (subject_alias, subject) = self.opt_aliased_expr()?;
+        self.expect_word("using")?;
+        let mut using = vec![self.aliased_expr()?];
+        while self.eat_kind(Kind::Comma) {
+            using.push(self.aliased_expr()?);
+        }
+        self.expect_word("by")?;
+        let mut by = Vec::new();
+        loop {
+            let start = self.next_pos();
+            let name = self.ident()?;
+            let end = self.prev_end;
+            by.push(Expr {
+                kind: ExprKind::Path(Path {
+                    steps: vec![PathStep::ObjectRef(ObjectRef {
+                        module: None, name, start, end,
+                    })],
+                    partial: false,
+                }),
+                start, end,
+            });
+            if !self.eat_kind(Kind::Comma) {
+                break;
+            }
+        }
+        self.expect_word("into")?;
+        let into = self.ident()?;
+        self.expect_word("union")?;
+        let (result_alias, result) = self.opt_aliased_expr()?;
+        let filter = self.opt_filter()?;
+        let order_by = self.opt_sort()?;
+        let (offset, limit) = self.opt_limit()?;
+        Ok(Statement::Group(GroupQuery {
+            aliases, subject_alias, subject, using, by, into,
+            result_alias, result, filter, order_by, offset, limit,
+            start, end: self.prev_end,
+        }))
+    }
+
+    fn insert(&mut self, start: Pos, aliases: Vec<AliasDecl>)
+        -> Result<Statement, ParseError>
+    {
+        self.expect_word("insert")?;
+        let (subject_alias, subject) = self.opt_aliased_expr()?;
+        let (subject, shape) = match subject.kind {
+            ExprKind::Shape(Shape { expr, elements }) => match expr.kind {
+                ExprKind::Path(path) => (path, elements),
+                _ => return Err(self.error_at(
+                    "insert expression must be an object type reference"
+                    .into(), subject.start, subject.end)),
+            },
+            ExprKind::Path(path) => (path, Vec::new()),
+            _ => return Err(self.error_at(
+                "insert expression must be an object type reference".into(),
+                subject.start, subject.end)),
+        };
+        Ok(Statement::Insert(InsertQuery {
+            aliases, subject_alias, subject, shape,
+            start, end: self.prev_end,
+        }))
+    }
+
+    fn update(&mut self, start: Pos, aliases: Vec<AliasDecl>)
+        -> Result<Statement, ParseError>
+    {
+        self.expect_word("update")?;
+        let (subject_alias, subject) = self.opt_aliased_expr()?;
+        let filter = self.opt_filter()?;
+        self.expect_word("set")?;
+        let shape = self.shape()?;
+        Ok(Statement::Update(UpdateQuery {
+            aliases, subject_alias, subject, filter, shape,
+            start, end: self.prev_end,
+        }))
+    }
+
+    fn delete(&mut self, start: Pos, aliases: Vec<AliasDecl>)
+        -> Result<Statement, ParseError>
+    {
+        self.expect_word("delete")?;
+        let (subject_alias, subject) = self.opt_aliased_expr()?;
+        let filter = self.opt_filter()?;
+        let order_by = self.opt_sort()?;
+        let (offset, limit) = self.opt_limit()?;
+        Ok(Statement::Delete(DeleteQuery {
+            aliases, subject_alias, subject, filter, order_by, offset, limit,
+            start, end: self.prev_end,
+        }))
+    }
+
+    fn for_query(&mut self, start: Pos, aliases: Vec<AliasDecl>)
+        -> Result<Statement, ParseError>
+    {
+        self.expect_word("for")?;
+        let iterator_alias = self.ident()?;
+        self.expect_word("in")?;
+        if !self.at_kind(Kind::OpenBrace) {
+            return Err(self.unexpected("`{`"));
+        }
+        let iterator = self.primary()?;
+        self.expect_word("union")?;
+        let (result_alias, result) = self.opt_aliased_expr()?;
+        Ok(Statement::For(ForQuery {
+            aliases, iterator_alias, iterator, result_alias, result,
+            start, end: self.prev_end,
+        }))
+    }
+
+    fn peek_infix(&self) -> Option<(Infix, u8, Associativity, usize)> {
+        use BinaryOperator::*;
+        use Infix::*;
+        use Associativity::*;
+
+        let tok = self.peek()?;
+        let res = match tok.token.kind {
+            Kind::Keyword => {
+                let word = tok.token.value.to_ascii_lowercase();
+                match &word[..] {
+                    "union" => (Binary(Union), PREC_UNION, Left, 1),
+                    "if" => (IfElse, PREC_IFELSE, Right, 1),
+                    "or" => (Binary(Or), PREC_OR, Left, 1),
+                    "and" => (Binary(And), PREC_AND, Left, 1),
+                    "like" => (Binary(Like), PREC_LIKE, NonAssoc, 1),
+                    "ilike" => (Binary(ILike), PREC_LIKE, NonAssoc, 1),
+                    "in" => (Binary(In), PREC_IN, NonAssoc, 1),
+                    "is" if self.at_word_at(1, "not")
+                    => (Is { negated: true }, PREC_IS, NonAssoc, 2),
+                    "is" => (Is { negated: false }, PREC_IS, NonAssoc, 1),
+                    "not" if self.at_word_at(1, "like")
+                    => (Binary(NotLike), PREC_LIKE, NonAssoc, 2),
+                    "not" if self.at_word_at(1, "ilike")
+                    => (Binary(NotILike), PREC_LIKE, NonAssoc, 2),
+                    "not" if self.at_word_at(1, "in")
+                    => (Binary(NotIn), PREC_IN, NonAssoc, 2),
+                    _ => return None,
+                }
+            }
+            Kind::Eq => (Binary(Eq), PREC_EQUALS, Right, 1),
+            Kind::Less => (Binary(Less), PREC_ANGBRACKET, NonAssoc, 1),
+            Kind::Greater
+            => (Binary(Greater), PREC_ANGBRACKET, NonAssoc, 1),
+            Kind::LessEq => (Binary(LessEq), PREC_OP, Left, 1),
+            Kind::GreaterEq => (Binary(GreaterEq), PREC_OP, Left, 1),
+            Kind::NotEq => (Binary(NotEq), PREC_OP, Left, 1),
+            Kind::NotDistinctFrom
+            => (Binary(NotDistinctFrom), PREC_OP, Left, 1),
+            Kind::DistinctFrom
+            => (Binary(DistinctFrom), PREC_OP, Left, 1),
+            Kind::Add => (Binary(Add), PREC_ADD, Left, 1),
+            Kind::Sub => (Binary(Sub), PREC_ADD, Left, 1),
+            Kind::Concat => (Binary(Concat), PREC_ADD, Left, 1),
+            Kind::Mul => (Binary(Mul), PREC_MUL, Left, 1),
+            Kind::Div => (Binary(Div), PREC_MUL, Left, 1),
+            Kind::FloorDiv => (Binary(FloorDiv), PREC_MUL, Left, 1),
+            Kind::Modulo => (Binary(Modulo), PREC_MUL, Left, 1),
+            Kind::Coalesce
+            => (Binary(Coalesce), PREC_COALESCE, Right, 1),
+            Kind::Pow => (Binary(Pow), PREC_POW, Right, 1),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    fn expr_bp(&mut self, min_prec: u8) -> Result<Expr, ParseError> {
+        self.nested(|p| p.binary_expr(min_prec))
+    }
+
+    fn binary_expr(&mut self, min_prec: u8) -> Result<Expr, ParseError> {
+        let mut left = self.unary_expr()?;
+        loop {
+            let (op, prec, assoc, ntokens) = match self.peek_infix() {
+                Some(infix) if infix.1 >= min_prec => infix,
+                _ => break,
+            };
+            for _ in 0..ntokens {
+                self.bump();
+            }
+            let next_prec = match assoc {
+                Associativity::Right => prec,
+                Associativity::Left | Associativity::NonAssoc => prec + 1,
+            };
+            let start = left.start;
+            left = match op {
+                Infix::Binary(op) => {
+                    let right = self.expr_bp(next_prec)?;
+                    Expr {
+                        start,
+                        end: right.end,
+                        kind: ExprKind::BinOp {
+                            left: Box::new(left),
+                            op,
+                            right: Box::new(right),
+                        },
+                    }
+                }
+                Infix::Is { negated } => {
+                    let right = self.type_expr(false)?;
+                    Expr {
+                        start,
+                        end: right.end,
+                        kind: ExprKind::IsOp {
+                            left: Box::new(left),
+                            negated,
+                            right,
+                        },
+                    }
+                }
+                Infix::IfElse => {
+                    let condition = self.expr()?;
+                    self.expect_word("else")?;
+                    let else_expr = self.expr_bp(next_prec)?;
+                    Expr {
+                        start,
+                        end: else_expr.end,
+                        kind: ExprKind::IfElse {
+                            condition: Box::new(condition),
+                            if_expr: Box::new(left),
+                            else_expr: Box::new(else_expr),
+                        },
+                    }
+                }
+            };
+            if assoc == Associativity::NonAssoc {
+                if let Some((_, next, _, _)) = self.peek_infix() {
+                    if next == prec {
+                        return Err(self.unexpected("end of expression"));
+                    }
+                }
+            }
+        }
+        Ok(left)
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, ParseError> {
+        let tok = match self.peek() {
+            Some(tok) => tok.clone(),
+            None => return Err(self.unexpected("expression")),
+        };
+        let start = tok.start;
+        let word = if tok.token.kind == Kind::Keyword {
+            tok.token.value.to_ascii_lowercase()
+        } else {
+            String::new()
+        };
+        let unary = match (tok.token.kind, &word[..]) {
+            (Kind::Add, _) => Some((UnaryOperator::Plus, PREC_UNARY)),
+            (Kind::Sub, _) => Some((UnaryOperator::Minus, PREC_UNARY)),
+            (Kind::Keyword, "not") => Some((UnaryOperator::Not, PREC_NOT)),
+            (Kind::Keyword, "exists")
+            => Some((UnaryOperator::Exists, PREC_UNARY)),
+            (Kind::Keyword, "distinct")
+            => Some((UnaryOperator::Distinct, PREC_UNARY)),
+            _ => None,
+        };
+        if let Some((op, prec)) = unary {
+            self.bump();
+            let operand = self.expr_bp(prec)?;
+            return Ok(Expr {
+                start,
+                end: operand.end,
+                kind: ExprKind::UnaryOp { op, operand: Box::new(operand) },
+            });
+        }
+        match (tok.token.kind, &word[..]) {
+            (Kind::Less, _) => {
+                self.bump();
+                let cardinality_mod = if self.eat_word("optional") {
+                    Some(CardinalityModifier::Optional)
+                } else if self.eat_word("required") {
+                    Some(CardinalityModifier::Required)
+                } else {
+                    None
+                };
+                let type_ = self.type_expr(true)?;
+                self.expect_kind(Kind::Greater, "`>`")?;
+                let expr = self.expr_bp(PREC_CAST)?;
+                Ok(Expr {
+                    start,
+                    end: expr.end,
+                    kind: ExprKind::TypeCast {
+                        type_,
+                        cardinality_mod,
+                        expr: Box::new(expr),
+                    },
+                })
+            }
+            (Kind::Keyword, "detached") => {
+                let expr = self.detached()?;
+                self.postfix(expr)
+            }
+            (Kind::Keyword, "introspect") => {
+                self.bump();
+                let type_ = self.type_expr(false)?;
+                Ok(Expr {
+                    start,
+                    end: type_.end,
+                    kind: ExprKind::Introspect(type_),
+                })
+            }
+            _ => {
+                let expr = self.primary()?;
+                self.postfix(expr)
+            }
+        }
+    }
+
+    /// `DETACHED` binds tighter than path steps and shapes
+    fn detached(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect_word("detached")?.start;
+        let expr = if self.at_word("detached") {
+            self.nested(|p| p.detached())?
+        } else {
+            self.primary()?
+        };
+        Ok(Expr {
+            start,
+            end: expr.end,
+            kind: ExprKind::Detached(Box::new(expr)),
+        })
+    }
+
+    fn constant(&self, tok: &SpannedToken) -> Result<Constant, ParseError> {
+        let value = tok.token.value;
+        let res = match tok.token.kind {
+            Kind::IntConst => Constant::Int(value.into()),
+            Kind::FloatConst => Constant::Float(value.into()),
+            Kind::BigIntConst => {
+                Constant::BigInt(value[..value.len()-1].into())
+            }
+            Kind::DecimalConst => {
+                Constant::Decimal(value[..value.len()-1].into())
+            }
+            Kind::Str => Constant::Str(unquote_string(value)
+                .map_err(|e| self.error_at(e.to_string(),
+                                           tok.start, tok.end))?
+                .into()),
+            Kind::BinStr => Constant::Bytes(unquote_bytes(value)
+                .map_err(|e| self.error_at(e.to_string(),
+                                           tok.start, tok.end))?),
+            _ => unreachable!("constant kind"),
+        };
+        Ok(res)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let tok = match self.peek() {
+            Some(tok) => tok.clone(),
+            None => return Err(self.unexpected("expression")),
+        };
+        let start = tok.start;
+        let kind = match tok.token.kind {
+            Kind::IntConst | Kind::FloatConst | Kind::BigIntConst
+            | Kind::DecimalConst | Kind::Str | Kind::BinStr
+            => {
+                self.bump();
+                ExprKind::Constant(self.constant(&tok)?)
+            }
+            Kind::Argument => {
+                self.bump();
+                let value = tok.token.value;
+                if value[1..].starts_with('`') {
+                    ExprKind::Parameter(unquote_backtick(&value[1..]))
+                } else {
+                    ExprKind::Parameter(value[1..].into())
+                }
+            }
+            Kind::OpenParen => return self.paren_expr(),
+            Kind::OpenBracket => {
+                self.bump();
+                let elements = self.expr_list(Kind::CloseBracket)?;
+                self.expect_kind(Kind::CloseBracket, "`]`")?;
+                ExprKind::Array(elements)
+            }
+            Kind::OpenBrace => {
+                self.bump();
+                let elements = self.expr_list(Kind::CloseBrace)?;
+                self.expect_kind(Kind::CloseBrace, "`}`")?;
+                ExprKind::Set(elements)
+            }
+            Kind::Dot | Kind::ForwardLink | Kind::BackwardLink | Kind::At => {
+                let step = self.path_step()?;
+                ExprKind::Path(Path { steps: vec![step], partial: true })
+            }
+            Kind::Ident | Kind::BacktickName => return self.name_expr(),
+            Kind::Keyword => {
+                let word = tok.token.value.to_ascii_lowercase();
+                match &word[..] {
+                    "true" | "false" => {
+                        self.bump();
+                        ExprKind::Constant(Constant::Bool(word == "true"))
+                    }
+                    "__source__" => {
+                        self.bump();
+                        ExprKind::Path(Path {
+                            steps: vec![PathStep::Source {
+                                start, end: tok.end,
+                            }],
+                            partial: false,
+                        })
+                    }
+                    "__subject__" => {
+                        self.bump();
+                        ExprKind::Path(Path {
+                            steps: vec![PathStep::Subject {
+                                start, end: tok.end,
+                            }],
+                            partial: false,
+                        })
+                    }
+                    "__std__" => return self.name_expr(),
+                    _ => return Err(self.unexpected("expression")),
+                }
+            }
+            _ => return Err(self.unexpected("expression")),
+        };
+        Ok(Expr { kind, start, end: self.prev_end })
+    }
+
+    pub(crate) fn expr_list(&mut self, close: Kind) -> Result<Vec<Expr>, ParseError> {
+        let mut elements = Vec::new();
+        while !self.at_kind(close) {
+            elements.push(self.expr()?);
+            if !self.eat_kind(Kind::Comma) {
+                break;
+            }
+        }
+        Ok(elements)
+    }
+
+    pub(crate) fn paren_expr(&mut self) -> Result<Expr, ParseError> {
+        let start = self.expect_kind(Kind::OpenParen, "`(`")?.start;
+        if self.eat_kind(Kind::CloseParen) {
+            return Ok(Expr {
+                kind: ExprKind::Tuple(Vec::new()),
+                start, end: self.prev_end,
+            });
+        }
+        if self.at_statement_start() {
+            let stmt = self.statement()?;
+            self.expect_kind(Kind::CloseParen, "`)`")?;
+            return Ok(Expr {
+                kind: ExprKind::Statement(Box::new(stmt)),
+                start, end: self.prev_end,
+            });
+        }
+        if self.at_ident() && self.peek_kind_at(1) == Some(Kind::Assign) {
+            let mut elements = Vec::new();
+            while !self.at_kind(Kind::CloseParen) {
+                let el_start = self.next_pos();
+                let name = self.ident()?;
+                self.expect_kind(Kind::Assign, "`:=`")?;
+                let value = self.expr()?;
+                elements.push(TupleElement {
+                    name, value,
+                    start: el_start, end: self.prev_end,
+                });
+                if !self.eat_kind(Kind::Comma) {
+                    break;
+                }
+            }
+            self.expect_kind(Kind::CloseParen, "`)`")?;
+            return Ok(Expr {
+                kind: ExprKind::NamedTuple(elements),
+                start, end: self.prev_end,
+            });
+        }
+        let first = self.expr()?;
+        if self.eat_kind(Kind::Comma) {
+            let mut elements = vec![first];
+            elements.extend(self.expr_list(Kind::CloseParen)?);
+            self.expect_kind(Kind::CloseParen, "`)`")?;
+            return Ok(Expr {
+                kind: ExprKind::Tuple(elements),
+                start, end: self.prev_end,
+            });
+        }
+        self.expect_kind(Kind::CloseParen, "`)`")?;
+        Ok(first)
+    }
+
+    fn name_expr(&mut self) -> Result<Expr, ParseError> {
+        let name = self.node_name()?;
+        let start = name.start;
+        if self.at_kind(Kind::OpenParen) {
+            let call = self.func_call(name)?;
+            return Ok(Expr {
+                kind: ExprKind::FunctionCall(call),
+                start, end: self.prev_end,
+            });
+        }
+        let end = name.end;
+        Ok(Expr {
+            kind: ExprKind::Path(Path {
+                steps: vec![PathStep::ObjectRef(name)],
+                partial: false,
+            }),
+            start, end,
+        })
+    }
+
+    fn func_call(&mut self, func: ObjectRef)
+        -> Result<FunctionCall, ParseError>
+    {
+        self.expect_kind(Kind::OpenParen, "`(`")?;
+        let mut args = Vec::new();
+        let mut kwargs: Vec<(String, FuncArg)> = Vec::new();
+        while !self.at_kind(Kind::CloseParen) {
+            let start = self.next_pos();
+            let name = if self.peek_kind_at(1) == Some(Kind::Assign) {
+                match self.peek_kind_at(0) {
+                    Some(Kind::Ident) | Some(Kind::BacktickName)
+                    | Some(Kind::Keyword) => {
+                        let name = self.any_ident()?;
+                        self.bump();
+                        Some(name)
+                    }
+                    Some(Kind::Argument) => {
+                        let tok = self.bump();
+                        return Err(self.error_at(format!(
+                            "named arguments do not need a '$' prefix, \
+                             rewrite as '{} := ...'", &tok.token.value[1..]),
+                            tok.start, tok.end));
+                    }
+                    _ => None,
+                }
+            } else {
+                None
+            };
+            let value = self.expr()?;
+            let filter = self.opt_filter()?;
+            let order_by = self.opt_sort()?;
+            let end = self.prev_end;
+            let arg = FuncArg { value, filter, order_by, start, end };
+            match name {
+                Some(name) => {
+                    if kwargs.iter().any(|(n, _)| n == &name) {
+                        return Err(self.error_at(
+                            format!("duplicate named argument `{}`", name),
+                            start, end));
+                    }
+                    kwargs.push((name, arg));
+                }
+                None => {
+                    if let Some((last, _)) = kwargs.last() {
+                        return Err(self.error_at(
+                            format!("positional argument after named \
+                                     argument `{}`", last),
+                            start, end));
+                    }
+                    args.push(arg);
+                }
+            }
+            if !self.eat_kind(Kind::Comma) {
+                break;
+            }
+        }
+        self.expect_kind(Kind::CloseParen, "`)`")?;
+        Ok(FunctionCall { func, args, kwargs })
+    }
+
+    fn postfix(&mut self, mut expr: Expr) -> Result<Expr, ParseError> {
+        loop {
+            match self.peek_kind_at(0) {
+                Some(Kind::Dot) | Some(Kind::ForwardLink)
+                | Some(Kind::BackwardLink) | Some(Kind::At)
+                => {
+                    let step = self.path_step()?;
+                    expr = append_step(expr, step, self.prev_end);
+                }
+                Some(Kind::OpenBracket) if self.at_word_at(1, "is") => {
+                    let step = self.type_intersection()?;
+                    expr = append_step(expr, step, self.prev_end);
+                }
+                Some(Kind::OpenBracket) => {
+                    let item = self.indirection()?;
+                    expr = append_indirection(expr, item, self.prev_end);
+                }
+                Some(Kind::OpenBrace) => {
+                    let elements = self.shape()?;
+                    expr = Expr {
+                        start: expr.start,
+                        end: self.prev_end,
+                        kind: ExprKind::Shape(Shape {
+                            expr: Box::new(expr),
+                            elements,
+                        }),
+                    };
+                }
+                _ => break,
+            }
+        }
+        Ok(expr)
+    }
+
+    fn path_step_name(&mut self) -> Result<String, ParseError> {
+        if self.at_word("__type__") {
+            self.bump();
+            return Ok("__type__".into());
+        }
+        self.ident()
+    }
+
+    fn path_step(&mut self) -> Result<PathStep, ParseError> {
+        let tok = self.bump();
+        let (name, direction, property) = match tok.token.kind {
+            Kind::Dot if self.at_kind(Kind::IntConst) => {
+                // tuple element access: `.0`
+                let name = self.bump().token.value.to_string();
+                (name, PointerDirection::Outbound, false)
+            }
+            Kind::Dot | Kind::ForwardLink => {
+                (self.path_step_name()?, PointerDirection::Outbound, false)
+            }
+            Kind::BackwardLink => {
+                (self.path_step_name()?, PointerDirection::Inbound, false)
+            }
+            Kind::At => (self.ident()?, PointerDirection::Outbound, true),
+            _ => unreachable!("path step token"),
+        };
+        Ok(PathStep::Ptr(Ptr {
+            name, direction, property,
+            start: tok.start, end: self.prev_end,
+        }))
+    }
+
+    fn type_intersection(&mut self) -> Result<PathStep, ParseError> {
+        self.expect_kind(Kind::OpenBracket, "`[`")?;
+        self.expect_word("is")?;
+        let typ = self.type_expr(true)?;
+        self.expect_kind(Kind::CloseBracket, "`]`")?;
+        Ok(PathStep::TypeIntersection(typ))
+    }
+
+    fn indirection(&mut self) -> Result<Indirection, ParseError> {
+        self.expect_kind(Kind::OpenBracket, "`[`")?;
+        if self.eat_kind(Kind::Colon) {
+            let stop = self.expr()?;
+            self.expect_kind(Kind::CloseBracket, "`]`")?;
+            return Ok(Indirection::Slice { start: None, stop: Some(stop) });
+        }
+        let first = self.expr()?;
+        if self.eat_kind(Kind::Colon) {
+            if self.eat_kind(Kind::CloseBracket) {
+                return Ok(Indirection::Slice {
+                    start: Some(first),
+                    stop: None,
+                });
+            }
+            let stop = self.expr()?;
+            self.expect_kind(Kind::CloseBracket, "`]`")?;
+            return Ok(Indirection::Slice {
+                start: Some(first),
+                stop: Some(stop),
+            });
+        }
+        self.expect_kind(Kind::CloseBracket, "`]`")?;
+        Ok(Indirection::Index(first))
+    }
+
+    pub(crate) fn shape(&mut self) -> Result<Vec<ShapeElement>, ParseError> {
+        self.expect_kind(Kind::OpenBrace, "`{`")?;
+        let mut elements = Vec::new();
+        while !self.at_kind(Kind::CloseBrace) {
+            elements.push(self.nested(|p| p.shape_element())?);
+            if !self.eat_kind(Kind::Comma) {
+                break;
+            }
+        }
+        self.expect_kind(Kind::CloseBrace, "`}`")?;
+        Ok(elements)
+    }
+
+    /// Qualifier words are only qualifiers if followed by a pointer name
+    fn at_qualifier(&self, word: &str) -> bool {
+        self.at_word(word) && matches!(self.peek_kind_at(1),
+            Some(Kind::Ident) | Some(Kind::BacktickName) | Some(Kind::At))
+    }
+
+    fn shape_element(&mut self) -> Result<ShapeElement, ParseError> {
+        let start = self.next_pos();
+        let mut required = None;
+        let mut cardinality = None;
+        if self.at_word("optional") {
+            self.bump();
+            required = Some(false);
+        } else if self.at_qualifier("required") {
+            self.bump();
+            required = Some(true);
+        }
+        if self.at_qualifier("single") {
+            self.bump();
+            cardinality = Some(Cardinality::One);
+        } else if self.at_qualifier("multi") {
+            self.bump();
+            cardinality = Some(Cardinality::Many);
+        }
+        let expr = self.shape_path()?;
+        let operation = match self.peek_kind_at(0) {
+            Some(Kind::Assign) => Some(ShapeOp::Assign),
+            Some(Kind::AddAssign) => Some(ShapeOp::Append),
+            Some(Kind::SubAssign) => Some(ShapeOp::Subtract),
+            _ => None,
+        };
+        let qualified = required.is_some() || cardinality.is_some();
+        if let Some(operation) = operation {
+            if qualified && operation != ShapeOp::Assign {
+                return Err(self.unexpected("`:=`"));
+            }
+            self.bump();
+            let compexpr = self.expr()?;
+            return Ok(ShapeElement {
+                expr,
+                elements: Vec::new(),
+                filter: None,
+                order_by: Vec::new(),
+                offset: None,
+                limit: None,
+                compexpr: Some(compexpr),
+                operation: Some(operation),
+                required,
+                cardinality,
+                start, end: self.prev_end,
+            });
+        }
+        if qualified {
+            return Err(self.unexpected("`:=`"));
+        }
+        let elements = if self.eat_kind(Kind::Colon) {
+            self.shape()?
+        } else if self.at_kind(Kind::OpenBrace) {
+            return Err(self.unexpected("`:` before `{` in a sub-shape"));
+        } else {
+            Vec::new()
+        };
+        let filter = self.opt_filter()?;
+        let order_by = self.opt_sort()?;
+        let (offset, limit) = self.opt_limit()?;
+        Ok(ShapeElement {
+            expr, elements, filter, order_by, offset, limit,
+            compexpr: None,
+            operation: None,
+            required: None,
+            cardinality: None,
+            start, end: self.prev_end,
+        })
+    }
+
+    fn shape_path(&mut self) -> Result<Path, ParseError> {
+        let start = self.next_pos();
+        let mut steps = Vec::new();
+        if self.eat_kind(Kind::At) {
+            let name = self.ident()?;
+            steps.push(PathStep::Ptr(Ptr {
+                name,
+                direction: PointerDirection::Outbound,
+                property: true,
+                start, end: self.prev_end,
+            }));
+            return Ok(Path { steps, partial: false });
+        }
+        if self.at_kind(Kind::OpenBracket) {
+            steps.push(self.type_intersection()?);
+            self.expect_kind(Kind::Dot, "`.`")?;
+        }
+        let ptr_start = self.next_pos();
+        let name = self.path_step_name()?;
+        steps.push(PathStep::Ptr(Ptr {
+            name,
+            direction: PointerDirection::Outbound,
+            property: false,
+            start: ptr_start, end: self.prev_end,
+        }));
+        if self.at_kind(Kind::OpenBracket) && self.at_word_at(1, "is") {
+            steps.push(self.type_intersection()?);
+        }
+        Ok(Path { steps, partial: false })
+    }
+
+    /// Type expression, `full` allows collection types with subtypes
+    pub(crate) fn type_expr(&mut self, full: bool)
+        -> Result<TypeExpr, ParseError>
+    {
+        let mut left = self.type_and(full)?;
+        while self.eat_kind(Kind::Pipe) {
+            let right = self.type_and(full)?;
+            left = TypeExpr {
+                start: left.start,
+                end: right.end,
+                kind: TypeExprKind::TypeOp {
+                    left: Box::new(left),
+                    op: TypeOperator::Or,
+                    right: Box::new(right),
+                },
+            };
+        }
+        Ok(left)
+    }
+
+    fn type_and(&mut self, full: bool) -> Result<TypeExpr, ParseError> {
+        let mut left = self.type_atom(full)?;
+        while self.eat_kind(Kind::Ampersand) {
+            let right = self.type_atom(full)?;
+            left = TypeExpr {
+                start: left.start,
+                end: right.end,
+                kind: TypeExprKind::TypeOp {
+                    left: Box::new(left),
+                    op: TypeOperator::And,
+                    right: Box::new(right),
+                },
+            };
+        }
+        Ok(left)
+    }
+
+    fn type_atom(&mut self, full: bool) -> Result<TypeExpr, ParseError> {
+        let start = self.next_pos();
+        if self.eat_word("typeof") {
+            let expr = self.expr_bp(PREC_UNARY)?;
+            return Ok(TypeExpr {
+                kind: TypeExprKind::TypeOf(Box::new(expr)),
+                start, end: self.prev_end,
+            });
+        }
+        if self.eat_kind(Kind::OpenParen) {
+            let typ = self.nested(|p| p.type_expr(true))?;
+            self.expect_kind(Kind::CloseParen, "`)`")?;
+            return Ok(typ);
+        }
+        let maintype = if self.at_word("anytype") || self.at_word("anytuple") {
+            let tok = self.bump();
+            ObjectRef {
+                module: None,
+                name: tok.token.value.to_ascii_lowercase(),
+                start: tok.start,
+                end: tok.end,
+            }
+        } else {
+            self.node_name()?
+        };
+        let subtypes = if full && self.eat_kind(Kind::Less) {
+            let mut subtypes = Vec::new();
+            while !self.at_kind(Kind::Greater) {
+                subtypes.push(self.nested(|p| p.subtype())?);
+                if !self.eat_kind(Kind::Comma) {
+                    break;
+                }
+            }
+            self.expect_kind(Kind::Greater, "`>`")?;
+            Some(subtypes)
+        } else {
+            None
+        };
+        Ok(TypeExpr {
+            kind: TypeExprKind::Name(TypeName {
+                name: None,
+                maintype,
+                subtypes,
+            }),
+            start, end: self.prev_end,
+        })
+    }
+
+    fn subtype(&mut self) -> Result<TypeExpr, ParseError> {
+        if self.at_kind(Kind::Str) {
+            let tok = self.bump();
+            let value = unquote_string(tok.token.value)
+                .map_err(|e| self.error_at(e.to_string(),
+                                           tok.start, tok.end))?;
+            return Ok(TypeExpr {
+                kind: TypeExprKind::Literal(value.into()),
+                start: tok.start,
+                end: tok.end,
+            });
+        }
+        if self.at_ident() && self.peek_kind_at(1) == Some(Kind::Colon) {
+            let start = self.next_pos();
+            let name = self.ident()?;
+            self.bump();
+            let mut typ = self.type_expr(true)?;
+            if let TypeExprKind::Name(ref mut type_name) = typ.kind {
+                type_name.name = Some(name);
+            }
+            typ.start = start;
+            return Ok(typ);
+        }
+        self.type_expr(true)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.start)
+    }
+}
+
+impl Error for ParseError {}
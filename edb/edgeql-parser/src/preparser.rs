@@ -1,25 +1,242 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::str;
+
 use twoway::find_bytes;
 
+use crate::position::Pos;
+
+/// Size of the chunk `StatementReader` reads at once
+const CHUNK_SIZE: usize = 65536;
+
 #[derive(Debug, PartialEq)]
 pub struct Continuation {
     position: usize,
-    braces: Vec<u8>,
+    /// Offset of every opened bracket and the expected closing bracket
+    braces: Vec<(usize, u8)>,
+}
+
+/// Something opened but not closed in an incomplete statement
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Unclosed {
+    /// String literal quoted by `'` or `"`
+    String { quote: char, offset: usize },
+    /// Dollar-quoted string, `marker` is either `$$` or like `$tag$`
+    DollarQuote { marker: String, offset: usize },
+    /// Identifier quoted by backticks
+    Backtick { offset: usize },
+    /// One of the `{`, `(` or `[`
+    Bracket { bracket: char, offset: usize },
+}
+
+/// Pair of brackets found by `brackets`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bracket {
+    /// Offset of the opening bracket, `None` for a stray closing bracket
+    pub open: Option<usize>,
+    /// Offset of the matching closing bracket, `None` if it's not closed
+    pub close: Option<usize>,
+}
+
+/// Reason why `full_statement_checked` has not found the end of the statement
+#[derive(Debug, PartialEq)]
+pub enum StatementError {
+    /// Statement is not complete, continue search when more data arrives
+    Incomplete(Continuation),
+    /// Closing bracket at `close` doesn't match the one opened at `open`
+    ///
+    /// The `open` is `None` if there are no open brackets at all. The
+    /// statement can't become valid whatever data follows.
+    MismatchedBracket { open: Option<usize>, close: usize },
 }
 
 /// Returns index of semicolon, or position where to continue search on new
 /// data
+///
+/// Closing brackets which don't match the opening ones are skipped, so
+/// such statement may never end, use `full_statement_checked` to detect
+/// them.
 pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
     -> Result<usize, Continuation>
 {
-    let mut iter = data.iter().enumerate().peekable();
-    continuation.as_ref().map(|cont| {
-        if cont.position > 0 {
-            iter.nth(cont.position-1);
+    let position = continuation.as_ref().map(|c| c.position).unwrap_or(0);
+    let mut braces_buf = continuation
+        .map(|cont| cont.braces)
+        .unwrap_or_else(|| Vec::with_capacity(8));
+    let result = scan(data, position, |idx, b| {
+        match b {
+            b'{' => braces_buf.push((idx, b'}')),
+            b'(' => braces_buf.push((idx, b')')),
+            b'[' => braces_buf.push((idx, b']')),
+            b'}' | b')' | b']'
+            if braces_buf.last().map(|&(_, c)| c) == Some(b)
+            => { braces_buf.pop(); }
+            b';' if braces_buf.len() == 0 => return true,
+            _ => {}
         }
+        false
     });
+    result.map_err(|position| Continuation { position, braces: braces_buf })
+}
+
+/// Same as `full_statement` but fails on the first closing bracket which
+/// doesn't match the opening one
+pub fn full_statement_checked(data: &[u8],
+    continuation: Option<Continuation>)
+    -> Result<usize, StatementError>
+{
+    let position = continuation.as_ref().map(|c| c.position).unwrap_or(0);
     let mut braces_buf = continuation
         .map(|cont| cont.braces)
         .unwrap_or_else(|| Vec::with_capacity(8));
+    let mut mismatch = None;
+    let result = scan(data, position, |idx, b| {
+        match b {
+            b'{' => braces_buf.push((idx, b'}')),
+            b'(' => braces_buf.push((idx, b')')),
+            b'[' => braces_buf.push((idx, b']')),
+            b'}' | b')' | b']' => match braces_buf.pop() {
+                Some((_, close)) if close == b => {}
+                open => {
+                    mismatch = Some(StatementError::MismatchedBracket {
+                        open: open.map(|(open, _)| open),
+                        close: idx,
+                    });
+                    return true;
+                }
+            },
+            b';' if braces_buf.is_empty() => return true,
+            _ => {}
+        }
+        false
+    });
+    if let Some(err) = mismatch {
+        return Err(err);
+    }
+    result.map_err(|position| {
+        StatementError::Incomplete(Continuation {
+            position,
+            braces: braces_buf,
+        })
+    })
+}
+
+impl Continuation {
+    /// Returns what is left open at the end of the data, outermost first
+    ///
+    /// The `data` must be the same that was passed to `full_statement`
+    /// which returned this continuation. The last element is the innermost
+    /// one, it's useful for the continuation prompt of an interactive
+    /// shell. The list is empty if the statement just lacks a semicolon.
+    pub fn unclosed(&self, data: &[u8]) -> Vec<Unclosed> {
+        let mut result = self.braces.iter()
+            .map(|&(offset, b)| Unclosed::Bracket {
+                bracket: match b {
+                    b'}' => '{',
+                    b')' => '(',
+                    _ => '[',
+                },
+                offset,
+            })
+            .collect::<Vec<_>>();
+        let offset = self.position;
+        let literal = match data.get(offset) {
+            Some(b'\'') => Some(Unclosed::String { quote: '\'', offset }),
+            Some(b'"') => Some(Unclosed::String { quote: '"', offset }),
+            Some(b'`') => Some(Unclosed::Backtick { offset }),
+            Some(b'$') => dollar_marker(&data[offset..])
+                .map(|marker| Unclosed::DollarQuote { marker, offset }),
+            // comments and the end of data
+            _ => None,
+        };
+        result.extend(literal);
+        result
+    }
+}
+
+/// Returns the marker of the dollar-quote at the start of the data
+fn dollar_marker(data: &[u8]) -> Option<String> {
+    let len = data[1..].iter()
+        .position(|b| !matches!(b,
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_'))?;
+    if data[1+len] != b'$' || data[1].is_ascii_digit() {
+        return None;
+    }
+    str::from_utf8(&data[..len+2]).ok().map(String::from)
+}
+
+impl Unclosed {
+    /// Returns the text which opened the element, e.g. `(` or `$tag$`
+    pub fn opening(&self) -> &str {
+        match self {
+            Unclosed::String { quote: '\'', .. } => "'",
+            Unclosed::String { .. } => "\"",
+            Unclosed::DollarQuote { marker, .. } => marker,
+            Unclosed::Backtick { .. } => "`",
+            Unclosed::Bracket { bracket: '{', .. } => "{",
+            Unclosed::Bracket { bracket: '(', .. } => "(",
+            Unclosed::Bracket { .. } => "[",
+        }
+    }
+
+    /// Returns offset of the opening text in the data
+    pub fn offset(&self) -> usize {
+        match *self {
+            Unclosed::String { offset, .. } => offset,
+            Unclosed::DollarQuote { offset, .. } => offset,
+            Unclosed::Backtick { offset } => offset,
+            Unclosed::Bracket { offset, .. } => offset,
+        }
+    }
+}
+
+/// Returns all the brackets in the text with offsets of their pairs
+///
+/// Brackets are sorted by the offset of the opening bracket (or the
+/// closing one if there is no opening). Brackets inside strings and
+/// comments are skipped.
+pub fn brackets(data: &[u8]) -> Vec<Bracket> {
+    let mut result = Vec::new();
+    let mut stack: Vec<(usize, u8)> = Vec::with_capacity(8);
+    // incomplete text is fine, we just report what we've found so far
+    let _ = scan(data, 0, |idx, b| {
+        match b {
+            b'{' => stack.push((idx, b'}')),
+            b'(' => stack.push((idx, b')')),
+            b'[' => stack.push((idx, b']')),
+            b'}' | b')' | b']' => match stack.last() {
+                Some(&(open, close)) if close == b => {
+                    stack.pop();
+                    result.push(Bracket {
+                        open: Some(open),
+                        close: Some(idx),
+                    });
+                }
+                _ => result.push(Bracket { open: None, close: Some(idx) }),
+            },
+            _ => {}
+        }
+        false
+    });
+    result.extend(stack.into_iter()
+        .map(|(open, _)| Bracket { open: Some(open), close: None }));
+    result.sort_by_key(|b| b.open.or(b.close));
+    result
+}
+
+/// Walks the code skipping string literals and comments, and calls
+/// `visit` for every bracket and semicolon
+///
+/// Returns index after the byte for which `visit` returned `true`, or
+/// position where to continue search on new data.
+fn scan<F>(data: &[u8], position: usize, mut visit: F) -> Result<usize, usize>
+    where F: FnMut(usize, u8) -> bool
+{
+    let mut iter = data.iter().enumerate().peekable();
+    if position > 0 {
+        iter.nth(position-1);
+    }
     'outer: while let Some((idx, b)) = iter.next() {
         match b {
             b'"' => {
@@ -33,7 +250,7 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                         _ => continue,
                     }
                 }
-                return Err(Continuation { position: idx, braces: braces_buf });
+                return Err(idx);
             }
             b'\'' => {
                 while let Some((_, b)) = iter.next() {
@@ -46,7 +263,7 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                         _ => continue,
                     }
                 }
-                return Err(Continuation { position: idx, braces: braces_buf });
+                return Err(idx);
             }
             b'`' => {
                 while let Some((_, b)) = iter.next() {
@@ -55,7 +272,7 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                         _ => continue,
                     }
                 }
-                return Err(Continuation { position: idx, braces: braces_buf });
+                return Err(idx);
             }
             b'#' => {
                 while let Some((_, &b)) = iter.next() {
@@ -63,7 +280,7 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                         continue 'outer;
                     }
                 }
-                return Err(Continuation { position: idx, braces: braces_buf });
+                return Err(idx);
             }
             b'$' => {
                 match iter.next() {
@@ -74,10 +291,7 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                             iter.nth(end + end_idx - idx);
                             continue 'outer;
                         }
-                        return Err(Continuation {
-                            position: idx,
-                            braces: braces_buf,
-                        });
+                        return Err(idx);
                     }
                     | Some((_, b'A'..=b'Z'))
                     | Some((_, b'a'..=b'z'))
@@ -85,19 +299,13 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                     => { }
                     // Not a dollar-quote
                     Some((_, _)) => continue 'outer,
-                    None => return Err(Continuation {
-                        position: idx,
-                        braces: braces_buf,
-                    }),
+                    None => return Err(idx),
                 }
                 loop {
                     let (c_idx, c) = if let Some(pair) = iter.peek() {
                         *pair
                     } else {
-                        return Err(Continuation {
-                            position: idx,
-                            braces: braces_buf,
-                        })
+                        return Err(idx)
                     };
                     match c {
                         b'$' => {
@@ -109,10 +317,7 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                                 iter.nth(1 + end + marker_size - 1);
                                 continue 'outer;
                             }
-                            return Err(Continuation {
-                                position: idx,
-                                braces: braces_buf,
-                            });
+                            return Err(idx);
                         }
                         b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' => {},
                         // Not a dollar-quote
@@ -121,17 +326,15 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
                     iter.next();
                 }
             }
-            b'{' => braces_buf.push(b'}'),
-            b'(' => braces_buf.push(b')'),
-            b'[' => braces_buf.push(b']'),
-            b'}' | b')' | b']'
-            if braces_buf.last() == Some(b)
-            => { braces_buf.pop(); }
-            b';' if braces_buf.len() == 0 => return Ok(idx+1),
+            b'{' | b'(' | b'[' | b'}' | b')' | b']' | b';' => {
+                if visit(idx, *b) {
+                    return Ok(idx+1);
+                }
+            }
             _ => continue,
         }
     }
-    return Err(Continuation { position: data.len(), braces: braces_buf });
+    return Err(data.len());
 }
 
 /// Returns true if the text has no partial statements
@@ -143,24 +346,225 @@ pub fn full_statement(data: &[u8], continuation: Option<Continuation>)
 /// `full_statement` contains anything relevant. Before this function we
 /// couldn't add a comment at the end of EdgeQL file.
 pub fn is_empty(text: &str) -> bool {
-    let mut iter = text.chars();
+    trivia_len(text) == text.len()
+}
+
+/// Returns length of whitespace and comments at the start of the text
+fn trivia_len(text: &str) -> usize {
+    let mut iter = text.char_indices();
     loop {
-        let cur_char = match iter.next() {
-            Some(c) => c,
-            None => return true,
+        let (idx, cur_char) = match iter.next() {
+            Some(pair) => pair,
+            None => return text.len(),
         };
         match cur_char {
             '\u{feff}' | '\r' | '\t' | '\n' | ' ' => continue,
             // Comment
             '#' => {
-                while let Some(c) = iter.next() {
+                while let Some((_, c)) = iter.next() {
                     if c == '\r' || c == '\n' {
                         break;
                     }
                 }
                 continue;
             }
-            _ => return false,
+            _ => return idx,
+        }
+    }
+}
+
+/// Returns position after the text starting at `pos`
+fn advance(mut pos: Pos, text: &str) -> Pos {
+    match text.rfind('\n') {
+        Some(last) => {
+            pos.line += text.matches('\n').count();
+            pos.column = text[last+1..].chars().count() + 1;
+        }
+        None => pos.column += text.chars().count(),
+    }
+    pos.offset += text.len() as u64;
+    pos
+}
+
+/// Statement read by `StatementReader`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    /// Text of the statement including the semicolon, but without the
+    /// whitespace and comments before it
+    pub text: String,
+    /// Position of the first character of the `text` in the input
+    pub start: Pos,
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// Input is not valid UTF-8 at the position
+    Utf8 { position: Pos },
+    /// Input ends with a statement without a semicolon
+    Unterminated { start: Pos },
+    /// Closing bracket doesn't match the opening one (if there is any)
+    MismatchedBracket { open: Option<Pos>, close: Pos },
+}
+
+/// Splits a stream of EdgeQL into statements
+///
+/// Only the statement being read is kept in memory, so this works for
+/// scripts of any size, as long as each statement fits in memory. Text of
+/// the statement can be tokenized with `TokenStream::new_at` using the
+/// `start` position.
+#[derive(Debug)]
+pub struct StatementReader<R> {
+    input: R,
+    buf: Vec<u8>,
+    /// Bytes at the start of `buf` already returned as statements, they
+    /// are dropped only when more data is read
+    consumed: usize,
+    continuation: Option<Continuation>,
+    /// Position of `buf[consumed..]` in the input
+    position: Pos,
+    done: bool,
+}
+
+impl<R: Read> StatementReader<R> {
+    pub fn new(input: R) -> StatementReader<R> {
+        StatementReader {
+            input,
+            buf: Vec::new(),
+            consumed: 0,
+            continuation: None,
+            position: Pos { line: 1, column: 1, offset: 0 },
+            done: false,
+        }
+    }
+
+    /// Returns next statement, `None` if the input ended
+    pub fn read_statement(&mut self) -> Result<Option<Statement>, ReadError>
+    {
+        if self.done {
+            return Ok(None);
+        }
+        let result = self.read_inner();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result
+    }
+
+    fn read_inner(&mut self) -> Result<Option<Statement>, ReadError> {
+        loop {
+            let data = &self.buf[self.consumed..];
+            match full_statement_checked(data, self.continuation.take()) {
+                Ok(end) => return self.take(end).map(Some),
+                Err(StatementError::Incomplete(cont)) => {
+                    self.continuation = Some(cont);
+                }
+                Err(StatementError::MismatchedBracket { open, close }) => {
+                    return Err(ReadError::MismatchedBracket {
+                        open: open.map(|offset| self.position_at(offset)),
+                        close: self.position_at(close),
+                    });
+                }
+            }
+            if !self.read_chunk()? {
+                let len = self.buf.len() - self.consumed;
+                let statement = self.take(len)?;
+                if statement.text.is_empty() {
+                    return Ok(None);
+                }
+                return Err(ReadError::Unterminated { start: statement.start });
+            }
+        }
+    }
+
+    /// Reads more data into the buffer, returns `false` at the end of input
+    fn read_chunk(&mut self) -> Result<bool, ReadError> {
+        if self.consumed > 0 {
+            let len = self.buf.len();
+            self.buf.copy_within(self.consumed.., 0);
+            self.buf.truncate(len - self.consumed);
+            self.consumed = 0;
+        }
+        let len = self.buf.len();
+        self.buf.resize(len + CHUNK_SIZE, 0);
+        let result = loop {
+            match self.input.read(&mut self.buf[len..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.buf.truncate(len + *result.as_ref().unwrap_or(&0));
+        Ok(result? > 0)
+    }
+
+    /// Returns position of the byte at `offset` in the unconsumed data
+    fn position_at(&self, offset: usize) -> Pos {
+        let data = &self.buf[self.consumed..self.consumed + offset];
+        advance(self.position, &String::from_utf8_lossy(data))
+    }
+
+    /// Consumes `len` bytes of the buffer, returning them as a statement
+    fn take(&mut self, len: usize) -> Result<Statement, ReadError> {
+        let data = &self.buf[self.consumed..self.consumed + len];
+        let text = match str::from_utf8(data) {
+            Ok(text) => text,
+            Err(e) => {
+                let valid = &data[..e.valid_up_to()];
+                let valid = str::from_utf8(valid).expect("valid prefix");
+                return Err(ReadError::Utf8 {
+                    position: advance(self.position, valid),
+                });
+            }
+        };
+        let skip = trivia_len(text);
+        let start = advance(self.position, &text[..skip]);
+        let statement = Statement { text: text[skip..].to_string(), start };
+        self.position = advance(start, &statement.text);
+        self.consumed += len;
+        Ok(statement)
+    }
+}
+
+impl<R: Read> Iterator for StatementReader<R> {
+    type Item = Result<Statement, ReadError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_statement().transpose()
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> ReadError {
+        ReadError::Io(e)
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => e.fmt(f),
+            ReadError::Utf8 { position } => {
+                write!(f, "invalid UTF-8 at {}", position)
+            }
+            ReadError::Unterminated { start } => {
+                write!(f, "statement at {} is not terminated by semicolon",
+                       start)
+            }
+            ReadError::MismatchedBracket { open: Some(open), close } => {
+                write!(f, "closing bracket at {} doesn't match \
+                           the opening one at {}", close, open)
+            }
+            ReadError::MismatchedBracket { open: None, close } => {
+                write!(f, "unexpected closing bracket at {}", close)
+            }
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReadError::Io(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -63,6 +63,9 @@ pub enum Kind {
     BacktickName,     // `xx`
     Keyword,
     Ident,
+    Error,            // skipped invalid span, only in recovery mode
+    Whitespace,       // only in lossless mode
+    Comment,          // `# xx`, only in lossless mode
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -78,6 +81,17 @@ pub struct SpannedToken<'a> {
     pub end: Pos,
 }
 
+/// Lexical error with its location
+///
+/// Collected by the stream in the recovery mode, also returned by
+/// `incremental::retokenize` for the changed tokens
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub start: Pos,
+    pub end: Pos,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct TokenStream<'a> {
     buf: &'a str,
@@ -86,6 +100,9 @@ pub struct TokenStream<'a> {
     dot: bool,
     next_state: Option<(usize, Token<'a>, usize, Pos, Pos)>,
     keyword_buf: String,
+    recover: bool,
+    lossless: bool,
+    errors: Vec<Diagnostic>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -154,6 +171,9 @@ impl<'a> TokenStream<'a> {
             // Current max keyword length is 10, but we're reserving some
             // space
             keyword_buf: String::with_capacity(MAX_KEYWORD_LENGTH),
+            recover: false,
+            lossless: false,
+            errors: Vec::new(),
         };
         me.skip_whitespace();
         me
@@ -170,11 +190,70 @@ impl<'a> TokenStream<'a> {
             dot: false,
             next_state: None,
             keyword_buf: String::with_capacity(MAX_KEYWORD_LENGTH),
+            recover: false,
+            lossless: false,
+            errors: Vec::new(),
         };
         me.skip_whitespace();
         me
     }
 
+    /// Start stream in the error-recovery mode
+    ///
+    /// Instead of failing on the first lexical error, the stream skips the
+    /// offending span and yields it as a `Kind::Error` token. Messages for
+    /// all such spans are available from `errors()`.
+    pub fn new_recovering(s: &str) -> TokenStream {
+        let mut me = TokenStream::new(s);
+        me.recover = true;
+        me
+    }
+
+    /// Same as `new_at` but in the error-recovery mode
+    pub fn new_at_recovering(s: &str, position: Pos) -> TokenStream<'_> {
+        let mut me = TokenStream::new_at(s, position);
+        me.recover = true;
+        me
+    }
+
+    /// Start stream in the lossless mode
+    ///
+    /// Whitespace and comments are yielded as `Kind::Whitespace` and
+    /// `Kind::Comment` tokens, so concatenating values of all the tokens
+    /// gives the original text. A comment token doesn't include the newline.
+    pub fn new_lossless(s: &str) -> TokenStream {
+        TokenStream {
+            buf: s,
+            position: Pos { line: 1, column: 1, offset: 0 },
+            off: 0,
+            dot: false,
+            next_state: None,
+            keyword_buf: String::with_capacity(MAX_KEYWORD_LENGTH),
+            recover: false,
+            lossless: true,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Start stream in both the lossless and the error-recovery modes
+    ///
+    /// This is what editors need: every byte of the text is covered by some
+    /// token, even if the text is invalid.
+    pub fn new_lossless_recovering(s: &str) -> TokenStream {
+        let mut me = TokenStream::new_lossless(s);
+        me.recover = true;
+        me
+    }
+
+    /// Errors skipped so far in the recovery mode
+    pub fn errors(&self) -> &[Diagnostic] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.errors)
+    }
+
     pub fn current_pos(&self) -> Pos {
         self.position
     }
@@ -192,7 +271,18 @@ impl<'a> TokenStream<'a> {
             }
         }
         let old_pos = self.off;
-        let (kind, len) = self.peek_token()?;
+        let start = self.position;
+        if let Some((token, end)) = self.read_trivia() {
+            self.next_state = Some((old_pos, token, self.off, end, end));
+            return Ok((token, end));
+        }
+        let (kind, len, error) = match self.peek_token() {
+            Ok((kind, len)) => (kind, len, None),
+            Err(e) if self.recover && e != Error::end_of_input() => {
+                (Kind::Error, self.error_len(), Some(e))
+            }
+            Err(e) => return Err(e),
+        };
 
         // note we may want to get rid of "update_position" here as it's
         // faster to update 'as you go', but this is easier to get right first
@@ -203,6 +293,9 @@ impl<'a> TokenStream<'a> {
         };
         let value = &self.buf[self.off-len..self.off];
         let end = self.position;
+        if let Some(error) = error {
+            self.add_error(error, start, end);
+        }
 
         self.skip_whitespace();
         let token = Token { kind, value };
@@ -215,6 +308,8 @@ impl<'a> TokenStream<'a> {
         -> Result<(Kind, usize), Error<Token<'a>, Token<'a>>>
     {
         use self::Kind::*;
+        // glob import above shadows combine's `Error` with `Kind::Error`
+        use combine::easy::Error;
         let tail = &self.buf[self.off..];
         let mut iter = tail.char_indices();
         let cur_char = match iter.next() {
@@ -516,6 +611,65 @@ impl<'a> TokenStream<'a> {
         }
     }
 
+    /// Length of the span to skip after a lexical error at current offset
+    ///
+    /// Quoted tokens are skipped up to the closing quote (or to the end of
+    /// input if it's missing), words and numbers up to the first
+    /// non-alphanumeric character, anything else is skipped by one char.
+    fn error_len(&self) -> usize {
+        let tail = &self.buf[self.off..];
+        let first = match tail.chars().next() {
+            Some(c) => c,
+            None => return 0,
+        };
+        let word_len = |start: usize, number: bool| {
+            tail[start..].find(|c: char| {
+                !(c == '_' || c.is_alphanumeric() || number && c == '.')
+            }).map(|idx| start + idx).unwrap_or(tail.len())
+        };
+        match first {
+            '"' | '\'' | '`' => quoted_len(tail, 0),
+            '$' => {
+                if tail[1..].starts_with('`') {
+                    return quoted_len(tail, 1);
+                }
+                let marker_end = word_len(1, false);
+                if tail[marker_end..].starts_with('$') {
+                    let marker = &tail[..marker_end+1];
+                    find_str(&tail[marker.len()..], marker)
+                        .map(|idx| marker.len()*2 + idx)
+                        .unwrap_or(tail.len())
+                } else {
+                    marker_end
+                }
+            }
+            c if c == '_' || c.is_alphanumeric() => {
+                let len = word_len(0, c.is_ascii_digit());
+                let prefix = &tail[..len];
+                match tail[len..].chars().next() {
+                    Some('"') | Some('\'') if prefix == "b" || prefix == "r"
+                    => quoted_len(tail, len),
+                    _ => len,
+                }
+            }
+            '?' if tail[1..].starts_with('!') => 2,
+            c => c.len_utf8(),
+        }
+    }
+
+    fn add_error(&mut self, error: Error<Token<'a>, Token<'a>>,
+        start: Pos, end: Pos)
+    {
+        // stream may be reset and read again, but errors are reported once
+        if let Some(last) = self.errors.last() {
+            if last.start.offset >= start.offset {
+                return;
+            }
+        }
+        let message = error_message(error);
+        self.errors.push(Diagnostic { message, start, end });
+    }
+
     fn parse_string(&mut self, quote_off: usize, raw: bool, binary: bool)
         -> Result<(Kind, usize), Error<Token<'a>, Token<'a>>>
     {
@@ -570,7 +724,7 @@ impl<'a> TokenStream<'a> {
             Letter,
             End,
         }
-        use self::Kind::*;
+        use self::Kind::{IntConst, FloatConst, DecimalConst, BigIntConst};
         let mut iter = self.buf[self.off+1..].char_indices();
         let mut suffix = None;
         let mut decimal = false;
@@ -730,7 +884,39 @@ impl<'a> TokenStream<'a> {
         }
     }
 
+    /// Reads a whitespace or a comment token in the lossless mode
+    fn read_trivia(&mut self) -> Option<(Token<'a>, Pos)> {
+        if !self.lossless {
+            return None;
+        }
+        let buf = self.buf;
+        let start = self.off;
+        let tail = &buf[start..];
+        let kind = match tail.chars().next()? {
+            '#' => {
+                let len = tail.find(&['\r', '\n'][..])
+                    .unwrap_or(tail.len());
+                self.update_position(len);
+                Kind::Comment
+            }
+            ' ' | '\t' | '\n' | '\r' | '\u{feff}' => {
+                self.skip_trivia(false);
+                Kind::Whitespace
+            }
+            _ => return None,
+        };
+        let value = &buf[start..self.off];
+        Some((Token { kind, value }, self.position))
+    }
+
     fn skip_whitespace(&mut self) {
+        if !self.lossless {
+            self.skip_trivia(true);
+        }
+    }
+
+    /// Skips whitespace and also comments if `comments` is set
+    fn skip_trivia(&mut self, comments: bool) {
         let mut iter = self.buf[self.off..].char_indices();
         let idx = loop {
             let (idx, cur_char) = match iter.next() {
@@ -750,7 +936,7 @@ impl<'a> TokenStream<'a> {
                     continue;
                 }
                 //comment
-                '#' => {
+                '#' if comments => {
                     while let Some((_, cur_char)) = iter.next() {
                         if cur_char == '\r' || cur_char == '\n' {
                             self.position.column = 1;
@@ -794,6 +980,37 @@ impl<'a> TokenStream<'a> {
     }
 }
 
+/// Message of the error without the `Unexpected` wrapper
+pub(crate) fn error_message(error: Error<Token, Token>) -> String {
+    match error {
+        Error::Unexpected(s) | Error::Expected(s) => s.to_string(),
+        e => e.to_string(),
+    }
+}
+
+/// Length of the quoted token starting at `quote_off` including the quotes
+///
+/// Returns length of the whole `tail` if the closing quote is missing.
+fn quoted_len(tail: &str, quote_off: usize) -> usize {
+    let mut iter = tail[quote_off..].char_indices();
+    let quote = match iter.next() {
+        Some((_, c)) => c,
+        None => return tail.len(),
+    };
+    while let Some((idx, c)) = iter.next() {
+        if c == '\\' && quote != '`' {
+            iter.next();
+        } else if c == quote {
+            if quote == '`' && tail[quote_off+idx+1..].starts_with('`') {
+                iter.next();
+                continue;
+            }
+            return quote_off + idx + 1;
+        }
+    }
+    tail.len()
+}
+
 impl<'a> fmt::Display for Token<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}[{:?}]", self.value, self.kind)
@@ -0,0 +1,423 @@
+use edgeql_parser::ast::*;
+use edgeql_parser::parser::{parse_block, parse_statement, parse_schema};
+
+fn ddl(s: &str) -> DdlCommandKind {
+    match parse_statement(s).unwrap() {
+        Statement::Ddl(stmt) => stmt.command.kind,
+        stmt => panic!("not a DDL command: {:?}", stmt),
+    }
+}
+
+fn created(s: &str) -> Declaration {
+    match ddl(s) {
+        DdlCommandKind::Create(decl) => decl,
+        cmd => panic!("not a CREATE command: {:?}", cmd),
+    }
+}
+
+fn migration(s: &str) -> MigrationCommandKind {
+    match parse_statement(s).unwrap() {
+        Statement::Migration(cmd) => cmd.kind,
+        stmt => panic!("not a migration command: {:?}", stmt),
+    }
+}
+
+fn stmt_err(s: &str) -> String {
+    parse_statement(s).unwrap_err().message
+}
+
+fn schema_err(s: &str) -> String {
+    parse_schema(s).unwrap_err().message
+}
+
+fn type_name(t: &TypeExpr) -> String {
+    match &t.kind {
+        TypeExprKind::Name(TypeName { maintype, .. }) => {
+            match &maintype.module {
+                Some(module) => format!("{}::{}", module, maintype.name),
+                None => maintype.name.clone(),
+            }
+        }
+        kind => format!("{:?}", kind),
+    }
+}
+
+fn names(types: &[TypeExpr]) -> Vec<String> {
+    types.iter().map(type_name).collect()
+}
+
+#[test]
+fn create_type() {
+    let decl = created("CREATE ABSTRACT TYPE default::Named \
+                        EXTENDING std::BaseObject, Foo");
+    match decl {
+        Declaration::ObjectType(t) => {
+            assert_eq!(t.name.module.as_deref(), Some("default"));
+            assert_eq!(t.name.name, "Named");
+            assert!(t.is_abstract);
+            assert_eq!(names(&t.bases), vec!["std::BaseObject", "Foo"]);
+            assert!(t.commands.is_empty());
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+}
+
+#[test]
+fn create_type_block() {
+    let decl = created("CREATE TYPE User {
+        CREATE REQUIRED PROPERTY name -> str {
+            CREATE CONSTRAINT exclusive;
+        };
+        CREATE MULTI LINK friends -> User;
+        CREATE PROPERTY upper := str_upper(.name);
+        CREATE ANNOTATION title := 'User';
+    }");
+    let commands = match decl {
+        Declaration::ObjectType(t) => t.commands,
+        decl => panic!("unexpected declaration: {:?}", decl),
+    };
+    assert_eq!(commands.len(), 4);
+    match &commands[0].kind {
+        DdlCommandKind::Create(Declaration::ConcreteProperty(p)) => {
+            assert_eq!(p.name.name, "name");
+            assert_eq!(p.required, Some(true));
+            assert_eq!(p.cardinality, None);
+            match &p.target {
+                Some(PointerTarget::Type(t)) => {
+                    assert_eq!(type_name(t), "str");
+                }
+                t => panic!("unexpected target: {:?}", t),
+            }
+            match &p.commands[..] {
+                [DdlCommand {
+                    kind: DdlCommandKind::Create(
+                        Declaration::ConcreteConstraint(c)),
+                    ..
+                }] => assert_eq!(c.name.name, "exclusive"),
+                cmds => panic!("unexpected commands: {:?}", cmds),
+            }
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    match &commands[1].kind {
+        DdlCommandKind::Create(Declaration::ConcreteLink(l)) => {
+            assert_eq!(l.cardinality, Some(Cardinality::Many));
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    match &commands[2].kind {
+        DdlCommandKind::Create(Declaration::ConcreteProperty(p)) => {
+            assert!(matches!(p.target, Some(PointerTarget::Computable(_))));
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    match &commands[3].kind {
+        DdlCommandKind::SetAnnotation { name, .. } => {
+            assert_eq!(name.name, "title");
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+}
+
+#[test]
+fn create_misc() {
+    match created("CREATE MODULE foo.bar IF NOT EXISTS") {
+        Declaration::Module(m) => {
+            assert_eq!(m.name, "foo.bar");
+            assert!(m.if_not_exists);
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match created("CREATE FINAL SCALAR TYPE Color EXTENDING enum<Red, Green>")
+    {
+        Declaration::ScalarType(t) => {
+            assert!(t.is_final);
+            assert!(!t.is_abstract);
+            assert_eq!(t.bases.len(), 1);
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match created("CREATE ALIAS Admins := (SELECT User FILTER .admin)") {
+        Declaration::Alias(a) => assert_eq!(a.name.name, "Admins"),
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match created("CREATE ABSTRACT INHERITABLE ANNOTATION note") {
+        Declaration::Annotation(a) => assert!(a.inheritable),
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match created("CREATE ABSTRACT CONSTRAINT max_len(max: int64) \
+                   ON (len(__subject__)) EXTENDING max_value")
+    {
+        Declaration::Constraint(c) => {
+            assert_eq!(c.params.len(), 1);
+            assert!(c.subject.is_some());
+            assert_eq!(names(&c.bases), vec!["max_value"]);
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match created("CREATE INDEX ON (.name)") {
+        Declaration::Index(i) => assert!(i.commands.is_empty()),
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+}
+
+#[test]
+fn create_function() {
+    let decl = created("CREATE FUNCTION foo(a: int64, VARIADIC rest: str, \
+                        NAMED ONLY flag: OPTIONAL bool = false) \
+                        -> SET OF str { \
+                            SET volatility := 'IMMUTABLE'; \
+                            USING (SELECT rest) \
+                        }");
+    match decl {
+        Declaration::Function(f) => {
+            let kinds = f.params.iter().map(|p| p.kind).collect::<Vec<_>>();
+            assert_eq!(kinds, vec![
+                ParameterKind::Positional,
+                ParameterKind::Variadic,
+                ParameterKind::NamedOnly,
+            ]);
+            assert_eq!(f.params[2].typemod, TypeModifier::Optional);
+            assert!(f.params[2].default.is_some());
+            assert_eq!(f.returning_typemod, TypeModifier::SetOf);
+            assert_eq!(type_name(&f.returning), "str");
+            assert!(matches!(f.code, FunctionCode::Expr(_)));
+            assert_eq!(f.commands.len(), 1);
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match created("CREATE FUNCTION len(s: str) -> int64 \
+                   USING SQL FUNCTION 'length'")
+    {
+        Declaration::Function(f) => {
+            assert_eq!(f.code, FunctionCode::SqlFunction("length".into()));
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match created("CREATE FUNCTION one() -> int64 USING EdgeQL $$ 1 $$") {
+        Declaration::Function(f) => {
+            assert_eq!(f.code, FunctionCode::Code {
+                language: Language::EdgeQL,
+                code: " 1 ".into(),
+            });
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+}
+
+#[test]
+fn alter_and_drop() {
+    match ddl("ALTER TYPE User {
+        RENAME TO Person;
+        EXTENDING Named FIRST;
+        ALTER PROPERTY name {
+            SET REQUIRED;
+            SET TYPE str;
+            DROP ANNOTATION title;
+        };
+        DROP LINK friends;
+        DROP ABSTRACT;
+    }") {
+        DdlCommandKind::Alter(alter) => {
+            assert_eq!(alter.kind, ObjectKind::ObjectType);
+            assert_eq!(alter.name.name, "User");
+            let kinds = alter.commands.iter()
+                .map(|c| &c.kind).collect::<Vec<_>>();
+            assert!(matches!(kinds[0], DdlCommandKind::Rename(_)));
+            assert!(matches!(kinds[1], DdlCommandKind::Extending {
+                position: Some(InheritPosition::First), ..
+            }));
+            match kinds[2] {
+                DdlCommandKind::Alter(prop) => {
+                    assert_eq!(prop.kind, ObjectKind::Property);
+                    assert_eq!(prop.commands.len(), 3);
+                    assert_eq!(prop.commands[0].kind,
+                               DdlCommandKind::SetRequired(true));
+                }
+                cmd => panic!("unexpected command: {:?}", cmd),
+            }
+            assert!(matches!(kinds[3], DdlCommandKind::Drop(_)));
+            assert_eq!(kinds[4], &DdlCommandKind::SetAbstract(false));
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    match ddl("ALTER ABSTRACT CONSTRAINT foo SET errmessage := 'x'") {
+        DdlCommandKind::Alter(alter) => {
+            assert!(alter.is_abstract);
+            assert!(matches!(alter.commands[..], [DdlCommand {
+                kind: DdlCommandKind::SetField { .. }, ..
+            }]));
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    match ddl("DROP FUNCTION foo(a: int64)") {
+        DdlCommandKind::Drop(drop) => {
+            assert_eq!(drop.kind, ObjectKind::Function);
+            assert_eq!(drop.params.map(|p| p.len()), Some(1));
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    match ddl("DROP MODULE foo.bar") {
+        DdlCommandKind::Drop(drop) => assert_eq!(drop.name.name, "foo.bar"),
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+}
+
+#[test]
+fn ddl_with_block() {
+    match parse_statement("WITH MODULE test CREATE TYPE Foo").unwrap() {
+        Statement::Ddl(stmt) => assert_eq!(stmt.aliases.len(), 1),
+        stmt => panic!("not a DDL command: {:?}", stmt),
+    }
+}
+
+#[test]
+fn migrations() {
+    match migration("CREATE MIGRATION m1 ONTO initial {
+        SET message := 'first';
+        CREATE TYPE Foo;
+        CREATE TYPE Bar;
+    }") {
+        MigrationCommandKind::Create { name, parent, message, commands } => {
+            assert_eq!(name.as_deref(), Some("m1"));
+            assert_eq!(parent.as_deref(), Some("initial"));
+            assert!(message.is_some());
+            assert_eq!(commands.len(), 2);
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    match migration("START MIGRATION TO {
+        module default {
+            type Foo;
+        }
+    }") {
+        MigrationCommandKind::Start { target } => {
+            assert_eq!(target.declarations.len(), 1);
+        }
+        cmd => panic!("unexpected command: {:?}", cmd),
+    }
+    assert_eq!(migration("COMMIT MIGRATION"), MigrationCommandKind::Commit);
+    assert_eq!(migration("DROP MIGRATION m1"),
+               MigrationCommandKind::Drop { name: "m1".into() });
+    let block = parse_block("START MIGRATION TO {}; POPULATE MIGRATION; \
+                             ABORT MIGRATION").unwrap();
+    assert_eq!(block.len(), 3);
+}
+
+#[test]
+fn schema() {
+    let schema = parse_schema("
+        module default {
+            abstract type Named {
+                required property name -> str {
+                    constraint exclusive;
+                }
+                annotation title := 'Named';
+            }
+            type User extending Named {
+                multi link friends -> User {
+                    on target delete allow;
+                };
+                property upper := str_upper(.name);
+            };
+            scalar type Color extending enum<Red, Green>;
+            alias Admins := User;
+            function hello(name: str) -> str using (
+                SELECT 'hello ' ++ name
+            );
+        }
+        type other::Foo;
+    ").unwrap();
+    assert_eq!(schema.declarations.len(), 2);
+    let module = match &schema.declarations[0] {
+        Declaration::Module(m) => m,
+        decl => panic!("unexpected declaration: {:?}", decl),
+    };
+    assert_eq!(module.name, "default");
+    assert_eq!(module.declarations.len(), 5);
+    match &module.declarations[1] {
+        Declaration::ObjectType(t) => {
+            assert_eq!(names(&t.bases), vec!["Named"]);
+            match &t.commands[0].kind {
+                DdlCommandKind::Create(Declaration::ConcreteLink(l)) => {
+                    assert_eq!(l.commands[0].kind,
+                        DdlCommandKind::OnTargetDelete(
+                            TargetDeleteAction::Allow));
+                }
+                cmd => panic!("unexpected command: {:?}", cmd),
+            }
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+    match &schema.declarations[1] {
+        Declaration::ObjectType(t) => {
+            assert_eq!(t.name.module.as_deref(), Some("other"));
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+}
+
+#[test]
+fn schema_fields() {
+    let schema = parse_schema("
+        abstract constraint default::positive {
+            errmessage := 'must be positive';
+            using (__subject__ > 0);
+        }
+    ").unwrap();
+    match &schema.declarations[0] {
+        Declaration::Constraint(c) => {
+            assert!(matches!(c.commands[0].kind,
+                             DdlCommandKind::SetField { .. }));
+            assert!(matches!(c.commands[1].kind, DdlCommandKind::Using(_)));
+        }
+        decl => panic!("unexpected declaration: {:?}", decl),
+    }
+}
+
+#[test]
+fn errors() {
+    assert_eq!(stmt_err("CREATE FUNCTION foo($a: int64) -> int64 \
+                         USING (1)"),
+               "function parameters do not need a $ prefix, \
+                rewrite as 'a'");
+    assert_eq!(stmt_err("CREATE FUNCTION foo(a) -> int64 USING (1)"),
+               "missing type declaration for the `a` parameter");
+    assert_eq!(stmt_err("CREATE FUNCTION foo(a: int64, a: str) -> int64 \
+                         USING (1)"),
+               "duplicate parameter name `a`");
+    assert_eq!(stmt_err("CREATE FUNCTION foo() -> int64 \
+                         { SET volatility := 'IMMUTABLE' }"),
+               "missing a USING clause");
+    assert_eq!(stmt_err("CREATE FUNCTION foo() -> int64 \
+                         { USING (1); USING (2) }"),
+               "more than one USING clause");
+    assert_eq!(stmt_err("CREATE FINAL TYPE Foo"), "unexpected `FINAL`");
+    assert_eq!(stmt_err("CREATE PROPERTY foo"), "missing target of `foo`");
+    assert_eq!(stmt_err("CREATE MIGRATION { SET name := 'x' }"),
+               "unexpected field: \"name\"");
+    assert_eq!(stmt_err("WITH x := 1 COMMIT MIGRATION"),
+               "WITH block is not allowed for migration commands");
+    assert_eq!(schema_err("type Foo;"),
+               "only fully-qualified name is allowed in \
+                top-level declaration");
+    assert_eq!(schema_err("module a { module b {} }"),
+               "nested module declaration is not allowed");
+    assert_eq!(schema_err("module a { type b::Foo; }"),
+               "fully-qualified name is not allowed in \
+                a module declaration");
+    assert_eq!(schema_err("module a { type Foo type Bar }"),
+               "Unexpected \"type\", expected `;`");
+}
+
+#[test]
+fn nesting_limit() {
+    // debug builds use a lot more stack per level than release
+    let thread = std::thread::Builder::new().stack_size(64 << 20)
+        .spawn(|| {
+            assert_eq!(stmt_err(&format!("ALTER TYPE X {}RENAME TO y",
+                                         "ALTER LINK y ".repeat(1000))),
+                       "Nesting is deeper than 256 levels");
+        }).unwrap();
+    thread.join().unwrap();
+}
@@ -0,0 +1,139 @@
+use std::ops::Range;
+
+use edgeql_parser::incremental::{retokenize, Edit};
+use edgeql_parser::position::Pos;
+use edgeql_parser::tokenizer::{Kind, TokenStream, SpannedToken, Diagnostic};
+
+type Tok = (Kind, String, Pos, Pos);
+
+fn simple(tokens: &[SpannedToken<'_>]) -> Vec<Tok> {
+    tokens.iter()
+        .map(|t| (t.token.kind, t.token.value.to_string(), t.start, t.end))
+        .collect()
+}
+
+fn tokenize(s: &str) -> (Vec<SpannedToken<'_>>, Vec<Diagnostic>) {
+    let mut stream = TokenStream::new_recovering(s);
+    let tokens = (&mut stream).collect::<Result<_, _>>().expect("recovered");
+    (tokens, stream.take_errors())
+}
+
+/// Replaces `range` of `old` with `new`, checks that the result matches
+/// full re-tokenization and returns changed and replaced ranges, and the
+/// messages of the errors in the changed tokens
+fn apply(old: &str, range: Range<usize>, new: &str)
+    -> (Range<usize>, Range<usize>, Vec<String>)
+{
+    let text = format!("{}{}{}", &old[..range.start], new, &old[range.end..]);
+    let (old_tokens, _) = tokenize(old);
+    let result = retokenize(&old_tokens, &text, &Edit {
+        start: range.start,
+        end: range.end,
+        text: new,
+    });
+    let (tokens, errors) = tokenize(&text);
+    assert_eq!(simple(&result.tokens), simple(&tokens), "{:?}", text);
+    let changed = &result.tokens[result.changed.clone()];
+    let errors = errors.into_iter()
+        .filter(|e| changed.iter().any(|t| t.start == e.start))
+        .collect::<Vec<_>>();
+    assert_eq!(result.diagnostics, errors, "{:?}", text);
+    let messages = errors.into_iter().map(|e| e.message).collect();
+    (result.changed, result.replaced, messages)
+}
+
+fn edit(old: &str, range: Range<usize>, new: &str)
+    -> (Range<usize>, Range<usize>)
+{
+    let (changed, replaced, _) = apply(old, range, new);
+    (changed, replaced)
+}
+
+fn edit_err(old: &str, range: Range<usize>, new: &str) -> Vec<String> {
+    apply(old, range, new).2
+}
+
+#[test]
+fn extend_token() {
+    assert_eq!(edit("SELECT abc + 1", 10..10, "d"), (1..2, 1..2));
+    assert_eq!(edit("SELECT abc + 1", 7..7, "x"), (1..2, 1..2));
+    assert_eq!(edit("SELECT a; SELECT b", 7..8, "xyz"), (1..2, 1..2));
+    assert_eq!(edit("SELECT a.b", 9..10, "1"), (3..4, 3..4));
+    assert_eq!(edit("SELECT a.1", 9..9, "2"), (3..4, 3..4));
+}
+
+#[test]
+fn split_and_join() {
+    assert_eq!(edit("SELECT abc", 8..8, " "), (1..3, 1..2));
+    assert_eq!(edit("SELECT a b", 8..9, ""), (1..2, 1..3));
+    assert_eq!(edit("SELECT a - b", 10..10, ">"), (2..3, 2..3));
+    assert_eq!(edit("SELECT 1", 0..0, "WITH x := 2 "), (0..4, 0..0));
+    assert_eq!(edit("SELECT 1;", 9..9, " SELECT 2;"), (3..6, 3..3));
+    assert_eq!(edit("SELECT 1; SELECT 2;", 0..19, ""), (0..0, 0..6));
+}
+
+#[test]
+fn whitespace_only() {
+    assert_eq!(edit("SELECT a + b", 8..8, "   "), (2..2, 2..2));
+    assert_eq!(edit("SELECT a\n+ b", 8..9, " "), (2..2, 2..2));
+    assert_eq!(edit("SELECT a,\n  b,\n  c", 10..10, "\n\n"),
+               (3..3, 3..3));
+}
+
+#[test]
+fn strings_and_comments() {
+    assert_eq!(edit("SELECT 'a' ++ 'b'", 8..9, "x' ++ 'y"), (1..4, 1..2));
+    assert_eq!(edit("SELECT 'a' ++ 'b'", 14..17, "'b' ++ 'c'"),
+               (4..6, 4..4));
+    assert_eq!(edit("SELECT a # x\n+ b", 11..11, "\n y"),
+               (2..3, 2..2));
+    assert_eq!(edit("SELECT a + b", 8..8, " #"), (2..2, 2..4));
+    assert_eq!(edit("SELECT a\n# b\n+ c", 9..10, ""), (2..3, 2..2));
+}
+
+#[test]
+fn positions() {
+    let (changed, replaced) = edit(
+        "SELECT User {\n    name,\n    email\n} FILTER .id = 1",
+        13..13, "\n    id,");
+    assert_eq!((changed, replaced), (3..5, 3..3));
+    edit("SELECT a, b, c\nFILTER x", 7..8, "привет");
+    edit("SELECT 'мир', b,\n c", 8..14, "x");
+}
+
+#[test]
+fn errors() {
+    assert_eq!(edit_err("SELECT 'a' ++ b", 14..14, "'"),
+               ["unterminated string, quoted by `'`"]);
+    assert_eq!(edit_err("SELECT a", 8..8, "!"),
+               ["Bare `!` is not an operator, did you mean `!=`?"]);
+    // typing inside of an unclosed quote
+    assert_eq!(edit("SELECT 'ab ++ c", 10..10, "c"), (1..2, 1..2));
+    assert_eq!(edit_err("SELECT 'ab ++ c", 10..10, "c"),
+               ["unterminated string, quoted by `'`"]);
+    assert_eq!(edit_err("SELECT $$ab; SELECT 1", 11..11, "c"),
+               ["unterminated string started with $$"]);
+    // closing it
+    assert_eq!(edit("SELECT 'ab ++ c", 10..10, "'"), (1..4, 1..2));
+    assert!(edit_err("SELECT 'ab ++ c", 10..10, "'").is_empty());
+}
+
+#[test]
+fn every_position() {
+    let texts = [
+        "WITH x := (SELECT User.<owner { name }) # comment\n\
+         SELECT x.1 ++ 'str' FILTER .a = 1.5e3;",
+        // invalid text, like in the middle of typing
+        "SELECT a ! 'b ++ $x$ c; # d\n1.e5 ++ `e",
+    ];
+    for old in &texts {
+        for (pos, c) in old.char_indices() {
+            let edits = [" ", "x", ".", "1", "\n", "#", "'", "\"", "<", "$"]
+                .iter().map(|&new| (pos..pos, new))
+                .chain(Some((pos..pos+c.len_utf8(), "")));
+            for (range, new) in edits {
+                apply(old, range, new);
+            }
+        }
+    }
+}
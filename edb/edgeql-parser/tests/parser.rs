@@ -0,0 +1,436 @@
+use edgeql_parser::ast::*;
+use edgeql_parser::parser::{parse_block, parse_statement, parse_expression};
+
+/// Renders expression as an s-expression to make precedence visible
+fn sexp(e: &Expr) -> String {
+    match &e.kind {
+        ExprKind::Constant(Constant::Int(v)) => v.clone(),
+        ExprKind::Constant(Constant::Str(v)) => format!("{:?}", v),
+        ExprKind::Constant(Constant::Bool(v)) => v.to_string(),
+        ExprKind::Constant(c) => format!("{:?}", c),
+        ExprKind::Parameter(name) => format!("${}", name),
+        ExprKind::Path(path) => {
+            let mut buf = String::new();
+            if path.partial {
+                buf.push('.');
+            }
+            for (i, step) in path.steps.iter().enumerate() {
+                match step {
+                    PathStep::ObjectRef(r) => buf.push_str(&r.name),
+                    PathStep::Ptr(p) => {
+                        if i > 0 {
+                            buf.push(if p.property { '@' } else { '.' });
+                        } else if p.property {
+                            buf.push('@');
+                        }
+                        if p.direction == PointerDirection::Inbound {
+                            buf.push('<');
+                        }
+                        buf.push_str(&p.name);
+                    }
+                    PathStep::TypeIntersection(_) => buf.push_str("[IS]"),
+                    PathStep::Source { .. } => buf.push_str("__source__"),
+                    PathStep::Subject { .. } => buf.push_str("__subject__"),
+                    PathStep::Expr(e) => buf.push_str(&sexp(e)),
+                }
+            }
+            buf
+        }
+        ExprKind::BinOp { left, op, right } => {
+            format!("({:?} {} {})", op, sexp(left), sexp(right))
+        }
+        ExprKind::UnaryOp { op, operand } => {
+            format!("({:?} {})", op, sexp(operand))
+        }
+        ExprKind::IsOp { left, negated, .. } => {
+            format!("({} {})", if *negated { "IsNot" } else { "Is" },
+                    sexp(left))
+        }
+        ExprKind::IfElse { condition, if_expr, else_expr } => {
+            format!("(If {} {} {})",
+                sexp(condition), sexp(if_expr), sexp(else_expr))
+        }
+        ExprKind::TypeCast { expr, .. } => format!("(Cast {})", sexp(expr)),
+        ExprKind::Tuple(items) => list("Tuple", items),
+        ExprKind::Set(items) => list("Set", items),
+        ExprKind::Array(items) => list("Array", items),
+        ExprKind::FunctionCall(call) => {
+            let args = call.args.iter().map(|a| a.value.clone())
+                .collect::<Vec<_>>();
+            list(&call.func.name, &args)
+        }
+        ExprKind::Shape(shape) => format!("(Shape {})", sexp(&shape.expr)),
+        ExprKind::Detached(e) => format!("(Detached {})", sexp(e)),
+        ExprKind::Indirection { arg, indirection } => {
+            format!("(Index {} {})", sexp(arg), indirection.len())
+        }
+        _ => format!("{:?}", e.kind),
+    }
+}
+
+fn list(name: &str, items: &[Expr]) -> String {
+    let mut buf = format!("({}", name);
+    for item in items {
+        buf.push(' ');
+        buf.push_str(&sexp(item));
+    }
+    buf.push(')');
+    return buf;
+}
+
+fn expr(s: &str) -> String {
+    sexp(&parse_expression(s).unwrap())
+}
+
+fn expr_err(s: &str) -> String {
+    parse_expression(s).unwrap_err().message
+}
+
+fn select(s: &str) -> SelectQuery {
+    match parse_statement(s).unwrap() {
+        Statement::Select(q) => q,
+        stmt => panic!("not a select: {:?}", stmt),
+    }
+}
+
+#[test]
+fn arithmetic_precedence() {
+    assert_eq!(expr("1 + 2 * 3"), "(Add 1 (Mul 2 3))");
+    assert_eq!(expr("(1 + 2) * 3"), "(Mul (Add 1 2) 3)");
+    assert_eq!(expr("1 - 2 - 3"), "(Sub (Sub 1 2) 3)");
+    assert_eq!(expr("2 ^ 3 ^ 4"), "(Pow 2 (Pow 3 4))");
+    assert_eq!(expr("-2 ^ 2"), "(Minus (Pow 2 2))");
+    assert_eq!(expr("a ?? b ?? c"), "(Coalesce a (Coalesce b c))");
+    assert_eq!(expr("a ++ b // c"), "(Concat a (FloorDiv b c))");
+}
+
+#[test]
+fn logical_precedence() {
+    assert_eq!(expr("a OR b AND NOT c"), "(Or a (And b (Not c)))");
+    assert_eq!(expr("NOT a = b"), "(Not (Eq a b))");
+    assert_eq!(expr("a = b OR c"), "(Or (Eq a b) c)");
+    assert_eq!(expr("a < b AND c >= d"),
+               "(And (Less a b) (GreaterEq c d))");
+    assert_eq!(expr("{1} UNION {2} UNION {3}"),
+               "(Union (Union (Set 1) (Set 2)) (Set 3))");
+    assert_eq!(expr("EXISTS a ?= b"), "(NotDistinctFrom (Exists a) b)");
+}
+
+#[test]
+fn negated_operators() {
+    assert_eq!(expr("a NOT IN b"), "(NotIn a b)");
+    assert_eq!(expr("a not like 'x%'"), "(NotLike a \"x%\")");
+    assert_eq!(expr("a ILIKE 'x%'"), "(ILike a \"x%\")");
+    assert_eq!(expr("a IS NOT str"), "(IsNot a)");
+    assert_eq!(expr("a IS str AND b"), "(And (Is a) b)");
+}
+
+#[test]
+fn nonassoc_chain() {
+    assert_eq!(expr_err("a < b < c"),
+               "Unexpected \"<\", expected end of expression");
+    assert_eq!(expr_err("a IN b IN c"),
+               "Unexpected \"IN\", expected end of expression");
+}
+
+#[test]
+fn if_else() {
+    assert_eq!(expr("a IF b ELSE c IF d ELSE e"),
+               "(If b a (If d c e))");
+    assert_eq!(expr("a IF b OR c ELSE d"), "(If (Or b c) a d)");
+    assert_eq!(expr("{a} UNION b IF c ELSE d"),
+               "(Union (Set a) (If c b d))");
+}
+
+#[test]
+fn paths() {
+    assert_eq!(expr("User.name"), "User.name");
+    assert_eq!(expr("default::User.<owner"), "User.<owner");
+    assert_eq!(expr(".name"), ".name");
+    assert_eq!(expr("@weight"), ".@weight");
+    assert_eq!(expr("User.friends@since"), "User.friends@since");
+    assert_eq!(expr("User.__type__.name"), "User.__type__.name");
+    assert_eq!(expr("(1, (2, 3)).1.0"), "(Tuple 1 (Tuple 2 3)).1.0");
+    assert_eq!(expr("Issue.owner[IS Admin].name"),
+               "Issue.owner[IS].name");
+    assert_eq!(expr("__subject__.x"), "__subject__.x");
+}
+
+#[test]
+fn literals() {
+    let e = parse_expression(r#"b'\x01\x02'"#).unwrap();
+    assert_eq!(e.kind, ExprKind::Constant(Constant::Bytes(vec![1, 2])));
+    let e = parse_expression(r#"'a\nb'"#).unwrap();
+    assert_eq!(e.kind, ExprKind::Constant(Constant::Str("a\nb".into())));
+    let e = parse_expression("12n").unwrap();
+    assert_eq!(e.kind, ExprKind::Constant(Constant::BigInt("12".into())));
+    let e = parse_expression("1.5n").unwrap();
+    assert_eq!(e.kind, ExprKind::Constant(Constant::Decimal("1.5".into())));
+    assert_eq!(expr("$`my arg`"), "$my arg");
+    assert_eq!(expr("[true, false]"), "(Array true false)");
+    assert_eq!(expr("()"), "(Tuple)");
+    assert_eq!(expr("(1,)"), "(Tuple 1)");
+}
+
+#[test]
+fn named_tuple() {
+    let e = parse_expression("(a := 1, b := 'x')").unwrap();
+    match e.kind {
+        ExprKind::NamedTuple(els) => {
+            assert_eq!(els.iter().map(|e| &e.name[..]).collect::<Vec<_>>(),
+                       vec!["a", "b"]);
+        }
+        kind => panic!("unexpected {:?}", kind),
+    }
+}
+
+#[test]
+fn casts() {
+    assert_eq!(expr("<int64>'1' + 1"), "(Add (Cast \"1\") 1)");
+    assert_eq!(expr("<str>a.b"), "(Cast a.b)");
+    let e = parse_expression("<optional array<tuple<a: str, int64>>>$0")
+        .unwrap();
+    match e.kind {
+        ExprKind::TypeCast { type_, cardinality_mod, .. } => {
+            assert_eq!(cardinality_mod,
+                       Some(CardinalityModifier::Optional));
+            let array = match type_.kind {
+                TypeExprKind::Name(name) => name,
+                kind => panic!("unexpected {:?}", kind),
+            };
+            assert_eq!(array.maintype.name, "array");
+            let tuple = match &array.subtypes.unwrap()[0].kind {
+                TypeExprKind::Name(name) => name.clone(),
+                kind => panic!("unexpected {:?}", kind),
+            };
+            let subtypes = tuple.subtypes.unwrap();
+            assert_eq!(subtypes.len(), 2);
+            match &subtypes[0].kind {
+                TypeExprKind::Name(name) => {
+                    assert_eq!(name.name.as_ref().unwrap(), "a");
+                }
+                kind => panic!("unexpected {:?}", kind),
+            }
+        }
+        kind => panic!("unexpected {:?}", kind),
+    }
+}
+
+#[test]
+fn function_calls() {
+    assert_eq!(expr("count(User)"), "(count User)");
+    assert_eq!(expr("std::len('x') + 1"), "(Add (len \"x\") 1)");
+    let e = parse_expression(
+        "array_agg(User.name ORDER BY .name, sep := ',')").unwrap();
+    match e.kind {
+        ExprKind::FunctionCall(call) => {
+            assert_eq!(call.args[0].order_by.len(), 1);
+            assert_eq!(call.kwargs[0].0, "sep");
+        }
+        kind => panic!("unexpected {:?}", kind),
+    }
+    assert_eq!(expr_err("f(a := 1, 2)"),
+               "positional argument after named argument `a`");
+}
+
+#[test]
+fn indirection() {
+    assert_eq!(expr("a[0]"), "(Index a 1)");
+    assert_eq!(expr("a[1:][:2]"), "(Index a 2)");
+    assert_eq!(expr("'abc'[-1]"), "(Index \"abc\" 1)");
+}
+
+#[test]
+fn detached() {
+    assert_eq!(expr("DETACHED User.name"), "(Detached User).name");
+}
+
+#[test]
+fn select_clauses() {
+    let q = select("SELECT User { name } FILTER .age > 18 \
+                    ORDER BY .name DESC EMPTY LAST THEN .age \
+                    OFFSET 2 LIMIT 10");
+    assert_eq!(sexp(&q.result), "(Shape User)");
+    assert_eq!(sexp(q.filter.as_ref().unwrap()), "(Greater .age 18)");
+    assert_eq!(q.order_by.len(), 2);
+    assert_eq!(q.order_by[0].direction, SortDirection::Desc);
+    assert_eq!(q.order_by[0].nones_order, Some(NonesOrder::Last));
+    assert_eq!(q.order_by[1].direction, SortDirection::Default);
+    assert_eq!(sexp(q.offset.as_ref().unwrap()), "2");
+    assert_eq!(sexp(q.limit.as_ref().unwrap()), "10");
+}
+
+#[test]
+fn with_block() {
+    let q = select("WITH MODULE test, x AS MODULE a.b, y := 1, SELECT y");
+    assert_eq!(q.aliases.len(), 3);
+    match &q.aliases[1] {
+        AliasDecl::Module { alias, module, .. } => {
+            assert_eq!(alias.as_ref().unwrap(), "x");
+            assert_eq!(module, "a.b");
+        }
+        decl => panic!("unexpected {:?}", decl),
+    }
+}
+
+#[test]
+fn shapes() {
+    let q = select("SELECT User { \
+        name, \
+        friends: { name } FILTER .active LIMIT 5, \
+        required single total := count(.friends), \
+        @since, \
+        [IS Admin].level, \
+    }");
+    let shape = match q.result.kind {
+        ExprKind::Shape(shape) => shape,
+        kind => panic!("unexpected {:?}", kind),
+    };
+    assert_eq!(shape.elements.len(), 5);
+    assert_eq!(shape.elements[1].elements.len(), 1);
+    assert!(shape.elements[1].filter.is_some());
+    assert!(shape.elements[1].limit.is_some());
+    let total = &shape.elements[2];
+    assert_eq!(total.required, Some(true));
+    assert_eq!(total.cardinality, Some(Cardinality::One));
+    assert_eq!(total.operation, Some(ShapeOp::Assign));
+    match &shape.elements[3].expr.steps[0] {
+        PathStep::Ptr(ptr) => assert!(ptr.property),
+        step => panic!("unexpected {:?}", step),
+    }
+    assert_eq!(shape.elements[4].expr.steps.len(), 2);
+}
+
+#[test]
+fn shape_property_named_like_qualifier() {
+    let q = select("SELECT User { multi, required := true }");
+    let shape = match q.result.kind {
+        ExprKind::Shape(shape) => shape,
+        kind => panic!("unexpected {:?}", kind),
+    };
+    assert_eq!(shape.elements[0].cardinality, None);
+    assert_eq!(shape.elements[1].required, None);
+    assert!(shape.elements[1].compexpr.is_some());
+}
+
+#[test]
+fn insert_update_delete() {
+    match parse_statement("INSERT User { name := 'x', tags += 'y' }")
+        .unwrap()
+    {
+        Statement::Insert(q) => {
+            assert_eq!(q.shape.len(), 2);
+            assert_eq!(q.shape[1].operation, Some(ShapeOp::Append));
+        }
+        stmt => panic!("unexpected {:?}", stmt),
+    }
+    match parse_statement("UPDATE User FILTER .id = <uuid>$id \
+                           SET { tags -= 'y' }").unwrap()
+    {
+        Statement::Update(q) => {
+            assert!(q.filter.is_some());
+            assert_eq!(q.shape[0].operation, Some(ShapeOp::Subtract));
+        }
+        stmt => panic!("unexpected {:?}", stmt),
+    }
+    match parse_statement("DELETE User FILTER .name = 'x' LIMIT 1")
+        .unwrap()
+    {
+        Statement::Delete(q) => assert!(q.limit.is_some()),
+        stmt => panic!("unexpected {:?}", stmt),
+    }
+    assert_eq!(parse_statement("INSERT 1").unwrap_err().message,
+               "insert expression must be an object type reference");
+}
+
+#[test]
+fn for_and_group() {
+    match parse_statement("FOR x IN {1, 2} UNION (INSERT Foo { n := x })")
+        .unwrap()
+    {
+        Statement::For(q) => {
+            assert_eq!(q.iterator_alias, "x");
+            assert_eq!(sexp(&q.iterator), "(Set 1 2)");
+            match q.result.kind {
+                ExprKind::Statement(stmt) => match *stmt {
+                    Statement::Insert(_) => {}
+                    stmt => panic!("unexpected {:?}", stmt),
+                },
+                kind => panic!("unexpected {:?}", kind),
+            }
+        }
+        stmt => panic!("unexpected {:?}", stmt),
+    }
+    match parse_statement("GROUP u := User USING b := .age BY b \
+                           INTO g UNION count(g)").unwrap()
+    {
+        Statement::Group(q) => {
+            assert_eq!(q.subject_alias.as_ref().unwrap(), "u");
+            assert_eq!(q.using.len(), 1);
+            assert_eq!(q.into, "g");
+        }
+        stmt => panic!("unexpected {:?}", stmt),
+    }
+}
+
+#[test]
+fn block() {
+    let stmts = parse_block("SELECT 1; ; select 2;").unwrap();
+    assert_eq!(stmts.len(), 2);
+    assert_eq!(stmts[1].start().offset, 12);
+    assert_eq!(stmts[1].end().offset, 20);
+    assert!(parse_block("").unwrap().is_empty());
+}
+
+#[test]
+fn positions() {
+    let e = parse_expression("1 +\n  foo.bar").unwrap();
+    assert_eq!((e.start.line, e.start.column), (1, 1));
+    assert_eq!((e.end.line, e.end.column), (2, 10));
+}
+
+#[test]
+fn errors() {
+    let err = parse_statement("SELECT 1 2").unwrap_err();
+    assert_eq!(err.message, "Unexpected \"2\", expected end of input");
+    assert_eq!(err.start.offset, 9);
+    let err = parse_statement("SELECT (1").unwrap_err();
+    assert_eq!(err.message, "Unexpected end of input, expected `)`");
+    let err = parse_statement("SELECT 'abc").unwrap_err();
+    assert_eq!(err.message, "unterminated string, quoted by `'`");
+    assert_eq!(err.start.offset, 7);
+    assert_eq!(parse_statement("SELECT User { x { y } }")
+               .unwrap_err().message,
+               "Unexpected \"{\", expected `:` before `{` in a sub-shape");
+}
+
+#[test]
+fn nesting_limit() {
+    const TOO_DEEP: &str = "Nesting is deeper than 256 levels";
+    // debug builds use a lot more stack per level than release
+    let thread = std::thread::Builder::new().stack_size(64 << 20)
+        .spawn(|| {
+            let parens = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+            assert_eq!(expr(&parens(200)), "1");
+            let err = parse_expression(&parens(300)).unwrap_err();
+            assert_eq!(err.message, TOO_DEEP);
+            assert_eq!(err.start.offset, 256);
+            assert_eq!(expr_err(&format!("{}1", "-".repeat(100000))),
+                       TOO_DEEP);
+            assert_eq!(expr_err(&format!("{}1{}",
+                                         "[{".repeat(200), "}]".repeat(200))),
+                       TOO_DEEP);
+            assert_eq!(expr_err(&format!("{}1", "DETACHED ".repeat(1000))),
+                       TOO_DEEP);
+            assert_eq!(expr_err(&format!("<{}int64{}>1",
+                                         "array<".repeat(300),
+                                         ">".repeat(300))),
+                       TOO_DEEP);
+            assert_eq!(parse_statement(&format!("SELECT User {}{}",
+                                                "{ x: ".repeat(300),
+                                                "}".repeat(300)))
+                       .unwrap_err().message,
+                       TOO_DEEP);
+        }).unwrap();
+    thread.join().unwrap();
+}
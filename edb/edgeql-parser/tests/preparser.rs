@@ -1,4 +1,6 @@
-use edgeql_parser::preparser::{full_statement, is_empty};
+use edgeql_parser::preparser::{full_statement, is_empty, brackets, Bracket};
+use edgeql_parser::preparser::{StatementReader, Unclosed};
+use edgeql_parser::preparser::{full_statement_checked, StatementError};
 
 fn test_statement(data: &[u8], len: usize) {
     for i in 0..len-1 {
@@ -143,3 +145,185 @@ fn empty() {
     assert!(!is_empty("    ;\n#cd"));
     assert!(!is_empty("ab\n#cd"));
 }
+
+fn pairs(data: &str) -> Vec<(Option<usize>, Option<usize>)> {
+    brackets(data.as_bytes()).into_iter()
+        .map(|Bracket { open, close }| (open, close))
+        .collect()
+}
+
+#[test]
+fn test_brackets() {
+    assert_eq!(pairs("select (1, [2])"), vec![
+        (Some(7), Some(14)),
+        (Some(11), Some(13)),
+    ]);
+    assert_eq!(pairs("select { x := '(', y := \"}\" } # ["), vec![
+        (Some(7), Some(28)),
+    ]);
+    assert_eq!(pairs("select $$ { $$ + $a$ ) $a$ + (1;"), vec![
+        (Some(29), None),
+    ]);
+    assert_eq!(pairs("select (1]) ] + {'"), vec![
+        (Some(7), Some(10)),
+        (None, Some(9)),
+        (None, Some(12)),
+        (Some(16), None),
+    ]);
+}
+
+/// Reader returning data in chunks of one to three bytes
+struct Chunked<'a> {
+    data: &'a [u8],
+    step: usize,
+}
+
+impl std::io::Read for Chunked<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.step = self.step % 3 + 1;
+        let len = self.step.min(buf.len()).min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Ok(len)
+    }
+}
+
+fn read_all(data: &[u8]) -> Vec<Result<(String, String), String>> {
+    StatementReader::new(Chunked { data, step: 0 })
+        .map(|r| r
+            .map(|s| (s.text, format!("{}:{}", s.start, s.start.offset)))
+            .map_err(|e| e.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_reader() {
+    let text = "# header\nselect 1;\n  select 'a;\nb' ++ \"ц\";select {\
+        (1, 2)};\n# trailer\n";
+    assert_eq!(read_all(text.as_bytes()), vec![
+        Ok(("select 1;".into(), "2:1:9".into())),
+        Ok(("select 'a;\nb' ++ \"ц\";".into(), "3:3:21".into())),
+        Ok(("select {(1, 2)};".into(), "4:11:43".into())),
+    ]);
+    assert_eq!(read_all(b""), vec![]);
+    assert_eq!(read_all(b"  \n# only a comment"), vec![]);
+}
+
+#[test]
+fn test_reader_many() {
+    // a lot of statements in each chunk, and some spanning two chunks
+    let text = (0..20000).map(|i| format!("select {};\n", i))
+        .collect::<String>();
+    let statements = StatementReader::new(text.as_bytes())
+        .collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(statements.len(), 20000);
+    let mut offset = 0;
+    for (i, s) in statements.iter().enumerate() {
+        assert_eq!(s.text, format!("select {};", i));
+        assert_eq!(s.start.line, i + 1);
+        assert_eq!(s.start.offset, offset);
+        offset += s.text.len() as u64 + 1;
+    }
+
+    let data = b"select 1;\n select {\n  (1];";
+    let errors = StatementReader::new(&data[..])
+        .map(|r| r.map_err(|e| e.to_string()).map(|s| s.text))
+        .collect::<Vec<_>>();
+    assert_eq!(errors, vec![
+        Ok("select 1;".into()),
+        Err("closing bracket at 3:5 doesn't match \
+             the opening one at 3:3".into()),
+    ]);
+}
+
+#[test]
+fn test_reader_errors() {
+    assert_eq!(read_all(b"select 1;\n select $$ ; ;"), vec![
+        Ok(("select 1;".into(), "1:1:0".into())),
+        Err("statement at 2:2 is not terminated by semicolon".into()),
+    ]);
+    assert_eq!(read_all(b"select 1; select (2"), vec![
+        Ok(("select 1;".into(), "1:1:0".into())),
+        Err("statement at 1:11 is not terminated by semicolon".into()),
+    ]);
+    assert_eq!(read_all(b"select 1;\nselect '\xff';"), vec![
+        Ok(("select 1;".into(), "1:1:0".into())),
+        Err("invalid UTF-8 at 2:9".into()),
+    ]);
+}
+
+fn unclosed(data: &str) -> Vec<Unclosed> {
+    full_statement(data.as_bytes(), None).unwrap_err()
+        .unclosed(data.as_bytes())
+}
+
+#[test]
+fn test_unclosed() {
+    use Unclosed::*;
+
+    assert_eq!(unclosed("select 1"), vec![]);
+    assert_eq!(unclosed("select 1 # comment ("), vec![]);
+    assert_eq!(unclosed("select {(1, [2"), vec![
+        Bracket { bracket: '{', offset: 7 },
+        Bracket { bracket: '(', offset: 8 },
+        Bracket { bracket: '[', offset: 12 },
+    ]);
+    assert_eq!(unclosed("select ('a;"), vec![
+        Bracket { bracket: '(', offset: 7 },
+        String { quote: '\'', offset: 8 },
+    ]);
+    assert_eq!(unclosed("select \"a\\\""), vec![
+        String { quote: '"', offset: 7 },
+    ]);
+    assert_eq!(unclosed("select `a"), vec![Backtick { offset: 7 }]);
+    assert_eq!(unclosed("select $$a"), vec![
+        DollarQuote { marker: "$$".into(), offset: 7 },
+    ]);
+    assert_eq!(unclosed("select [$tag1$ ; $tag"), vec![
+        Bracket { bracket: '[', offset: 7 },
+        DollarQuote { marker: "$tag1$".into(), offset: 8 },
+    ]);
+    assert_eq!(unclosed("select $ta"), vec![]);
+    assert_eq!(unclosed("select ($"), vec![
+        Bracket { bracket: '(', offset: 7 },
+    ]);
+}
+
+#[test]
+fn test_unclosed_continued() {
+    let data = b"select (1, 'a; b', {2";
+    let cont = full_statement(&data[..13], None).unwrap_err();
+    let cont = full_statement(data, Some(cont)).unwrap_err();
+    let open = cont.unclosed(data);
+    assert_eq!(open.iter().map(|u| u.opening()).collect::<Vec<_>>(),
+               ["(", "{"]);
+    assert_eq!(open.iter().map(|u| u.offset()).collect::<Vec<_>>(),
+               [7, 19]);
+}
+
+#[test]
+fn test_mismatched_bracket() {
+    use StatementError::MismatchedBracket;
+
+    assert_eq!(full_statement_checked(b"SELECT (1]; SELECT 2;", None),
+               Err(MismatchedBracket { open: Some(7), close: 9 }));
+    assert_eq!(full_statement_checked(b"SELECT {(1, 2}", None),
+               Err(MismatchedBracket { open: Some(8), close: 13 }));
+    assert_eq!(full_statement_checked(b"SELECT 1); SELECT 2;", None),
+               Err(MismatchedBracket { open: None, close: 8 }));
+    assert_eq!(full_statement_checked(b"SELECT ')', \"]\", `}`;", None),
+               Ok(21));
+    let cont = full_statement(b"SELECT [(", None).unwrap_err();
+    assert_eq!(full_statement_checked(b"SELECT [(1)}", Some(cont)),
+               Err(MismatchedBracket { open: Some(7), close: 11 }));
+    assert!(matches!(full_statement_checked(b"SELECT (1", None),
+                     Err(StatementError::Incomplete(_))));
+    // the unchecked version skips stray brackets, as it always did
+    assert_eq!(full_statement(b"SELECT 1); SELECT 2;", None), Ok(10));
+    assert!(full_statement(b"SELECT (1]; SELECT 2;", None).is_err());
+    assert_eq!(read_all(b"select 1;\n select {\n  (1];"), vec![
+        Ok(("select 1;".into(), "1:1:0".into())),
+        Err("closing bracket at 3:5 doesn't match \
+             the opening one at 3:3".into()),
+    ]);
+}
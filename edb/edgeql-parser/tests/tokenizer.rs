@@ -1,4 +1,4 @@
-use edgeql_parser::tokenizer::{Kind, TokenStream};
+use edgeql_parser::tokenizer::{Kind, TokenStream, Diagnostic};
 use edgeql_parser::tokenizer::Kind::*;
 use combine::easy::Error;
 
@@ -42,6 +42,23 @@ fn tok_err(s: &str) -> String {
     panic!("No error, where error expected");
 }
 
+fn tok_recover(s: &str) -> (Vec<(Kind, &str)>, Vec<Diagnostic>) {
+    let mut s = TokenStream::new_recovering(s);
+    let tokens = (&mut s)
+        .map(|t| t.map(|t| (t.token.kind, t.token.value)))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("no errors in recovery mode");
+    (tokens, s.take_errors())
+}
+
+fn tok_lossless(s: &str) -> Vec<(Kind, &str)> {
+    let mut s = TokenStream::new_lossless(s);
+    (&mut s)
+        .map(|t| t.map(|t| (t.token.kind, t.token.value)))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("valid input")
+}
+
 #[test]
 fn whitespace_and_comments() {
     assert_eq!(tok_str("# hello { world }"), &[] as &[&str]);
@@ -750,3 +767,98 @@ fn invalid_suffix() {
     assert_eq!(tok_err("SELECT 1d;"), "Unexpected `suffix \"d\" \
         is invalid for numbers, perhaps you wanted `1n` (bigint)?`");
 }
+
+#[test]
+fn recovery() {
+    let (tokens, errors) = tok_recover("SELECT 1d + ?! x; `@a` + r'ok'");
+    assert_eq!(tokens, [
+        (Keyword, "SELECT"), (Error, "1d"), (Add, "+"), (Error, "?!"),
+        (Ident, "x"), (Semicolon, ";"), (Error, "`@a`"), (Add, "+"),
+        (Str, "r'ok'"),
+    ]);
+    let messages = errors.iter().map(|e| &e.message[..]).collect::<Vec<_>>();
+    assert_eq!(messages, [
+        "suffix \"d\" is invalid for numbers, \
+         perhaps you wanted `1n` (bigint)?",
+        "`?!` is not an operator, did you mean `?!=` ?",
+        "backtick-quoted name cannot start with char `@`",
+    ]);
+    let spans = errors.iter()
+        .map(|e| (e.start.offset, e.end.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(spans, [(7, 9), (12, 14), (18, 22)]);
+}
+
+#[test]
+fn recovery_unterminated() {
+    let (tokens, errors) = tok_recover("select 'abc\n  + 1");
+    assert_eq!(tokens, [(Keyword, "select"), (Error, "'abc\n  + 1")]);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "unterminated string, quoted by `'`");
+    assert_eq!((errors[0].end.line, errors[0].end.column), (2, 6));
+
+    let (tokens, errors) = tok_recover("x b'\u{444}' $a$ y");
+    assert_eq!(tokens, [(Ident, "x"), (Error, "b'\u{444}'"),
+                        (Error, "$a$ y")]);
+    assert_eq!(errors.len(), 2);
+}
+
+#[test]
+fn recovery_clean_input() {
+    let (tokens, errors) = tok_recover("select __type__");
+    assert_eq!(tokens, [(Keyword, "select"), (Keyword, "__type__")]);
+    assert!(errors.is_empty());
+    assert_eq!(tok_err("select 1d"), "Unexpected `suffix \"d\" \
+        is invalid for numbers, perhaps you wanted `1n` (bigint)?`");
+}
+
+#[test]
+fn lossless() {
+    assert_eq!(tok_lossless("  select # hello\n\t1;# end"), [
+        (Whitespace, "  "), (Keyword, "select"), (Whitespace, " "),
+        (Comment, "# hello"), (Whitespace, "\n\t"), (IntConst, "1"),
+        (Semicolon, ";"), (Comment, "# end"),
+    ]);
+    assert_eq!(tok_lossless("a.\n  1"), [
+        (Ident, "a"), (Dot, "."), (Whitespace, "\n  "), (IntConst, "1"),
+    ]);
+    assert_eq!(tok_lossless(""), []);
+}
+
+#[test]
+fn lossless_roundtrip() {
+    let text = "\u{feff}WITH x := {1, 2}  # comment\n\
+        SELECT x { name }\r\n\t FILTER .name = 'a # b' # tail\n\n";
+    let tokens = tok_lossless(text);
+    let rebuilt = tokens.iter().map(|(_, v)| *v).collect::<String>();
+    assert_eq!(rebuilt, text);
+
+    let mut lossless = TokenStream::new_lossless(text);
+    let significant = (&mut lossless)
+        .map(|t| t.unwrap())
+        .filter(|t| t.token.kind != Whitespace && t.token.kind != Comment)
+        .map(|t| (t.token.value, t.start, t.end))
+        .collect::<Vec<_>>();
+    let mut plain = TokenStream::new(text);
+    let expected = (&mut plain)
+        .map(|t| t.unwrap())
+        .map(|t| (t.token.value, t.start, t.end))
+        .collect::<Vec<_>>();
+    assert_eq!(significant, expected);
+}
+
+#[test]
+fn lossless_recovering() {
+    let text = "SELECT 'a' ! # c\n'b";
+    let mut s = TokenStream::new_lossless_recovering(text);
+    let tokens = (&mut s)
+        .map(|t| t.map(|t| (t.token.kind, t.token.value)))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("no errors in recovery mode");
+    assert_eq!(tokens, [
+        (Keyword, "SELECT"), (Whitespace, " "), (Str, "'a'"),
+        (Whitespace, " "), (Error, "!"), (Whitespace, " "),
+        (Comment, "# c"), (Whitespace, "\n"), (Error, "'b"),
+    ]);
+    assert_eq!(s.take_errors().len(), 2);
+}
@@ -1,4 +1,5 @@
-use cpython::{Python, ObjectProtocol, PyResult, PyString, PyList, PyObject};
+use pyo3::prelude::*;
+use pyo3::types::PyList;
 
 use edgeql_parser::keywords;
 
@@ -10,28 +11,17 @@ pub struct AllKeywords {
 
 
 pub fn get_keywords(py: Python) -> PyResult<AllKeywords> {
-    let py_intern = py.import("sys")?.get(py, "intern")?;
-    let py_frozenset = py.import("builtins")?.get(py, "frozenset")?;
-    let intern = |s: &str| -> PyResult<PyObject> {
-        let py_str = PyString::new(py, s);
-        py_intern.call(py, (py_str,), None)
+    let py_intern = py.import("sys")?.getattr("intern")?;
+    let py_frozenset = py.import("builtins")?.getattr("frozenset")?;
+    let frozenset = |names: &[&str]| -> PyResult<PyObject> {
+        let names = names.iter()
+            .map(|name| py_intern.call1((*name,)))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(py_frozenset.call1((PyList::new(py, names),))?.into())
     };
-    let current = keywords::CURRENT_RESERVED_KEYWORDS
-        .iter()
-        .map(|x| *x).map(&intern)
-        .collect::<Result<Vec<_>,_>>()?;
-    let unreserved = keywords::UNRESERVED_KEYWORDS
-        .iter()
-        .map(|x| *x).map(&intern)
-        .collect::<Result<Vec<_>,_>>()?;
-    let future = keywords::FUTURE_RESERVED_KEYWORDS
-        .iter()
-        .map(|x| *x).map(&intern)
-        .collect::<Result<Vec<_>,_>>()?;
     Ok(AllKeywords {
-        current: py_frozenset.call(py, (PyList::new(py, &current),), None)?,
-        unreserved: py_frozenset.call(py, (PyList::new(py, &unreserved),), None)?,
-        future: py_frozenset.call(py, (PyList::new(py, &future),), None)?,
+        current: frozenset(keywords::CURRENT_RESERVED_KEYWORDS)?,
+        unreserved: frozenset(keywords::UNRESERVED_KEYWORDS)?,
+        future: frozenset(keywords::FUTURE_RESERVED_KEYWORDS)?,
     })
 }
-
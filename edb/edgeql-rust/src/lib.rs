@@ -1,33 +1,58 @@
-#[macro_use] extern crate cpython;
-
-use cpython::PyString;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use pyo3::wrap_pyfunction;
 
+#[cfg(feature = "python")]
 mod errors;
+#[cfg(feature = "python")]
 mod keywords;
+#[cfg(feature = "python")]
 mod tokenizer;
-pub mod normalize;
+#[cfg(feature = "python")]
 mod pynormalize;
+pub mod normalize;
+pub mod tokens;
 
+#[cfg(feature = "python")]
 use errors::TokenizerError;
-use tokenizer::{Token, tokenize, get_unpickle_fn};
-use pynormalize::normalize;
+#[cfg(feature = "python")]
+use tokenizer::{Token, TokenList, tokenize, tokenize_many, _unpickle_token};
+#[cfg(feature = "python")]
+use tokenizer::{dump_tokens, load_tokens};
+#[cfg(feature = "python")]
+use pynormalize::{Entry, normalize, normalize_script, normalize_many};
+#[cfg(feature = "python")]
+use pynormalize::deserialize_extra;
 
 
-py_module_initializer!(
-    _edgeql_rust, init_edgeql_rust, PyInit__edgeql_rust,
-    |py, m| {
-        tokenizer::init_module(py);
-        let keywords = keywords::get_keywords(py)?;
-        m.add(py, "__doc__", "Rust enhancements for edgeql language parser")?;
+/// Rust enhancements for edgeql language parser
+#[cfg(feature = "python")]
+#[pymodule]
+fn _edgeql_rust(py: Python, m: &PyModule) -> PyResult<()> {
+    let unpickle_token = wrap_pyfunction!(_unpickle_token, m)?;
+    let load_tokens = wrap_pyfunction!(load_tokens, m)?;
+    tokenizer::init_module(py, unpickle_token.into(), load_tokens.into());
+    let keywords = keywords::get_keywords(py)?;
 
-        m.add(py, "tokenize", py_fn!(py, tokenize(data: &PyString)))?;
-        m.add(py, "_unpickle_token", get_unpickle_fn(py))?;
-        m.add(py, "Token", py.get_type::<Token>())?;
-        m.add(py, "TokenizerError", py.get_type::<TokenizerError>())?;
-        m.add(py, "Entry", py.get_type::<pynormalize::Entry>())?;
-        m.add(py, "normalize", py_fn!(py, normalize(query: &PyString)))?;
-        m.add(py, "unreserved_keywords", keywords.unreserved)?;
-        m.add(py, "future_reserved_keywords", keywords.future)?;
-        m.add(py, "current_reserved_keywords", keywords.current)?;
-        Ok(())
-    });
+    m.add_function(wrap_pyfunction!(tokenize, m)?)?;
+    m.add_function(wrap_pyfunction!(tokenize_many, m)?)?;
+    m.add("_unpickle_token", unpickle_token)?;
+    m.add_class::<Token>()?;
+    m.add_class::<TokenList>()?;
+    m.add_function(wrap_pyfunction!(dump_tokens, m)?)?;
+    m.add("load_tokens", load_tokens)?;
+    let tokenizer_error = py.get_type::<TokenizerError>();
+    // keep the error importable by pickle
+    tokenizer_error.setattr("__module__", "edb._edgeql_rust")?;
+    m.add("TokenizerError", tokenizer_error)?;
+    m.add_class::<Entry>()?;
+    m.add_function(wrap_pyfunction!(normalize, m)?)?;
+    m.add_function(wrap_pyfunction!(normalize_script, m)?)?;
+    m.add_function(wrap_pyfunction!(normalize_many, m)?)?;
+    m.add_function(wrap_pyfunction!(deserialize_extra, m)?)?;
+    m.add("unreserved_keywords", keywords.unreserved)?;
+    m.add("future_reserved_keywords", keywords.future)?;
+    m.add("current_reserved_keywords", keywords.current)?;
+    Ok(())
+}
@@ -1,11 +1,22 @@
 use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::ops::Range;
 
 use edgeql_parser::tokenizer::{TokenStream, Kind};
 use edgeql_parser::position::Pos;
-use edgeql_parser::helpers::unquote_string;
+use edgeql_parser::helpers::{unquote_string, unquote_bytes};
+use edgeql_parser::helpers::{quote_string, quote_bytes};
+use edgeql_parser::preparser::{full_statement, is_empty};
 use num_bigint::{BigInt, ToBigInt};
 use bigdecimal::BigDecimal;
-use crate::tokenizer::{CowToken};
+use bytes::{BytesMut, Bytes, BufMut};
+use edgedb_protocol::codec;
+use edgedb_protocol::value::{BigInt as ProtoBigInt, Decimal as ProtoDecimal};
+use sha2::{Sha256, Digest};
+use rayon::prelude::*;
+use crate::tokens::{CowToken};
 
 
 #[derive(Debug, PartialEq)]
@@ -15,6 +26,10 @@ pub enum Value {
     Float(f64),
     BigInt(BigInt),
     Decimal(BigDecimal),
+    Bytes(Vec<u8>),
+    Bool(bool),
+    /// Elements are always of the same kind
+    Array(Vec<Value>),
 }
 
 #[derive(Debug, PartialEq)]
@@ -22,6 +37,65 @@ pub struct Variable {
     pub value: Value,
 }
 
+/// Kind of the literal that can be extracted into an argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Literal {
+    Int,
+    Float,
+    BigInt,
+    Decimal,
+    Str,
+    Bytes,
+    Bool,
+}
+
+/// Type of the extracted argument, needed to decode it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Scalar(Literal),
+    /// Array of literals, see `NormalizeOptions::collapse_arrays`
+    Array(Literal),
+}
+
+/// Literal kept in the query when it directly follows the keyword
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preserve {
+    /// Keyword preceding the literal, case-insensitive
+    pub keyword: String,
+    /// Text of the literal to keep, any literal is kept if `None`
+    pub value: Option<String>,
+    /// Kinds of the literal to keep, any kind is kept if `None`
+    pub kinds: Option<BTreeSet<Literal>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NormalizeOptions {
+    /// Kinds of literals that are extracted into arguments
+    ///
+    /// If it's empty, arguments of the query aren't checked either, so
+    /// mixing positional and named ones is not an error.
+    ///
+    /// `Literal::Bool` isn't extracted by default, because it changes keys
+    /// (and cached compiled queries) of any query with `true` or `false`.
+    /// When it's enabled the `IF` rule of the default `preserve` keeps
+    /// the branch selection constant.
+    pub extract: BTreeSet<Literal>,
+    /// Keyword contexts where literals are kept as is
+    pub preserve: Vec<Preserve>,
+    /// Maximum number of extracted arguments per query
+    ///
+    /// Literals past the limit are kept in the query.
+    pub max_args: Option<usize>,
+    /// Keywords that disable extraction for the whole query
+    pub skip_statements: Vec<String>,
+    /// Extract array literals like `[1, 2, 3]` as a single argument
+    ///
+    /// Only arrays where all elements are literals of the same kind are
+    /// extracted, so queries which differ only in the length of such arrays
+    /// have the same key.
+    pub collapse_arrays: bool,
+}
+
 #[derive(Debug)]
 pub struct Entry<'a> {
     pub key: String,
@@ -30,12 +104,214 @@ pub struct Entry<'a> {
     pub end_pos: Pos,
     pub named_args: bool,
     pub first_arg: Option<usize>,
+    /// Byte range of the query in the original text
+    pub range: Range<usize>,
+    /// SHA-256 of the normalized tokens, a compact identifier of the query
+    pub fingerprint: [u8; 32],
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
-    Tokenizer(String, Pos),
-    Assertion(String, Pos),
+    /// Query can't be tokenized
+    Tokenizer { message: String, start: Pos, end: Pos },
+    /// Integer literal doesn't fit into `std::int64`
+    IntOverflow { start: Pos, end: Pos },
+    /// Float literal doesn't fit into `std::float64`
+    FloatOutOfRange { start: Pos, end: Pos },
+    /// Invalid escape sequence in a string or bytes literal
+    InvalidEscape { message: String, start: Pos, end: Pos },
+    /// Bigint literal has a fractional part, like `1.5e0n`
+    NonIntegralBigInt { start: Pos, end: Pos },
+    /// Bigint or decimal literal can't be parsed
+    InvalidNumber { message: String, start: Pos, end: Pos },
+    /// Query uses both positional (`$0`) and named (`$name`) arguments
+    MixedArguments { start: Pos, end: Pos },
+}
+
+impl Error {
+    /// Returns position of the first character of the erroneous token
+    pub fn start(&self) -> Pos {
+        use Error::*;
+        match *self {
+            | Tokenizer { start, .. }
+            | IntOverflow { start, .. }
+            | FloatOutOfRange { start, .. }
+            | InvalidEscape { start, .. }
+            | NonIntegralBigInt { start, .. }
+            | InvalidNumber { start, .. }
+            | MixedArguments { start, .. }
+            => start,
+        }
+    }
+    /// Returns position just after the erroneous token
+    pub fn end(&self) -> Pos {
+        use Error::*;
+        match *self {
+            | Tokenizer { end, .. }
+            | IntOverflow { end, .. }
+            | FloatOutOfRange { end, .. }
+            | InvalidEscape { end, .. }
+            | NonIntegralBigInt { end, .. }
+            | InvalidNumber { end, .. }
+            | MixedArguments { end, .. }
+            => end,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+        match self {
+            Tokenizer { message, .. } => f.write_str(message),
+            IntOverflow { .. } => {
+                f.write_str("number is out of range for std::int64")
+            }
+            FloatOutOfRange { .. } => {
+                f.write_str("number is out of range for std::float64")
+            }
+            InvalidEscape { message, .. } => f.write_str(message),
+            NonIntegralBigInt { .. } => f.write_str("number is not integer"),
+            InvalidNumber { message, .. } => f.write_str(message),
+            MixedArguments { .. } => {
+                f.write_str("cannot combine positional and named arguments")
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl ArgType {
+    /// Returns the name of the type, like `int64` or `array<int64>`
+    pub fn type_name(&self) -> String {
+        match self {
+            ArgType::Scalar(lit) => lit.type_name().to_string(),
+            ArgType::Array(lit) => format!("array<{}>", lit.type_name()),
+        }
+    }
+    /// Parses the name returned by `type_name`
+    pub fn from_type_name(name: &str) -> Option<ArgType> {
+        if name.starts_with("array<") && name.ends_with('>') {
+            Literal::from_type_name(&name["array<".len()..name.len()-1])
+                .map(ArgType::Array)
+        } else {
+            Literal::from_type_name(name).map(ArgType::Scalar)
+        }
+    }
+}
+
+impl Value {
+    /// Returns the type of the argument this value is extracted into
+    ///
+    /// Empty and nested arrays are never extracted, so they have no type.
+    pub fn arg_type(&self) -> Option<ArgType> {
+        let literal = match self {
+            Value::Str(_) => Literal::Str,
+            Value::Int(_) => Literal::Int,
+            Value::Float(_) => Literal::Float,
+            Value::BigInt(_) => Literal::BigInt,
+            Value::Decimal(_) => Literal::Decimal,
+            Value::Bytes(_) => Literal::Bytes,
+            Value::Bool(_) => Literal::Bool,
+            Value::Array(items) => {
+                return match items.first()?.arg_type()? {
+                    ArgType::Scalar(literal) => Some(ArgType::Array(literal)),
+                    ArgType::Array(_) => None,
+                };
+            }
+        };
+        Some(ArgType::Scalar(literal))
+    }
+}
+
+impl Literal {
+    /// Returns the type which the extracted argument is cast to
+    fn std_type(&self) -> &'static str {
+        match self {
+            Literal::Int => "__std__::int64",
+            Literal::Float => "__std__::float64",
+            Literal::BigInt => "__std__::bigint",
+            Literal::Decimal => "__std__::decimal",
+            Literal::Str => "__std__::str",
+            Literal::Bytes => "__std__::bytes",
+            Literal::Bool => "__std__::bool",
+        }
+    }
+    /// Returns the name of the type (without module) for this literal
+    pub fn type_name(&self) -> &'static str {
+        &self.std_type()["__std__::".len()..]
+    }
+    /// Finds the literal by the name of the type
+    pub fn from_type_name(name: &str) -> Option<Literal> {
+        use Literal::*;
+        [Int, Float, BigInt, Decimal, Str, Bytes, Bool].iter()
+            .find(|lit| lit.type_name() == name)
+            .copied()
+    }
+    fn of(token: &CowToken) -> Option<Literal> {
+        match token.kind {
+            Kind::IntConst => Some(Literal::Int),
+            Kind::FloatConst => Some(Literal::Float),
+            Kind::BigIntConst => Some(Literal::BigInt),
+            Kind::DecimalConst => Some(Literal::Decimal),
+            Kind::Str => Some(Literal::Str),
+            Kind::BinStr => Some(Literal::Bytes),
+            Kind::Keyword
+            if token.value.eq_ignore_ascii_case("true")
+            || token.value.eq_ignore_ascii_case("false")
+            => Some(Literal::Bool),
+            _ => None,
+        }
+    }
+}
+
+impl Preserve {
+    pub fn new(keyword: &str, value: Option<&str>) -> Preserve {
+        Preserve {
+            keyword: keyword.into(),
+            value: value.map(Into::into),
+            kinds: None,
+        }
+    }
+    /// Keeps literals of the specified kinds only
+    pub fn with_kinds(mut self, kinds: &[Literal]) -> Preserve {
+        self.kinds = Some(kinds.iter().copied().collect());
+        self
+    }
+    fn matches(&self, prev: Option<&CowToken>, token: &CowToken) -> bool {
+        match prev {
+            Some(CowToken { kind: Kind::Keyword, value, .. }) => {
+                value.eq_ignore_ascii_case(&self.keyword)
+                && self.value.iter().all(|v| v == &token.value)
+                && self.kinds.iter().all(|kinds| {
+                    matches!(Literal::of(token),
+                             Some(lit) if kinds.contains(&lit))
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> NormalizeOptions {
+        use Literal::*;
+        NormalizeOptions {
+            extract: [Int, Float, BigInt, Decimal, Str, Bytes]
+                .iter().copied().collect(),
+            preserve: vec![
+                // `LIMIT 1` is a special case for the query planner
+                Preserve::new("LIMIT", Some("1")),
+                // like `@include(if: true)` in GraphQL, selects a branch
+                Preserve::new("IF", None).with_kinds(&[Bool]),
+            ],
+            max_args: None,
+            skip_statements: ["CONFIGURE", "CREATE", "ALTER", "DROP", "START"]
+                .iter().map(|s| s.to_string()).collect(),
+            collapse_arrays: false,
+        }
+    }
 }
 
 fn push_var<'x>(res: &mut Vec<CowToken<'x>>, typ: &'x str, var: String,
@@ -49,66 +325,195 @@ fn push_var<'x>(res: &mut Vec<CowToken<'x>>, typ: &'x str, var: String,
     res.push(CowToken {kind: Kind::CloseParen, value: ")".into(), start, end});
 }
 
-fn scan_vars<'x, 'y: 'x, I>(tokens: I) -> Option<(bool, usize)>
+fn push_array_var<'x>(res: &mut Vec<CowToken<'x>>, typ: &'x str, var: String,
+    start: Pos, end: Pos)
+{
+    res.push(CowToken {kind: Kind::OpenParen, value: "(".into(), start, end});
+    res.push(CowToken {kind: Kind::Less, value: "<".into(), start, end});
+    res.push(CowToken {kind: Kind::Ident, value: "array".into(), start, end});
+    res.push(CowToken {kind: Kind::Less, value: "<".into(), start, end});
+    res.push(CowToken {kind: Kind::Ident, value: typ.into(), start, end});
+    res.push(CowToken {kind: Kind::Greater, value: ">".into(), start, end});
+    res.push(CowToken {kind: Kind::Greater, value: ">".into(), start, end});
+    res.push(CowToken {kind: Kind::Argument, value: var.into(), start, end});
+    res.push(CowToken {kind: Kind::CloseParen, value: ")".into(), start, end});
+}
+
+/// Returns true if an expression can start after the token
+///
+/// Used to distinguish array literal `[1, 2]` from indexing `x[1]`.
+fn starts_expression(prev: Option<&CowToken>) -> bool {
+    match prev {
+        None => true,
+        Some(tok) => match tok.kind {
+            Kind::CloseParen | Kind::CloseBracket | Kind::CloseBrace => false,
+            Kind::Keyword => Literal::of(tok).is_none(),
+            _ => is_operator(tok),
+        },
+    }
+}
+
+/// Checks if tokens start with an array of literals of the same kind
+///
+/// Returns the kind of the elements and the number of tokens in the array,
+/// including brackets.
+fn literal_array(tokens: &[CowToken], options: &NormalizeOptions)
+    -> Option<(Literal, usize)>
+{
+    if tokens.first()?.kind != Kind::OpenBracket {
+        return None;
+    }
+    let mut kind = None;
+    let mut iter = tokens.iter().enumerate().skip(1);
+    loop {
+        let (_, tok) = iter.next()?;
+        let literal = Literal::of(tok)?;
+        if !options.extract.contains(&literal)
+            || *kind.get_or_insert(literal) != literal
+            || tok.value == "9223372036854775808"
+        {
+            return None;
+        }
+        match iter.next()? {
+            (idx, CowToken { kind: Kind::CloseBracket, .. }) => {
+                return Some((literal, idx + 1));
+            }
+            (_, CowToken { kind: Kind::Comma, .. }) => continue,
+            _ => return None,
+        }
+    }
+}
+
+fn scan_vars<'x, 'y: 'x, I>(tokens: I) -> Result<(bool, usize), Error>
     where I: IntoIterator<Item=&'x CowToken<'y>>,
 {
     let mut max_visited = None::<usize>;
     let mut names = BTreeSet::new();
     for t in tokens {
         if t.kind == Kind::Argument {
-            if let Ok(v) = t.value[1..].parse() {
+            if let Ok(v) = t.value[1..].parse::<usize>() {
+                if !names.is_empty() {
+                    return Err(Error::MixedArguments {
+                        start: t.start, end: t.end });
+                }
+                if v == usize::MAX {
+                    return Err(Error::IntOverflow {
+                        start: t.start, end: t.end });
+                }
                 if max_visited.map(|old| v > old).unwrap_or(true) {
                     max_visited = Some(v);
                 }
             } else {
+                if max_visited.is_some() {
+                    return Err(Error::MixedArguments {
+                        start: t.start, end: t.end });
+                }
                 names.insert(&t.value[..]);
             }
         }
     }
     if names.is_empty() {
-        let next = max_visited.map(|x| x.checked_add(1)).unwrap_or(Some(0))?;
-        Some((false, next))
-    } else if max_visited.is_some() {
-        return None  // mixed arguments
+        Ok((false, max_visited.map(|x| x + 1).unwrap_or(0)))
     } else {
-        Some((true, names.len()))
+        Ok((true, names.len()))
     }
 }
 
 pub fn normalize<'x>(text: &'x str)
     -> Result<Entry<'x>, Error>
 {
-    use combine::easy::Error::*;
-    let mut token_stream = TokenStream::new(&text);
-    let mut tokens = Vec::new();
-    for res in &mut token_stream {
-        match res {
-            Ok(t) => tokens.push(CowToken::from(t)),
-            Err(Unexpected(s)) => {
-                return Err(Error::Tokenizer(
-                    s.to_string(), token_stream.current_pos()));
-            }
-            Err(e) => {
-                return Err(Error::Tokenizer(
-                    e.to_string(), token_stream.current_pos()));
-            }
-        }
+    normalize_with_options(text, &NormalizeOptions::default())
+}
+
+pub fn normalize_with_options<'x>(text: &'x str, options: &NormalizeOptions)
+    -> Result<Entry<'x>, Error>
+{
+    normalize_at(text, Pos { line: 1, column: 1, offset: 0 }, options)
+}
+
+/// Normalizes every statement of the script on its own
+///
+/// Statements are split by semicolons, so each entry has its own arguments
+/// numbered from the start, and a statement that can't be normalized (like
+/// `CONFIGURE`) doesn't prevent extraction from other statements. Text after
+/// the last semicolon, if it's not just whitespace and comments, is
+/// normalized as the last statement.
+pub fn normalize_script<'x>(text: &'x str, options: &NormalizeOptions)
+    -> Result<Vec<Entry<'x>>, Error>
+{
+    let mut entries = Vec::new();
+    let mut pos = Pos { line: 1, column: 1, offset: 0 };
+    let mut rest = text;
+    while !is_empty(rest) {
+        // mismatched or unclosed brackets are reported by the parser later
+        let len = full_statement(rest.as_bytes(), None)
+            .unwrap_or(rest.len());
+        let entry = normalize_at(&rest[..len], pos, options)?;
+        pos = entry.end_pos;
+        rest = &rest[len..];
+        entries.push(entry);
     }
-    let end_pos = token_stream.current_pos();
-    let (named_args, var_idx) = match scan_vars(&tokens) {
-        Some(pair) => pair,
-        None => {
-            // don't extract from invalid query, let python code do its work
-            return Ok(Entry {
-                key: serialize_tokens(&tokens),
-                tokens,
-                variables: Vec::new(),
-                end_pos,
-                named_args: false,
-                first_arg: None,
-            });
-        }
-    };
+    Ok(entries)
+}
+
+/// Normalizes each of the `texts` as a separate query
+///
+/// Errors are reported per query, so a single broken query doesn't fail the
+/// whole batch. If `parallel` is set the work is spread over the global
+/// rayon thread pool. Results are in the order of `texts` either way.
+pub fn normalize_many<'x>(texts: &[&'x str], options: &NormalizeOptions,
+    parallel: bool)
+    -> Vec<Result<Entry<'x>, Error>>
+{
+    if parallel {
+        texts.par_iter()
+            .map(|text| normalize_with_options(text, options))
+            .collect()
+    } else {
+        texts.iter()
+            .map(|text| normalize_with_options(text, options))
+            .collect()
+    }
+}
+
+/// Tokenizes each of the `texts`, see `normalize_many` for details
+///
+/// Each successful result also contains the position of the end of text.
+pub fn tokenize_many<'x>(texts: &[&'x str], parallel: bool)
+    -> Vec<Result<(Vec<CowToken<'x>>, Pos), Error>>
+{
+    let start = Pos { line: 1, column: 1, offset: 0 };
+    if parallel {
+        texts.par_iter().map(|text| tokenize(text, start)).collect()
+    } else {
+        texts.iter().map(|text| tokenize(text, start)).collect()
+    }
+}
+
+fn normalize_at<'x>(text: &'x str, start: Pos, options: &NormalizeOptions)
+    -> Result<Entry<'x>, Error>
+{
+    let range = start.offset as usize..start.offset as usize + text.len();
+    let (tokens, end_pos) = tokenize(text, start)?;
+    // arguments are only checked if some literals can be extracted
+    let skip = options.extract.is_empty() || tokens.iter().any(|tok| {
+        tok.kind == Kind::Keyword
+        && options.skip_statements.iter()
+            .any(|kw| tok.value.eq_ignore_ascii_case(kw))
+    });
+    if skip {
+        return Ok(Entry {
+            key: serialize_tokens(&tokens),
+            fingerprint: fingerprint(&tokens),
+            tokens,
+            variables: Vec::new(),
+            end_pos,
+            named_args: false,
+            first_arg: None,
+            range,
+        });
+    }
+    let (named_args, var_idx) = scan_vars(&tokens)?;
     let mut rewritten_tokens = Vec::with_capacity(tokens.len());
     let mut variables = Vec::new();
     let next_var = |num: usize| {
@@ -118,118 +523,431 @@ pub fn normalize<'x>(text: &'x str)
             format!("${}", var_idx + num)
         }
     };
-    for tok in &tokens {
-        match tok.kind {
-            Kind::IntConst
-            // Don't replace `.12` because this is a tuple access
-            if !matches!(rewritten_tokens.last(),
-                Some(CowToken { kind: Kind::Dot, .. }))
-            // Don't replace 'LIMIT 1' as a special case
-            && (tok.value != "1"
-                || !matches!(rewritten_tokens.last(),
-                    Some(CowToken { kind: Kind::Keyword, ref value, .. })
-                    if value.eq_ignore_ascii_case("LIMIT")))
-            && tok.value != "9223372036854775808"
-            => {
-                push_var(&mut rewritten_tokens, "__std__::int64",
-                    next_var(variables.len()),
-                    tok.start, tok.end);
-                variables.push(Variable {
-                    value: Value::Int(tok.value.replace("_", "").parse()
-                        .map_err(|e| Error::Tokenizer(
-                            format!("can't parse integer: {}", e),
-                            tok.start))?),
-                });
-                continue;
-            }
-            Kind::FloatConst => {
-                push_var(&mut rewritten_tokens, "__std__::float64",
-                    next_var(variables.len()),
-                    tok.start, tok.end);
-                let value = tok.value.replace("_", "").parse()
-                    .map_err(|e| Error::Tokenizer(
-                        format!("can't parse std::float64: {}", e),
-                        tok.start))?;
-                if value == f64::INFINITY || value == -f64::INFINITY {
-                    return Err(Error::Tokenizer(
-                        format!("number is out of range for std::float64"),
-                        tok.start));
-                }
-                variables.push(Variable {
-                    value: Value::Float(value),
-                });
-                continue;
-            }
-            Kind::BigIntConst => {
-                push_var(&mut rewritten_tokens, "__std__::bigint",
-                    next_var(variables.len()),
-                    tok.start, tok.end);
-                let dec: BigDecimal = tok.value[..tok.value.len()-1]
-                        .replace("_", "").parse()
-                        .map_err(|e| Error::Tokenizer(
-                            format!("can't parse bigint: {}", e),
-                            tok.start))?;
-                variables.push(Variable {
-                    value: Value::BigInt(dec.to_bigint()
-                        .ok_or_else(|| Error::Assertion(
-                            format!("number is not integer"),
-                            tok.start))?),
-                });
-                continue;
-            }
-            Kind::DecimalConst => {
-                push_var(&mut rewritten_tokens, "__std__::decimal",
+    let mut resume = 0;
+    for (idx, tok) in tokens.iter().enumerate() {
+        if idx < resume {
+            continue;
+        }
+        let below_max = !matches!(options.max_args,
+            Some(max) if variables.len() >= max);
+        if options.collapse_arrays && below_max
+            && starts_expression(rewritten_tokens.last())
+        {
+            let array = literal_array(&tokens[idx..], options);
+            if let Some((literal, len)) = array {
+                let items = tokens[idx+1..idx+len-1].iter()
+                    .filter(|tok| tok.kind != Kind::Comma)
+                    .map(|tok| literal_value(literal, tok))
+                    .collect::<Result<_, _>>()?;
+                let end = tokens[idx+len-1].end;
+                push_array_var(&mut rewritten_tokens, literal.std_type(),
                     next_var(variables.len()),
-                    tok.start, tok.end);
-                variables.push(Variable {
-                    value: Value::Decimal(
-                        tok.value[..tok.value.len()-1]
-                        .replace("_", "")
-                        .parse()
-                        .map_err(|e| Error::Tokenizer(
-                            format!("can't parse decimal: {}", e),
-                            tok.start))?),
-                });
+                    tok.start, end);
+                variables.push(Variable { value: Value::Array(items) });
+                resume = idx + len;
                 continue;
             }
-            Kind::Str => {
-                push_var(&mut rewritten_tokens, "__std__::str",
-                    next_var(variables.len()),
-                    tok.start, tok.end);
-                variables.push(Variable {
-                    value: Value::Str(unquote_string(&tok.value)
-                        .map_err(|e| Error::Tokenizer(
-                            format!("can't unquote string: {}", e),
-                            tok.start))?.into()),
-                });
+        }
+        let literal = match Literal::of(tok) {
+            Some(literal) => literal,
+            None => {
+                rewritten_tokens.push(tok.clone());
                 continue;
             }
-            Kind::Keyword
-            if (matches!(&(&tok.value[..].to_uppercase())[..],
-                "CONFIGURE"|"CREATE"|"ALTER"|"DROP"|"START"))
-            => {
-                return Ok(Entry {
-                    key: serialize_tokens(&tokens),
-                    tokens,
-                    variables: Vec::new(),
-                    end_pos,
-                    named_args: false,
-                    first_arg: None,
-                });
-            }
-            _ => rewritten_tokens.push(tok.clone()),
+        };
+        let prev = rewritten_tokens.last();
+        let keep = !options.extract.contains(&literal)
+            || !below_max
+            || options.preserve.iter().any(|p| p.matches(prev, tok))
+            // Don't replace `.12` because this is a tuple access
+            || literal == Literal::Int
+                && matches!(prev, Some(CowToken { kind: Kind::Dot, .. }))
+            // Can only be used with unary minus, doesn't fit into int64
+            || tok.value == "9223372036854775808";
+        if keep {
+            rewritten_tokens.push(tok.clone());
+            continue;
         }
+        let value = literal_value(literal, tok)?;
+        push_var(&mut rewritten_tokens, literal.std_type(),
+            next_var(variables.len()),
+            tok.start, tok.end);
+        variables.push(Variable { value });
     }
     return Ok(Entry {
         named_args,
         first_arg: if variables.is_empty() { None } else { Some(var_idx) },
         key: serialize_tokens(&rewritten_tokens[..]),
+        fingerprint: fingerprint(&rewritten_tokens),
         tokens: rewritten_tokens,
         variables,
         end_pos,
+        range,
     });
 }
 
+fn tokenize<'x>(text: &'x str, start: Pos)
+    -> Result<(Vec<CowToken<'x>>, Pos), Error>
+{
+    use combine::easy::Error::*;
+    let mut token_stream = TokenStream::new_at(text, start);
+    let mut tokens = Vec::new();
+    for res in &mut token_stream {
+        match res {
+            Ok(t) => tokens.push(CowToken::from(t)),
+            Err(Unexpected(s)) => {
+                let pos = token_stream.current_pos();
+                return Err(Error::Tokenizer {
+                    message: s.to_string(), start: pos, end: pos });
+            }
+            Err(e) => {
+                let pos = token_stream.current_pos();
+                return Err(Error::Tokenizer {
+                    message: e.to_string(), start: pos, end: pos });
+            }
+        }
+    }
+    Ok((tokens, token_stream.current_pos()))
+}
+
+/// Rebuilds the query with extracted arguments replaced by literals
+///
+/// The result is equivalent to the original query, but whitespace and
+/// comments are lost and literals are written in a canonical form.
+pub fn denormalize(entry: &Entry) -> String {
+    inline_variables(&entry.tokens, &entry.variables,
+        entry.named_args, entry.first_arg)
+}
+
+/// Same as `denormalize` but starts from the `key` of the entry
+///
+/// This is useful when only the key and the variables are stored, for
+/// example in a query log.
+pub fn denormalize_key(key: &str, variables: &[Variable],
+    named_args: bool, first_arg: Option<usize>)
+    -> Result<String, Error>
+{
+    let start = Pos { line: 1, column: 1, offset: 0 };
+    let (tokens, _) = tokenize(key, start)?;
+    Ok(inline_variables(&tokens, variables, named_args, first_arg))
+}
+
+fn inline_variables(tokens: &[CowToken], variables: &[Variable],
+    named_args: bool, first_arg: Option<usize>)
+    -> String
+{
+    let mut res = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.iter();
+    while let Some(tok) = iter.next() {
+        let is_cast = tok.kind == Kind::Argument
+            && matches!(res.last(),
+                        Some(CowToken { kind: Kind::Greater, .. }));
+        let var = if is_cast {
+            first_arg
+                .and_then(|first| {
+                    argument_index(&tok.value, named_args)?
+                        .checked_sub(first)
+                })
+                .and_then(|idx| variables.get(idx))
+        } else {
+            None
+        };
+        match var {
+            Some(var) => {
+                // remove the cast `(<type>` and the closing parenthesis
+                while let Some(prev) = res.pop() {
+                    if prev.kind == Kind::OpenParen {
+                        break;
+                    }
+                }
+                iter.next();
+                push_literal(&mut res, &var.value, tok.start, tok.end);
+            }
+            None => res.push(tok.clone()),
+        }
+    }
+    serialize_tokens(&res)
+}
+
+fn argument_index(arg: &str, named_args: bool) -> Option<usize> {
+    let num = if named_args {
+        arg.strip_prefix("$__edb_arg_")?
+    } else {
+        arg.strip_prefix('$')?
+    };
+    num.parse().ok()
+}
+
+fn push_literal<'x>(res: &mut Vec<CowToken<'x>>, value: &Value,
+    start: Pos, end: Pos)
+{
+    let (kind, text) = match value {
+        Value::Int(v) => (Kind::IntConst, v.to_string()),
+        Value::Float(v) => {
+            let mut text = format!("{:?}", v);
+            if !text.contains(&['.', 'e', 'E'][..]) {
+                text.push_str(".0");
+            }
+            (Kind::FloatConst, text)
+        }
+        Value::BigInt(v) => (Kind::BigIntConst, format!("{}n", v)),
+        Value::Decimal(v) => {
+            let mut text = v.to_string();
+            if !text.contains(&['.', 'e', 'E'][..]) {
+                text.push_str(".0");
+            }
+            text.push('n');
+            (Kind::DecimalConst, text)
+        }
+        Value::Str(v) => (Kind::Str, quote_string(v)),
+        Value::Bytes(v) => (Kind::BinStr, quote_bytes(v)),
+        Value::Bool(v) => (Kind::Keyword, v.to_string()),
+        Value::Array(items) => {
+            res.push(CowToken {kind: Kind::OpenBracket, value: "[".into(),
+                               start, end});
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    res.push(CowToken {kind: Kind::Comma, value: ",".into(),
+                                       start, end});
+                }
+                push_literal(res, item, start, end);
+            }
+            res.push(CowToken {kind: Kind::CloseBracket, value: "]".into(),
+                               start, end});
+            return;
+        }
+    };
+    res.push(CowToken {kind, value: text.into(), start, end});
+}
+
+fn literal_value(literal: Literal, tok: &CowToken) -> Result<Value, Error> {
+    let (start, end) = (tok.start, tok.end);
+    match literal {
+        Literal::Int => {
+            tok.value.replace("_", "").parse()
+                .map(Value::Int)
+                .map_err(|_| Error::IntOverflow { start, end })
+        }
+        Literal::Float => {
+            let value: f64 = tok.value.replace("_", "").parse()
+                .map_err(|_| Error::FloatOutOfRange { start, end })?;
+            if value.is_infinite() {
+                return Err(Error::FloatOutOfRange { start, end });
+            }
+            Ok(Value::Float(value))
+        }
+        Literal::BigInt => {
+            let dec: BigDecimal = tok.value[..tok.value.len()-1]
+                    .replace("_", "").parse()
+                    .map_err(|e| Error::InvalidNumber {
+                        message: format!("can't parse bigint: {}", e),
+                        start, end })?;
+            dec.to_bigint()
+                .map(Value::BigInt)
+                .ok_or(Error::NonIntegralBigInt { start, end })
+        }
+        Literal::Decimal => {
+            tok.value[..tok.value.len()-1]
+                .replace("_", "")
+                .parse()
+                .map(Value::Decimal)
+                .map_err(|e| Error::InvalidNumber {
+                    message: format!("can't parse decimal: {}", e),
+                    start, end })
+        }
+        Literal::Str => {
+            unquote_string(&tok.value)
+                .map(|s| Value::Str(s.into()))
+                .map_err(|e| Error::InvalidEscape {
+                    message: format!("can't unquote string: {}", e),
+                    start, end })
+        }
+        Literal::Bytes => {
+            unquote_bytes(&tok.value)
+                .map(Value::Bytes)
+                .map_err(|e| Error::InvalidEscape {
+                    message: format!("can't unquote bytes: {}", e),
+                    start, end })
+        }
+        Literal::Bool => {
+            Ok(Value::Bool(tok.value.eq_ignore_ascii_case("true")))
+        }
+    }
+}
+
+/// Encodes the value with its length prefix
+fn encode_element(buf: &mut BytesMut, value: &Value) -> Result<(), String> {
+    buf.reserve(4);
+    let pos = buf.len();
+    buf.put_u32(0);  // replaced after serializing a value
+    encode_value(buf, value)?;
+    let len = buf.len()-pos-4;
+    buf[pos..pos+4].copy_from_slice(&u32::try_from(len)
+            .map_err(|_| "element isn't too long".to_owned())?
+            .to_be_bytes());
+    Ok(())
+}
+
+fn encode_value(buf: &mut BytesMut, value: &Value) -> Result<(), String> {
+    use edgedb_protocol::value::Value as P;
+    use edgedb_protocol::codec::Codec;
+
+    match *value {
+        Value::Int(v) => {
+            codec::Int64.encode(buf, &P::Int64(v))
+                .map_err(|e| format!("int cannot be encoded: {}", e))?;
+        }
+        Value::Str(ref v) => {
+            codec::Str.encode(buf, &P::Str(v.clone()))
+                .map_err(|e| format!("str cannot be encoded: {}", e))?;
+        }
+        Value::Float(ref v) => {
+            codec::Float64.encode(buf, &P::Float64(*v))
+                .map_err(|e| format!("float cannot be encoded: {}", e))?;
+        }
+        Value::BigInt(ref v) => {
+            let val = ProtoBigInt::try_from(v.clone())
+                .map_err(|e| format!("bigint cannot be encoded: {}", e))?;
+            codec::BigInt.encode(buf, &P::BigInt(val))
+                .map_err(|e| format!("bigint cannot be encoded: {}", e))?;
+        }
+        Value::Decimal(ref v) => {
+            let val = ProtoDecimal::try_from(v.clone())
+                .map_err(|e| format!("decimal cannot be encoded: {}", e))?;
+            codec::Decimal.encode(buf, &P::Decimal(val))
+                .map_err(|e| format!("decimal cannot be encoded: {}", e))?;
+        }
+        Value::Bytes(ref v) => {
+            codec::Bytes.encode(buf, &P::Bytes(v.clone()))
+                .map_err(|e| format!("bytes cannot be encoded: {}", e))?;
+        }
+        Value::Bool(v) => {
+            codec::Bool.encode(buf, &P::Bool(v))
+                .map_err(|e| format!("bool cannot be encoded: {}", e))?;
+        }
+        Value::Array(ref items) => {
+            let len = u32::try_from(items.len())
+                .map_err(|_| "array is too long".to_owned())?;
+            buf.reserve(20);
+            buf.put_u32(1);  // ndims
+            buf.put_u32(0);  // reserved
+            buf.put_u32(0);  // reserved
+            buf.put_u32(len);  // dimension length
+            buf.put_u32(1);  // lower bound
+            for item in items {
+                encode_element(buf, item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn serialize_extra(variables: &[Variable]) -> Result<Bytes, String> {
+    let mut buf = BytesMut::new();
+    buf.reserve(4*variables.len());
+    for var in variables {
+        encode_element(&mut buf, &var.value)?;
+    }
+    Ok(buf.freeze())
+}
+
+/// Decodes values written by `serialize_extra`
+///
+/// Types of the values aren't stored in the blob, so they must be passed
+/// in the same order as the arguments, i.e. `Value::arg_type` of each of
+/// the entry's variables (python code gets them from `Entry.arg_types()`
+/// and must keep them along with the `Entry.extra_blob()`).
+pub fn deserialize_extra(blob: &[u8], types: &[ArgType])
+    -> Result<Vec<Value>, String>
+{
+    let mut buf = blob;
+    let mut values = Vec::with_capacity(types.len());
+    for typ in types {
+        let data = take_element(&mut buf)?;
+        values.push(decode_value(data, *typ)?);
+    }
+    if !buf.is_empty() {
+        return Err(format!("{} bytes left after the last value",
+                           buf.len()));
+    }
+    Ok(values)
+}
+
+/// Splits off the length-prefixed element from the start of the buffer
+fn take_element<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    let len = take_u32(buf)? as usize;
+    if buf.len() < len {
+        return Err(format!("element of {} bytes is truncated", len));
+    }
+    let (data, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(data)
+}
+
+fn take_u32(buf: &mut &[u8]) -> Result<u32, String> {
+    if buf.len() < 4 {
+        return Err("unexpected end of data".to_owned());
+    }
+    let (head, rest) = buf.split_at(4);
+    *buf = rest;
+    Ok(u32::from_be_bytes([head[0], head[1], head[2], head[3]]))
+}
+
+fn decode_value(data: &[u8], typ: ArgType) -> Result<Value, String> {
+    let literal = match typ {
+        ArgType::Scalar(literal) => return decode_scalar(data, literal),
+        ArgType::Array(literal) => literal,
+    };
+    let mut buf = data;
+    let ndims = take_u32(&mut buf)?;
+    take_u32(&mut buf)?;  // reserved
+    take_u32(&mut buf)?;  // reserved
+    let mut items = Vec::new();
+    match ndims {
+        0 => {}
+        1 => {
+            let len = take_u32(&mut buf)?;
+            take_u32(&mut buf)?;  // lower bound
+            for _ in 0..len {
+                let item = take_element(&mut buf)?;
+                items.push(decode_scalar(item, literal)?);
+            }
+        }
+        _ => return Err(format!("unsupported array of {} dimensions",
+                                ndims)),
+    }
+    if !buf.is_empty() {
+        return Err("array has extra data".to_owned());
+    }
+    Ok(Value::Array(items))
+}
+
+fn decode_scalar(data: &[u8], literal: Literal) -> Result<Value, String> {
+    use edgedb_protocol::value::Value as P;
+    use edgedb_protocol::codec::Codec;
+
+    let value = match literal {
+        Literal::Int => codec::Int64.decode(data),
+        Literal::Float => codec::Float64.decode(data),
+        Literal::BigInt => codec::BigInt.decode(data),
+        Literal::Decimal => codec::Decimal.decode(data),
+        Literal::Str => codec::Str.decode(data),
+        Literal::Bytes => codec::Bytes.decode(data),
+        Literal::Bool => codec::Bool.decode(data),
+    }.map_err(|e| format!("{} cannot be decoded: {}",
+                          literal.type_name(), e))?;
+    match value {
+        P::Int64(v) => Ok(Value::Int(v)),
+        P::Float64(v) => Ok(Value::Float(v)),
+        P::BigInt(v) => Ok(Value::BigInt(v.into())),
+        P::Decimal(v) => Ok(Value::Decimal(v.into())),
+        P::Str(v) => Ok(Value::Str(v)),
+        P::Bytes(v) => Ok(Value::Bytes(v)),
+        P::Bool(v) => Ok(Value::Bool(v)),
+        _ => Err(format!("unexpected value for {}", literal.type_name())),
+    }
+}
+
 fn is_operator(token: &CowToken) -> bool {
     use edgeql_parser::tokenizer::Kind::*;
     match token.kind {
@@ -281,10 +999,24 @@ fn is_operator(token: &CowToken) -> bool {
         | BacktickName
         | Keyword
         | Ident
+        | Error
+        | Whitespace
+        | Comment
         => false,
     }
 }
 
+/// Hashes values of the tokens, so whitespace and comments don't matter
+fn fingerprint(tokens: &[CowToken<'_>]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for token in tokens {
+        // length prefix makes the boundaries of the tokens unambiguous
+        hasher.update((token.value.len() as u64).to_le_bytes());
+        hasher.update(token.value.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
 fn serialize_tokens(tokens: &[CowToken<'_>]) -> String {
     use edgeql_parser::tokenizer::Kind::Argument;
 
@@ -306,7 +1038,7 @@ mod test {
     use combine::{StreamOnce, Positioned, easy::Error};
     use edgeql_parser::tokenizer::{TokenStream};
     use edgeql_parser::position::Pos;
-    use crate::tokenizer::{CowToken};
+    use crate::tokens::{CowToken};
 
     fn tokenize<'x>(s: &'x str) -> Vec<CowToken<'x>> {
         let mut r = Vec::new();
@@ -350,10 +1082,14 @@ mod test {
 
     #[test]
     fn mixed() {
-        assert_eq!(scan_vars(&tokenize("$a $0")), None);
-        assert_eq!(scan_vars(&tokenize("$0 $a")), None);
-        assert_eq!(scan_vars(&tokenize("$b $c $100")), None);
-        assert_eq!(scan_vars(&tokenize("$10 $xx $yy")), None);
+        fn is_mixed(text: &str) -> bool {
+            matches!(scan_vars(&tokenize(text)),
+                     Err(super::Error::MixedArguments { .. }))
+        }
+        assert!(is_mixed("$a $0"));
+        assert!(is_mixed("$0 $a"));
+        assert!(is_mixed("$b $c $100"));
+        assert!(is_mixed("$10 $xx $yy"));
     }
 
 }
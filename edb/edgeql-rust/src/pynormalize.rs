@@ -1,157 +1,319 @@
-use std::convert::{TryFrom};
+use std::collections::BTreeSet;
 
-use cpython::{Python, PyClone, PyDict, PyList, PyString, PyResult};
-use cpython::{PyTuple, PyInt, ToPyObject, PythonObject, PyBytes, PyErr};
-use cpython::{PyFloat};
-use cpython::exc::AssertionError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyString, PyBytes, PyLong, PyFloat};
+use pyo3::exceptions::{PyAssertionError, PyTypeError, PyValueError};
 
-use bytes::{BytesMut, Bytes, BufMut};
 use edgeql_parser::position::Pos;
-use edgedb_protocol::codec;
-use edgedb_protocol::value::{BigInt, Decimal};
 
 use crate::errors::TokenizerError;
-use crate::normalize::{Error, Value, Variable, normalize as _normalize};
-use crate::tokenizer::convert_tokens;
-
-
-py_class!(pub class Entry |py| {
-    data _key: PyString;
-    data _tokens: PyList;
-    data _extra_blob: PyBytes;
-    data _extra_named: bool;
-    data _first_extra: Option<usize>;
-    data _extra_count: usize;
-    data _variables: Vec<Variable>;
-    def key(&self) -> PyResult<PyString> {
-        Ok(self._key(py).clone_ref(py))
+use crate::normalize::{Error, Value, Variable, normalize_with_options};
+use crate::normalize::{Entry as _Entry, normalize_script as _normalize_script};
+use crate::normalize::{NormalizeOptions, Literal, Preserve, ArgType};
+use crate::normalize::{denormalize_key, serialize_extra};
+use crate::normalize::{deserialize_extra as _deserialize_extra};
+use crate::normalize::{normalize_many as _normalize_many};
+use crate::tokenizer::TokenList;
+
+
+/// Position as seen by python code: `(line, column, offset)`
+pub type PyPos = (usize, usize, u64);
+
+#[pyclass(module = "edb._edgeql_rust")]
+pub struct Entry {
+    key: Py<PyString>,
+    tokens: Py<TokenList>,
+    extra_blob: Py<PyBytes>,
+    extra_named: bool,
+    first_extra: Option<usize>,
+    extra_count: usize,
+    variables: Vec<Variable>,
+    range: (usize, usize),
+    fingerprint: [u8; 32],
+}
+
+#[pymethods]
+impl Entry {
+    fn key(&self, py: Python) -> Py<PyString> {
+        self.key.clone_ref(py)
     }
-    def variables(&self) -> PyResult<PyDict> {
+    fn variables(&self, py: Python) -> PyResult<Py<PyDict>> {
         let vars = PyDict::new(py);
-        let named = *self._extra_named(py);
-        let first = match self._first_extra(py) {
+        let first = match self.first_extra {
             Some(first) => first,
-            None => return Ok(vars),
+            None => return Ok(vars.into()),
         };
-        for (idx, var) in self._variables(py).iter().enumerate() {
-            let s = if named {
+        for (idx, var) in self.variables.iter().enumerate() {
+            let s = if self.extra_named {
                 format!("__edb_arg_{}", first + idx)
             } else {
                 (first + idx).to_string()
             };
-            vars.set_item(py, s.to_py_object(py),
-                match var.value {
-                    Value::Int(ref v) => v.to_py_object(py).into_object(),
-                    Value::Str(ref v) => v.to_py_object(py).into_object(),
-                    Value::Float(ref v) => v.to_py_object(py).into_object(),
-                    Value::BigInt(ref v) => {
-                        py.get_type::<PyInt>()
-                        .call(py,
-                            PyTuple::new(py, &[
-                                v.to_string().to_py_object(py).into_object(),
-                            ]),
-                            None)?
-                    }
-                    Value::Decimal(ref v) => {
-                        py.get_type::<PyFloat>()
-                        .call(py,
-                            PyTuple::new(py, &[
-                                v.to_string().to_py_object(py).into_object(),
-                            ]),
-                            None)?
-                    }
-                })?;
+            vars.set_item(s, py_value(py, &var.value)?)?;
         }
-        Ok(vars)
+        Ok(vars.into())
+    }
+    fn tokens(&self, py: Python) -> Py<TokenList> {
+        self.tokens.clone_ref(py)
+    }
+    fn first_extra(&self) -> Option<usize> {
+        self.first_extra
     }
-    def tokens(&self) -> PyResult<PyList> {
-        Ok(self._tokens(py).clone_ref(py))
+    fn extra_count(&self) -> usize {
+        self.extra_count
     }
-    def first_extra(&self) -> PyResult<Option<PyInt>> {
-        Ok(self._first_extra(py).map(|x| x.to_py_object(py)))
+    fn extra_blob(&self, py: Python) -> Py<PyBytes> {
+        self.extra_blob.clone_ref(py)
     }
-    def extra_count(&self) -> PyResult<PyInt> {
-        Ok(self._extra_count(py).to_py_object(py))
+    /// Type names of the extracted arguments, like `int64` or
+    /// `array<str>`, needed along with `extra_blob()` to decode it by
+    /// `deserialize_extra()`
+    fn arg_types(&self) -> Vec<String> {
+        self.variables.iter()
+            .map(|var| var.value.arg_type().map(|t| t.type_name())
+                .unwrap_or_default())
+            .collect()
     }
-    def extra_blob(&self) -> PyResult<PyBytes> {
-        Ok(self._extra_blob(py).clone_ref(py))
+    fn range(&self) -> (usize, usize) {
+        self.range
     }
-});
+    fn fingerprint(&self, py: Python) -> Py<PyBytes> {
+        PyBytes::new(py, &self.fingerprint).into()
+    }
+    fn denormalize(&self, py: Python) -> PyResult<String> {
+        let key = self.key.as_ref(py).to_str()?;
+        denormalize_key(key, &self.variables,
+            self.extra_named, self.first_extra)
+            .map_err(py_error)
+    }
+}
 
 
-pub fn py_pos(py: Python, pos: &Pos) -> PyTuple {
-    (pos.line, pos.column, pos.offset).to_py_object(py)
+fn py_value(py: Python, value: &Value) -> PyResult<PyObject> {
+    Ok(match *value {
+        Value::Int(v) => v.into_py(py),
+        Value::Str(ref v) => v.to_object(py),
+        Value::Float(v) => v.into_py(py),
+        Value::BigInt(ref v) => {
+            py.get_type::<PyLong>().call1((v.to_string(),))?.into()
+        }
+        Value::Decimal(ref v) => {
+            py.get_type::<PyFloat>().call1((v.to_string(),))?.into()
+        }
+        Value::Bytes(ref v) => PyBytes::new(py, v).into(),
+        Value::Bool(v) => v.into_py(py),
+        Value::Array(ref items) => {
+            let items = items.iter()
+                .map(|item| py_value(py, item))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items).into()
+        }
+    })
 }
 
-pub fn serialize_extra(variables: &[Variable]) -> Result<Bytes, String> {
-    use edgedb_protocol::value::Value as P;
-    use edgedb_protocol::codec::Codec;
-
-    let mut buf = BytesMut::new();
-    buf.reserve(4*variables.len());
-    for var in variables {
-        buf.reserve(4);
-        let pos = buf.len();
-        buf.put_u32(0);  // replaced after serializing a value
-        match var.value {
-            Value::Int(v) => {
-                codec::Int64.encode(&mut buf, &P::Int64(v))
-                    .map_err(|e| format!("int cannot be encoded: {}", e))?;
+pub fn py_pos(pos: &Pos) -> PyPos {
+    (pos.line, pos.column, pos.offset)
+}
+
+fn literal(name: &str) -> PyResult<Literal> {
+    Literal::from_type_name(name).ok_or_else(|| {
+        PyValueError::new_err(format!("unsupported literal type {:?}", name))
+    })
+}
+
+/// Converts `(keyword, literal_text_or_none[, type_names_or_none])`
+fn preserve_rule(rule: &PyAny) -> PyResult<Preserve> {
+    let (keyword, value, kinds) = match rule.extract() {
+        Ok((keyword, value)) => (keyword, value, None),
+        Err(_) => rule.extract::<(String, Option<String>,
+                                  Option<Vec<String>>)>()?,
+    };
+    let kinds = match kinds {
+        Some(names) => Some(names.iter()
+            .map(|name| literal(name))
+            .collect::<PyResult<_>>()?),
+        None => None,
+    };
+    Ok(Preserve { keyword, value, kinds })
+}
+
+/// Converts keyword arguments of `normalize()` to options
+///
+/// Accepted arguments are `extract` (list of type names like `"int64"`,
+/// `"bool"` is only extracted if it's in the list), `preserve` (list of
+/// `(keyword, literal_text_or_none)` tuples, optionally with a third
+/// element, a list of type names of the literals to keep),
+/// `default_preserve` (bool, if false the `preserve` rules replace the
+/// default ones rather than being added to them), `max_args` (int or None),
+/// `skip_statements` (list of keywords) and `collapse_arrays` (bool).
+fn options(kwargs: Option<&PyDict>) -> PyResult<NormalizeOptions> {
+    let mut options = NormalizeOptions::default();
+    let kwargs = match kwargs {
+        Some(kwargs) => kwargs,
+        None => return Ok(options),
+    };
+    let mut preserve = Vec::new();
+    let mut default_preserve = true;
+    for (key, value) in kwargs {
+        match key.extract::<&str>()? {
+            "extract" => {
+                options.extract = value.extract::<Vec<String>>()?.iter()
+                    .map(|name| literal(name))
+                    .collect::<PyResult<_>>()?;
             }
-            Value::Str(ref v) => {
-                codec::Str.encode(&mut buf, &P::Str(v.clone()))
-                    .map_err(|e| format!("str cannot be encoded: {}", e))?;
+            "preserve" => {
+                preserve = value.extract::<Vec<&PyAny>>()?.into_iter()
+                    .map(preserve_rule)
+                    .collect::<PyResult<_>>()?;
             }
-            Value::Float(ref v) => {
-                codec::Float64.encode(&mut buf, &P::Float64(v.clone()))
-                    .map_err(|e| format!("float cannot be encoded: {}", e))?;
+            "default_preserve" => {
+                default_preserve = value.extract()?;
             }
-            Value::BigInt(ref v) => {
-                let val = BigInt::try_from(v.clone())
-                    .map_err(|e| format!("bigint cannot be encoded: {}", e))?;
-                codec::BigInt.encode(&mut buf, &P::BigInt(val))
-                    .map_err(|e| format!("bigint cannot be encoded: {}", e))?;
+            "max_args" => {
+                options.max_args = value.extract()?;
             }
-            Value::Decimal(ref v) => {
-                let val = Decimal::try_from(v.clone())
-                    .map_err(|e| format!("decimal cannot be encoded: {}", e))?;
-                codec::Decimal.encode(&mut buf, &P::Decimal(val))
-                    .map_err(|e| format!("decimal cannot be encoded: {}", e))?;
+            "skip_statements" => {
+                options.skip_statements = value.extract()?;
+            }
+            "collapse_arrays" => {
+                options.collapse_arrays = value.extract()?;
+            }
+            _ => {
+                return Err(PyTypeError::new_err(
+                    format!("normalize() got an unexpected keyword \
+                        argument {}", key.repr()?.to_str()?)));
             }
         }
-        let len = buf.len()-pos-4;
-        buf[pos..pos+4].copy_from_slice(&u32::try_from(len)
-                .map_err(|_| "element isn't too long".to_owned())?
-                .to_be_bytes());
     }
-    Ok(buf.freeze())
+    if !default_preserve {
+        options.preserve.clear();
+    }
+    options.preserve.extend(preserve);
+    Ok(options)
 }
 
-pub fn normalize(py: Python<'_>, text: &PyString)
-    -> PyResult<Entry>
+fn py_entry(py: Python, text: &str, entry: _Entry) -> PyResult<Entry> {
+    let blob = serialize_extra(&entry.variables)
+        .map_err(PyAssertionError::new_err)?;
+    let source = &text[entry.range.clone()];
+    let tokens = TokenList::new(source, &entry.tokens, entry.end_pos)
+        .map_err(py_error)?;
+
+    Ok(Entry {
+        key: PyString::new(py, &entry.key).into(),
+        tokens: Py::new(py, tokens)?,
+        extra_blob: PyBytes::new(py, &blob).into(),
+        extra_named: entry.named_args,
+        first_extra: entry.first_arg,
+        extra_count: entry.variables.len(),
+        variables: entry.variables,
+        range: (entry.range.start, entry.range.end),
+        fingerprint: entry.fingerprint,
+    })
+}
+
+/// Same as `normalize_with_options` but a query mixing positional and named
+/// arguments is returned without extraction, so the compiler reports the
+/// error along with other errors of the query
+fn normalize_or_keep<'x>(text: &'x str, options: &NormalizeOptions)
+    -> Result<_Entry<'x>, Error>
 {
-    let text = text.to_string(py)?;
-    match _normalize(&text) {
-        Ok(entry) => {
-            let blob = serialize_extra(&entry.variables)
-                .map_err(|e| PyErr::new::<AssertionError, _>(py, e))?;
-
-            Ok(Entry::create_instance(py,
-                /* key: */ entry.key.to_py_object(py),
-                /* tokens: */ convert_tokens(py, entry.tokens, entry.end_pos)?,
-                /* extra_blob: */ PyBytes::new(py, &blob),
-                /* extra_named: */ entry.named_args,
-                /* first_extra: */ entry.first_arg,
-                /* extra_count: */ entry.variables.len(),
-                /* variables: */ entry.variables,
-            )?)
-        }
-        Err(Error::Tokenizer(msg, pos)) => {
-            return Err(TokenizerError::new(py, (msg, py_pos(py, &pos))))
-        }
-        Err(Error::Assertion(msg, pos)) => {
-            return Err(PyErr::new::<AssertionError, _>(py,
-                format!("{}: {}", pos, msg)));
+    match normalize_with_options(text, options) {
+        Err(Error::MixedArguments { .. }) => {
+            normalize_with_options(text, &keep_literals(options))
         }
+        res => res,
+    }
+}
+
+fn keep_literals(options: &NormalizeOptions) -> NormalizeOptions {
+    NormalizeOptions {
+        extract: BTreeSet::new(),
+        .. options.clone()
     }
 }
+
+pub fn py_error(e: Error) -> PyErr {
+    TokenizerError::new_err((e.to_string(), py_pos(&e.start())))
+}
+
+#[pyfunction(kwargs = "**")]
+pub fn normalize(py: Python, text: &str, kwargs: Option<&PyDict>)
+    -> PyResult<Entry>
+{
+    let options = options(kwargs)?;
+    let entry = py.allow_threads(|| normalize_or_keep(text, &options))
+        .map_err(py_error)?;
+    py_entry(py, text, entry)
+}
+
+/// Decodes `Entry.extra_blob()` into a list of values, `types` are
+/// `Entry.arg_types()` of the same entry
+#[pyfunction]
+pub fn deserialize_extra(py: Python, blob: &[u8], types: Vec<&str>)
+    -> PyResult<Py<PyList>>
+{
+    let types = types.iter()
+        .map(|name| ArgType::from_type_name(name).ok_or_else(|| {
+            PyValueError::new_err(
+                format!("unsupported argument type {:?}", name))
+        }))
+        .collect::<PyResult<Vec<_>>>()?;
+    let values = _deserialize_extra(blob, &types)
+        .map_err(PyValueError::new_err)?;
+    let values = values.iter()
+        .map(|value| py_value(py, value))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(PyList::new(py, values).into())
+}
+
+#[pyfunction(kwargs = "**")]
+pub fn normalize_script(py: Python, text: &str, kwargs: Option<&PyDict>)
+    -> PyResult<Py<PyList>>
+{
+    let options = options(kwargs)?;
+    let entries = py.allow_threads(|| {
+        match _normalize_script(text, &options) {
+            Err(Error::MixedArguments { .. }) => {
+                _normalize_script(text, &keep_literals(&options))
+            }
+            res => res,
+        }
+    }).map_err(py_error)?;
+    let entries = entries.into_iter()
+        .map(|entry| Py::new(py, py_entry(py, text, entry)?))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(PyList::new(py, entries).into())
+}
+
+/// Normalizes a list of queries, returning an `Entry` or a `TokenizerError`
+/// instance for each of them
+#[pyfunction(texts, "*", parallel = "false", kwargs = "**")]
+pub fn normalize_many(py: Python, texts: Vec<&str>, parallel: bool,
+    kwargs: Option<&PyDict>)
+    -> PyResult<Py<PyList>>
+{
+    let options = options(kwargs)?;
+    let results = py.allow_threads(|| {
+        _normalize_many(&texts, &options, parallel).into_iter()
+            .zip(&texts)
+            .map(|(res, text)| match res {
+                Err(Error::MixedArguments { .. }) => {
+                    normalize_with_options(*text, &keep_literals(&options))
+                }
+                res => res,
+            })
+            .collect::<Vec<_>>()
+    });
+    let results = texts.iter().zip(results)
+        .map(|(text, res)| match res.map(|entry| py_entry(py, text, entry)) {
+            Ok(Ok(entry)) => Ok(Py::new(py, entry)?.into_py(py)),
+            Ok(Err(e)) if e.is_instance::<TokenizerError>(py) => {
+                Ok(e.into_py(py))
+            }
+            Ok(Err(e)) => Err(e),
+            Err(e) => Ok(py_error(e).into_py(py)),
+        })
+        .collect::<PyResult<Vec<PyObject>>>()?;
+    Ok(PyList::new(py, results).into())
+}
@@ -1,153 +1,326 @@
-use std::borrow::Cow;
 use std::char;
 use std::collections::HashMap;
 use std::iter::Peekable;
 use std::slice::Iter;
 use std::str::FromStr;
 
-use cpython::{PyString, PyBytes, PyResult, Python, PyClone, PythonObject};
-use cpython::{PyTuple, PyList, PyInt, PyObject, ToPyObject, ObjectProtocol};
-use cpython::{FromPyObject};
+use pyo3::prelude::*;
+use pyo3::once_cell::GILOnceCell;
+use pyo3::types::{PyString, PyBytes, PyList, PyLong, PySlice};
+use pyo3::exceptions::{PyIndexError, PyValueError};
 
-use edgeql_parser::tokenizer::{TokenStream, Kind, is_keyword, SpannedToken};
+use edgeql_parser::tokenizer::{TokenStream, Kind, is_keyword};
 use edgeql_parser::tokenizer::{MAX_KEYWORD_LENGTH};
 use edgeql_parser::position::Pos;
 use edgeql_parser::keywords::{CURRENT_RESERVED_KEYWORDS, UNRESERVED_KEYWORDS};
 use edgeql_parser::keywords::{FUTURE_RESERVED_KEYWORDS};
-use edgeql_parser::helpers::unquote_string;
+use edgeql_parser::helpers::{unquote_string, unquote_bytes};
 use crate::errors::TokenizerError;
-use crate::pynormalize::py_pos;
+use crate::tokens::CowToken;
+use crate::tokens::{dump_tokens as _dump_tokens, load_tokens as _load_tokens};
+use crate::normalize::{Error, tokenize_many as _tokenize_many};
+use crate::pynormalize::{py_pos, py_error, PyPos};
 
-static mut TOKENS: Option<Tokens> = None;
+static TOKENS: GILOnceCell<Tokens> = GILOnceCell::new();
 
 
-#[derive(Debug, Clone)]
-pub struct CowToken<'a> {
-    pub kind: Kind,
-    pub value: Cow<'a, str>,
-    pub start: Pos,
-    pub end: Pos,
+fn rs_pos((line, column, offset): PyPos) -> Pos {
+    Pos { line, column, offset }
 }
 
-fn rs_pos(py: Python, value: &PyObject) -> PyResult<Pos> {
-    let (line, column, offset) = FromPyObject::extract(py, value)?;
-    Ok(Pos { line, column, offset })
+#[pyclass(module = "edb._edgeql_rust")]
+pub struct Token {
+    kind: Py<PyString>,
+    text: Py<PyString>,
+    value: PyObject,
+    start: Pos,
+    end: Pos,
 }
 
-py_class!(pub class Token |py| {
-    data _kind: PyString;
-    data _text: PyString;
-    data _value: PyObject;
-    data _start: Pos;
-    data _end: Pos;
-    def kind(&self) -> PyResult<PyString> {
-        Ok(self._kind(py).clone_ref(py))
+#[pymethods]
+impl Token {
+    fn kind(&self, py: Python) -> Py<PyString> {
+        self.kind.clone_ref(py)
     }
-    def text(&self) -> PyResult<PyString> {
-        Ok(self._text(py).clone_ref(py))
+    fn text(&self, py: Python) -> Py<PyString> {
+        self.text.clone_ref(py)
     }
-    def value(&self) -> PyResult<PyObject> {
-        Ok(self._value(py).clone_ref(py))
+    fn value(&self, py: Python) -> PyObject {
+        self.value.clone_ref(py)
     }
-    def start(&self) -> PyResult<PyTuple> {
-        Ok(py_pos(py, self._start(py)))
+    fn start(&self) -> PyPos {
+        py_pos(&self.start)
     }
-    def end(&self) -> PyResult<PyTuple> {
-        Ok(py_pos(py, self._end(py)))
+    fn end(&self) -> PyPos {
+        py_pos(&self.end)
     }
-    def __repr__(&self) -> PyResult<PyString> {
-        let val = self._value(py);
-        let s = if *val == py.None() {
-            format!("<Token {}>", self._kind(py).to_string(py)?)
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        let kind = self.kind.as_ref(py).to_str()?;
+        let val = self.value.as_ref(py);
+        if val.is_none() {
+            Ok(format!("<Token {}>", kind))
         } else {
-            format!("<Token {} {}>",
-                self._kind(py).to_string(py)?,
-                val.repr(py)?.to_string(py)?)
-        };
-        Ok(PyString::new(py, &s))
+            Ok(format!("<Token {} {}>", kind, val.repr()?.to_str()?))
+        }
     }
-    def __reduce__(&self) -> PyResult<PyTuple> {
-        return Ok((
+    fn __reduce__(&self, py: Python) -> PyObject {
+        (
             get_unpickle_fn(py),
             (
-                self._kind(py),
-                self._text(py),
-                self._value(py),
-                py_pos(py, self._start(py)),
-                py_pos(py, self._end(py)),
+                self.kind.clone_ref(py),
+                self.text.clone_ref(py),
+                self.value.clone_ref(py),
+                py_pos(&self.start),
+                py_pos(&self.end),
             ),
-        ).to_py_object(py))
+        ).into_py(py)
+    }
+}
+
+
+/// Token data kept until python code asks for the token itself
+struct RawToken {
+    kind: Kind,
+    /// Byte range of the token text in `TokenList::buf`
+    text: (usize, usize),
+    start: Pos,
+    end: Pos,
+}
+
+/// Sequence of tokens which creates `Token` objects on access
+///
+/// Token text is stored as ranges of a single buffer, which is the source
+/// text itself plus the text of tokens that don't come from the source
+/// (those are produced by the normalizer).
+#[pyclass(module = "edb._edgeql_rust")]
+pub struct TokenList {
+    buf: String,
+    raw: Vec<RawToken>,
+    /// Index in `raw` of each python token, as a few keywords like
+    /// `NAMED ONLY` are made of two raw tokens
+    items: Vec<usize>,
+    end_pos: Pos,
+}
+
+#[pyclass(module = "edb._edgeql_rust")]
+pub struct TokenIter {
+    list: Py<TokenList>,
+    index: usize,
+}
+
+impl TokenList {
+    /// Builds the list, failing on the same tokens that `Token` can't be
+    /// created for, so errors are reported by `tokenize()` as before
+    pub fn new(source: &str, tokens: &[CowToken], end_pos: Pos)
+        -> Result<TokenList, Error>
+    {
+        let mut buf = String::with_capacity(source.len());
+        buf.push_str(source);
+        let base = source.as_ptr() as usize;
+        let mut raw = Vec::with_capacity(tokens.len());
+        for tok in tokens {
+            validate(tok).map_err(|message| Error::Tokenizer {
+                message, start: tok.start, end: tok.end })?;
+            let ptr = tok.value.as_ptr() as usize;
+            let text = if ptr >= base
+                && ptr + tok.value.len() <= base + source.len()
+            {
+                (ptr - base, ptr - base + tok.value.len())
+            } else {
+                buf.push_str(&tok.value);
+                (buf.len() - tok.value.len(), buf.len())
+            };
+            raw.push(RawToken {
+                kind: tok.kind,
+                text,
+                start: tok.start,
+                end: tok.end,
+            });
+        }
+        let mut items = Vec::with_capacity(tokens.len());
+        let mut idx = 0;
+        while idx < tokens.len() {
+            items.push(idx);
+            if merges_next(&tokens[idx], tokens.get(idx + 1)) {
+                idx += 2;
+            } else {
+                idx += 1;
+            }
+        }
+        Ok(TokenList { buf, raw, items, end_pos })
+    }
+    fn dump(&self) -> Vec<u8> {
+        let tokens = self.raw.iter()
+            .map(|raw| self.cow_token(raw))
+            .collect::<Vec<_>>();
+        _dump_tokens(&tokens, self.end_pos)
+    }
+    fn len(&self) -> usize {
+        // plus EOF
+        self.items.len() + 1
+    }
+    fn cow_token(&self, raw: &RawToken) -> CowToken<'_> {
+        CowToken {
+            kind: raw.kind,
+            value: self.buf[raw.text.0..raw.text.1].into(),
+            start: raw.start,
+            end: raw.end,
+        }
+    }
+    fn get(&self, py: Python, index: usize) -> PyResult<Token> {
+        let tokens = tokens(py);
+        let raw_index = match self.items.get(index) {
+            Some(&raw_index) => raw_index,
+            None => {
+                return Ok(Token {
+                    kind: tokens.eof.clone_ref(py),
+                    text: tokens.empty.clone_ref(py),
+                    value: py.None(),
+                    start: self.end_pos,
+                    end: self.end_pos,
+                });
+            }
+        };
+        let mut cache = Cache {
+            decimal: None,
+            keyword_buf: String::with_capacity(MAX_KEYWORD_LENGTH),
+        };
+        let end = (raw_index + 2).min(self.raw.len());
+        let rust_tokens = self.raw[raw_index..end].iter()
+            .map(|raw| self.cow_token(raw))
+            .collect::<Vec<_>>();
+        let tok = &rust_tokens[0];
+        let mut tok_iter = rust_tokens[1..].iter().peekable();
+        let (kind, text, value) = convert(py, &tokens, &mut cache,
+                                          tok, &mut tok_iter)?;
+        Ok(Token { kind, text, value, start: tok.start, end: tok.end })
     }
-});
+}
+
+#[pymethods]
+impl TokenList {
+    fn __len__(&self) -> usize {
+        self.len()
+    }
+    fn __getitem__(&self, py: Python, index: &PyAny) -> PyResult<PyObject> {
+        if let Ok(slice) = index.downcast::<PySlice>() {
+            let indices = slice.indices(self.len() as _)?;
+            let mut items = Vec::with_capacity(indices.slicelength as usize);
+            let mut idx = indices.start;
+            for _ in 0..indices.slicelength {
+                items.push(Py::new(py, self.get(py, idx as usize)?)?);
+                idx += indices.step;
+            }
+            return Ok(PyList::new(py, items).into());
+        }
+        let mut idx: isize = index.extract()?;
+        if idx < 0 {
+            idx += self.len() as isize;
+        }
+        if idx < 0 || idx as usize >= self.len() {
+            return Err(PyIndexError::new_err("token index out of range"));
+        }
+        Ok(Py::new(py, self.get(py, idx as usize)?)?.into_py(py))
+    }
+    fn __iter__(slf: PyRef<Self>) -> TokenIter {
+        TokenIter { list: slf.into(), index: 0 }
+    }
+    fn __reduce__(&self, py: Python) -> PyObject {
+        let data = PyBytes::new(py, &self.dump());
+        (get_load_fn(py), (data,)).into_py(py)
+    }
+}
+
+#[pymethods]
+impl TokenIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+    fn __next__(mut slf: PyRefMut<Self>, py: Python)
+        -> PyResult<Option<Token>>
+    {
+        let index = slf.index;
+        let token = {
+            let list = slf.list.borrow(py);
+            if index >= list.len() {
+                return Ok(None);
+            }
+            list.get(py, index)?
+        };
+        slf.index += 1;
+        Ok(Some(token))
+    }
+}
 
 
 pub struct Tokens {
-    ident: PyString,
-    argument: PyString,
-    eof: PyString,
-    empty: PyString,
-
-    named_only: PyString,
-    named_only_val: PyString,
-    set_annotation: PyString,
-    set_annotation_val: PyString,
-    set_type: PyString,
-    set_type_val: PyString,
-
-    dot: PyString,
-    forward_link: PyString,
-    backward_link: PyString,
-    open_bracket: PyString,
-    close_bracket: PyString,
-    open_paren: PyString,
-    close_paren: PyString,
-    open_brace: PyString,
-    close_brace: PyString,
-    namespace: PyString,
-    coalesce: PyString,
-    colon: PyString,
-    semicolon: PyString,
-    comma: PyString,
-    add: PyString,
-    concat: PyString,
-    sub: PyString,
-    mul: PyString,
-    div: PyString,
-    floor_div: PyString,
-    modulo: PyString,
-    pow: PyString,
-    less: PyString,
-    greater: PyString,
-    eq: PyString,
-    ampersand: PyString,
-    pipe: PyString,
-    at: PyString,
-
-    iconst: PyString,
-    niconst: PyString,
-    fconst: PyString,
-    nfconst: PyString,
-    bconst: PyString,
-    sconst: PyString,
-    op: PyString,
-
-    greater_eq: PyString,
-    less_eq: PyString,
-    not_eq: PyString,
-    distinct_from: PyString,
-    not_distinct_from: PyString,
-
-    assign: PyString,
-    assign_op: PyString,
-    add_assign: PyString,
-    add_assign_op: PyString,
-    sub_assign: PyString,
-    sub_assign_op: PyString,
-    arrow: PyString,
-    arrow_op: PyString,
+    ident: Py<PyString>,
+    argument: Py<PyString>,
+    eof: Py<PyString>,
+    empty: Py<PyString>,
+
+    named_only: Py<PyString>,
+    named_only_val: Py<PyString>,
+    set_annotation: Py<PyString>,
+    set_annotation_val: Py<PyString>,
+    set_type: Py<PyString>,
+    set_type_val: Py<PyString>,
+
+    dot: Py<PyString>,
+    forward_link: Py<PyString>,
+    backward_link: Py<PyString>,
+    open_bracket: Py<PyString>,
+    close_bracket: Py<PyString>,
+    open_paren: Py<PyString>,
+    close_paren: Py<PyString>,
+    open_brace: Py<PyString>,
+    close_brace: Py<PyString>,
+    namespace: Py<PyString>,
+    coalesce: Py<PyString>,
+    colon: Py<PyString>,
+    semicolon: Py<PyString>,
+    comma: Py<PyString>,
+    add: Py<PyString>,
+    concat: Py<PyString>,
+    sub: Py<PyString>,
+    mul: Py<PyString>,
+    div: Py<PyString>,
+    floor_div: Py<PyString>,
+    modulo: Py<PyString>,
+    pow: Py<PyString>,
+    less: Py<PyString>,
+    greater: Py<PyString>,
+    eq: Py<PyString>,
+    ampersand: Py<PyString>,
+    pipe: Py<PyString>,
+    at: Py<PyString>,
+
+    iconst: Py<PyString>,
+    niconst: Py<PyString>,
+    fconst: Py<PyString>,
+    nfconst: Py<PyString>,
+    bconst: Py<PyString>,
+    sconst: Py<PyString>,
+    op: Py<PyString>,
+
+    greater_eq: Py<PyString>,
+    less_eq: Py<PyString>,
+    not_eq: Py<PyString>,
+    distinct_from: Py<PyString>,
+    not_distinct_from: Py<PyString>,
+
+    assign: Py<PyString>,
+    assign_op: Py<PyString>,
+    add_assign: Py<PyString>,
+    add_assign_op: Py<PyString>,
+    sub_assign: Py<PyString>,
+    sub_assign_op: Py<PyString>,
+    arrow: Py<PyString>,
+    arrow_op: Py<PyString>,
 
     keywords: HashMap<String, TokenInfo>,
     unpickle_token: PyObject,
+    load_tokens: PyObject,
 }
 
 struct Cache {
@@ -157,14 +330,72 @@ struct Cache {
 
 pub struct TokenInfo {
     pub kind: Kind,
-    pub name: PyString,
-    pub value: Option<PyString>,
+    pub name: Py<PyString>,
+    pub value: Option<Py<PyString>>,
+}
+
+pub fn init_module(py: Python, unpickle_token: PyObject,
+    load_tokens: PyObject)
+{
+    // only fails if the module is initialized twice, keep the first one
+    TOKENS.set(py, Tokens::new(py, unpickle_token, load_tokens)).ok();
+}
+
+fn tokens(py: Python) -> &Tokens {
+    TOKENS.get(py).expect("module initialized")
 }
 
-pub fn init_module(py: Python) {
-    unsafe {
-        TOKENS = Some(Tokens::new(py))
+/// Whether `convert` merges the token with the next one, see `peek_keyword`
+fn merges_next(token: &CowToken, next: Option<&CowToken>) -> bool {
+    let next = match next {
+        Some(next) if next.kind == Kind::Ident => &next.value[..],
+        _ => return false,
+    };
+    match token.kind {
+        Kind::Ident | Kind::Keyword => {}
+        _ => return false,
+    }
+    let value = &token.value[..];
+    value.eq_ignore_ascii_case("named") && next.eq_ignore_ascii_case("only")
+    || value.eq_ignore_ascii_case("set") && (
+        next.eq_ignore_ascii_case("annotation") ||
+        next.eq_ignore_ascii_case("type"))
+}
+
+/// Checks the token for the errors `convert` can fail with
+fn validate(token: &CowToken) -> Result<(), String> {
+    use Kind::*;
+    let value = &token.value[..];
+    match token.kind {
+        FloatConst => float_value(value).map(|_| ()),
+        IntConst => int_value(value).map(|_| ()),
+        BinStr => unquote_bytes(value).map(|_| ()).map_err(|e| e.to_string()),
+        Str => unquote_string(value).map(|_| ()).map_err(|e| e.to_string()),
+        Error | Whitespace | Comment => {
+            Err(format!("unexpected token {:?}", value))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn float_value(value: &str) -> Result<f64, String> {
+    let float_value = f64::from_str(&value.replace("_", ""))
+        .map_err(|e| format!("error reading std::float64: {}", e))?;
+    if float_value == f64::INFINITY || float_value == -f64::INFINITY {
+        return Err("number is out of range for std::float64".into());
     }
+    Ok(float_value)
+}
+
+fn int_value(value: &str) -> Result<u64, String> {
+    // We read unsigned here, because unary minus will only
+    // be identified on the parser stage. And there is a number
+    // -9223372036854775808 which can't be represented in
+    // i64 as absolute (positive) value.
+    // Python has no problem of representing such a positive
+    // value, though.
+    u64::from_str(&value.replace("_", ""))
+        .map_err(|e| format!("error reading int: {}", e))
 }
 
 fn peek_keyword(iter: &mut Peekable<Iter<CowToken>>, kw: &str) -> bool {
@@ -173,28 +404,25 @@ fn peek_keyword(iter: &mut Peekable<Iter<CowToken>>, kw: &str) -> bool {
        .unwrap_or(false)
 }
 
-pub fn _unpickle_token(py: Python,
-        kind: &PyString, text: &PyString, value: &PyObject,
-        start: &PyObject, end: &PyObject)
-        -> PyResult<Token>
+#[pyfunction]
+pub fn _unpickle_token(kind: Py<PyString>, text: Py<PyString>,
+    value: PyObject, start: PyPos, end: PyPos)
+    -> Token
 {
-    // TODO(tailhook) We might some strings from Tokens structure
-    //                (i.e. internning them).
-    //                But if we're storing a collection of tokens
-    //                they will store the tokens only once, so it
-    //                doesn't seem to help that much.
-    Token::create_instance(py,
-        kind.clone_ref(py),
-        text.clone_ref(py),
-        value.clone_ref(py),
-        rs_pos(py, start)?,
-        rs_pos(py, end)?)
+    // Only used for single tokens, `TokenList` is pickled as a whole
+    // with `dump_tokens`, which stores every distinct string once
+    Token {
+        kind,
+        text,
+        value,
+        start: rs_pos(start),
+        end: rs_pos(end),
+    }
 }
 
-pub fn tokenize(py: Python, s: &PyString) -> PyResult<PyList> {
-    let data = s.to_string(py)?;
-
-    let mut token_stream = TokenStream::new(&data[..]);
+#[pyfunction]
+pub fn tokenize(py: Python, data: &str) -> PyResult<Py<TokenList>> {
+    let mut token_stream = TokenStream::new(data);
     let rust_tokens: Vec<_> = py.allow_threads(|| {
         let mut tokens = Vec::new();
         for res in &mut token_stream {
@@ -212,111 +440,128 @@ pub fn tokenize(py: Python, s: &PyString) -> PyResult<PyList> {
             Unexpected(s) => s.to_string(),
             o => o.to_string(),
         };
-        TokenizerError::new(py, (err, py_pos(py, &pos)))
+        TokenizerError::new_err((err, py_pos(&pos)))
     })?;
-    return convert_tokens(py, rust_tokens, token_stream.current_pos());
+    let end_pos = token_stream.current_pos();
+    let list = py.allow_threads(|| {
+        TokenList::new(data, &rust_tokens, end_pos)
+    }).map_err(py_error)?;
+    Py::new(py, list)
 }
 
-pub fn convert_tokens(py: Python, rust_tokens: Vec<CowToken<'_>>,
-    end_pos: Pos)
-    -> PyResult<PyList>
+/// Tokenizes a list of queries, returning a list of tokens or
+/// a `TokenizerError` instance for each of them
+#[pyfunction(texts, "*", parallel = "false")]
+pub fn tokenize_many(py: Python, texts: Vec<&str>, parallel: bool)
+    -> PyResult<Py<PyList>>
 {
-    let tokens = unsafe { TOKENS.as_ref().expect("module initialized") };
-    let mut cache = Cache {
-        decimal: None,
-        keyword_buf: String::with_capacity(MAX_KEYWORD_LENGTH),
-    };
-    let mut buf = Vec::with_capacity(rust_tokens.len());
-    let mut tok_iter = rust_tokens.iter().peekable();
-    while let Some(tok) = tok_iter.next() {
-        let (name, text, value) = convert(py, &tokens, &mut cache,
-                                          tok, &mut tok_iter)?;
-        let py_tok = Token::create_instance(py, name, text, value,
-            tok.start, tok.end)?;
+    let results = py.allow_threads(|| {
+        texts.iter().zip(_tokenize_many(&texts, parallel))
+            .map(|(text, res)| res.and_then(|(tokens, end_pos)| {
+                TokenList::new(text, &tokens, end_pos)
+            }))
+            .collect::<Vec<_>>()
+    });
+    let results = results.into_iter()
+        .map(|res| match res {
+            Ok(list) => Ok(Py::new(py, list)?.into_py(py)),
+            Err(e) => Ok(py_error(e).into_py(py)),
+        })
+        .collect::<PyResult<Vec<PyObject>>>()?;
+    Ok(PyList::new(py, results).into())
+}
 
-        buf.push(py_tok.into_object());
-    }
-    buf.push(Token::create_instance(py,
-        tokens.eof.clone_ref(py),
-        tokens.empty.clone_ref(py),
-        py.None(),
-        end_pos, end_pos)?
-        .into_object());
-    Ok(PyList::new(py, &buf[..]))
+/// Encodes the token list into compact bytes, see `load_tokens`
+#[pyfunction]
+pub fn dump_tokens(py: Python, tokens: PyRef<TokenList>) -> Py<PyBytes> {
+    PyBytes::new(py, &tokens.dump()).into()
+}
+
+/// Decodes a token list encoded by `dump_tokens`
+#[pyfunction]
+pub fn load_tokens(py: Python, data: &[u8]) -> PyResult<TokenList> {
+    let loaded = _load_tokens(data).map_err(PyValueError::new_err)?;
+    py.allow_threads(|| {
+        TokenList::new(loaded.strings, &loaded.tokens, loaded.end_pos)
+    }).map_err(py_error)
 }
 
 
 impl Tokens {
-    pub fn new(py: Python) -> Tokens {
+    pub fn new(py: Python, unpickle_token: PyObject, load_tokens: PyObject)
+        -> Tokens
+    {
+        let s = |value: &str| -> Py<PyString> {
+            PyString::new(py, value).into()
+        };
         let mut res = Tokens {
-            ident: PyString::new(py, "IDENT"),
-            argument: PyString::new(py, "ARGUMENT"),
-            eof: PyString::new(py, "EOF"),
-            empty: PyString::new(py, ""),
-            named_only: PyString::new(py, "NAMEDONLY"),
-            named_only_val: PyString::new(py, "NAMED ONLY"),
-            set_annotation: PyString::new(py, "SETANNOTATION"),
-            set_annotation_val: PyString::new(py, "SET ANNOTATION"),
-            set_type: PyString::new(py, "SETTYPE"),
-            set_type_val: PyString::new(py, "SET TYPE"),
-
-            dot: PyString::new(py, "."),
-            forward_link: PyString::new(py, ".>"),
-            backward_link: PyString::new(py, ".<"),
-            open_bracket: PyString::new(py, "["),
-            close_bracket: PyString::new(py, "]"),
-            open_paren: PyString::new(py, "("),
-            close_paren: PyString::new(py, ")"),
-            open_brace: PyString::new(py, "{"),
-            close_brace: PyString::new(py, "}"),
-            namespace: PyString::new(py, "::"),
-            coalesce: PyString::new(py, "??"),
-            colon: PyString::new(py, ":"),
-            semicolon: PyString::new(py, ";"),
-            comma: PyString::new(py, ","),
-            add: PyString::new(py, "+"),
-            concat: PyString::new(py, "++"),
-            sub: PyString::new(py, "-"),
-            mul: PyString::new(py, "*"),
-            div: PyString::new(py, "/"),
-            floor_div: PyString::new(py, "//"),
-            modulo: PyString::new(py, "%"),
-            pow: PyString::new(py, "^"),
-            less: PyString::new(py, "<"),
-            greater: PyString::new(py, ">"),
-            eq: PyString::new(py, "="),
-            ampersand: PyString::new(py, "&"),
-            pipe: PyString::new(py, "|"),
-            at: PyString::new(py, "@"),
-
-            iconst: PyString::new(py, "ICONST"),
-            niconst: PyString::new(py, "NICONST"),
-            fconst: PyString::new(py, "FCONST"),
-            nfconst: PyString::new(py, "NFCONST"),
-            bconst: PyString::new(py, "BCONST"),
-            sconst: PyString::new(py, "SCONST"),
-            op: PyString::new(py, "OP"),
+            ident: s("IDENT"),
+            argument: s("ARGUMENT"),
+            eof: s("EOF"),
+            empty: s(""),
+            named_only: s("NAMEDONLY"),
+            named_only_val: s("NAMED ONLY"),
+            set_annotation: s("SETANNOTATION"),
+            set_annotation_val: s("SET ANNOTATION"),
+            set_type: s("SETTYPE"),
+            set_type_val: s("SET TYPE"),
+
+            dot: s("."),
+            forward_link: s(".>"),
+            backward_link: s(".<"),
+            open_bracket: s("["),
+            close_bracket: s("]"),
+            open_paren: s("("),
+            close_paren: s(")"),
+            open_brace: s("{"),
+            close_brace: s("}"),
+            namespace: s("::"),
+            coalesce: s("??"),
+            colon: s(":"),
+            semicolon: s(";"),
+            comma: s(","),
+            add: s("+"),
+            concat: s("++"),
+            sub: s("-"),
+            mul: s("*"),
+            div: s("/"),
+            floor_div: s("//"),
+            modulo: s("%"),
+            pow: s("^"),
+            less: s("<"),
+            greater: s(">"),
+            eq: s("="),
+            ampersand: s("&"),
+            pipe: s("|"),
+            at: s("@"),
+
+            iconst: s("ICONST"),
+            niconst: s("NICONST"),
+            fconst: s("FCONST"),
+            nfconst: s("NFCONST"),
+            bconst: s("BCONST"),
+            sconst: s("SCONST"),
+            op: s("OP"),
 
             // as OP
-            greater_eq: PyString::new(py, ">="),
-            less_eq: PyString::new(py, "<="),
-            not_eq: PyString::new(py, "!="),
-            distinct_from: PyString::new(py, "?!="),
-            not_distinct_from: PyString::new(py, "?="),
-
-            assign: PyString::new(py, "ASSIGN"),
-            assign_op: PyString::new(py, ":="),
-            add_assign: PyString::new(py, "ADDASSIGN"),
-            add_assign_op: PyString::new(py, "+="),
-            sub_assign: PyString::new(py, "REMASSIGN"),
-            sub_assign_op: PyString::new(py, "-="),
-            arrow: PyString::new(py, "ARROW"),
-            arrow_op: PyString::new(py, "->"),
+            greater_eq: s(">="),
+            less_eq: s("<="),
+            not_eq: s("!="),
+            distinct_from: s("?!="),
+            not_distinct_from: s("?="),
+
+            assign: s("ASSIGN"),
+            assign_op: s(":="),
+            add_assign: s("ADDASSIGN"),
+            add_assign_op: s("+="),
+            sub_assign: s("REMASSIGN"),
+            sub_assign_op: s("-="),
+            arrow: s("ARROW"),
+            arrow_op: s("->"),
 
             keywords: HashMap::new(),
-            unpickle_token: py_fn!(py, _unpickle_token(
-                kind: &PyString, text: &PyString, value: &PyObject,
-                start: &PyObject, end: &PyObject)),
+            unpickle_token,
+            load_tokens,
         };
         // 'EOF'
         for kw in UNRESERVED_KEYWORDS.iter() {
@@ -331,13 +576,12 @@ impl Tokens {
         return res;
     }
     fn add_kw(&mut self, py: Python, name: &str) {
-        let py_name = PyString::new(py, &name.to_ascii_uppercase());
         let tok_name = if name.starts_with("__") && name.ends_with("__") {
             format!("DUNDER{}", name[2..name.len()-2].to_ascii_uppercase())
-            .to_py_object(py)
         } else {
-            py_name.clone_ref(py)
+            name.to_ascii_uppercase()
         };
+        let tok_name = PyString::new(py, &tok_name).into();
         self.keywords.insert(name.into(), TokenInfo {
             kind: if is_keyword(name) { Kind::Keyword } else { Kind::Ident },
             name: tok_name,
@@ -351,9 +595,8 @@ impl Cache {
         if let Some(ref mut d) = self.decimal {
             return Ok(d);
         }
-        let module = py.import("decimal")?;
-        let typ = module.get(py, "Decimal")?;
-        self.decimal = Some(typ);
+        let typ = py.import("decimal")?.getattr("Decimal")?;
+        self.decimal = Some(typ.into());
         Ok(self.decimal.as_mut().unwrap())
     }
 }
@@ -362,7 +605,7 @@ impl Cache {
 fn convert(py: Python, tokens: &Tokens, cache: &mut Cache,
     token: &CowToken,
     tok_iter: &mut Peekable<Iter<CowToken>>)
-    -> PyResult<(PyString, PyString, PyObject)>
+    -> PyResult<(Py<PyString>, Py<PyString>, PyObject)>
 {
     use Kind::*;
     let value = &token.value[..];
@@ -481,88 +724,80 @@ fn convert(py: Python, tokens: &Tokens, cache: &mut Cache,
         Argument => {
             if value[1..].starts_with('`') {
                 Ok((tokens.argument.clone_ref(py),
-                    PyString::new(py, value),
+                    PyString::new(py, value).into(),
                     PyString::new(py, &value[2..value.len()-1]
                                      .replace("``", "`"))
-                   .into_object()))
+                   .into()))
             } else {
                 Ok((tokens.argument.clone_ref(py),
-                    PyString::new(py, value),
+                    PyString::new(py, value).into(),
                     PyString::new(py, &value[1..])
-                    .into_object()))
+                    .into()))
             }
         }
         DecimalConst => {
             Ok((tokens.nfconst.clone_ref(py),
-                PyString::new(py, value),
-                cache.decimal(py)?.call(py,
-                    (&value[..value.len()-1].replace("_", ""),), None)?))
+                PyString::new(py, value).into(),
+                cache.decimal(py)?.call1(py,
+                    (&value[..value.len()-1].replace("_", ""),))?))
         }
         FloatConst => {
-            let float_value = f64::from_str(&value.replace("_", ""))
-                .map_err(|e| TokenizerError::new(py,
-                    (format!("error reading std::float64: {}", e),
-                     py_pos(py, &token.start))))?;
-            if float_value == f64::INFINITY || float_value == -f64::INFINITY {
-                return Err(TokenizerError::new(py,
-                    (format!("number is out of range for std::float64"),
-                     py_pos(py, &token.start))))?;
-            }
+            let float_value = float_value(value)
+                .map_err(|e| TokenizerError::new_err(
+                    (e, py_pos(&token.start))))?;
             Ok((tokens.fconst.clone_ref(py),
-                PyString::new(py, value),
-                float_value.to_py_object(py).into_object()))
+                PyString::new(py, value).into(),
+                float_value.into_py(py)))
         }
         IntConst => {
             Ok((tokens.iconst.clone_ref(py),
-                PyString::new(py, value),
-                // We read unsigned here, because unary minus will only
-                // be identified on the parser stage. And there is a number
-                // -9223372036854775808 which can't be represented in
-                // i64 as absolute (positive) value.
-                // Python has no problem of representing such a positive
-                // value, though.
-                u64::from_str(&value.replace("_", ""))
-                .map_err(|e| TokenizerError::new(py,
-                    (format!("error reading int: {}", e),
-                     py_pos(py, &token.start))))?
-               .to_py_object(py)
-               .into_object()))
+                PyString::new(py, value).into(),
+                int_value(value)
+                .map_err(|e| TokenizerError::new_err(
+                    (e, py_pos(&token.start))))?
+               .into_py(py)))
         }
         BigIntConst => {
             Ok((tokens.niconst.clone_ref(py),
-                PyString::new(py, value),
-                py.get_type::<PyInt>().call(py,
-                    (&value[..value.len()-1].replace("_", ""),), None)?))
+                PyString::new(py, value).into(),
+                py.get_type::<PyLong>().call1(
+                    (&value[..value.len()-1].replace("_", ""),))?.into()))
         }
         BinStr => {
             Ok((tokens.bconst.clone_ref(py),
-                PyString::new(py, value),
+                PyString::new(py, value).into(),
                 PyBytes::new(py,
-                    &unquote_bytes(&value[2..value.len()-1])
-                    .map_err(|s| TokenizerError::new(py,
-                        (s, py_pos(py, &token.start))))?)
-                   .into_object()))
+                    &unquote_bytes(value)
+                    .map_err(|s| TokenizerError::new_err(
+                        (s.to_string(), py_pos(&token.start))))?)
+                   .into()))
         }
         Str => {
             let content = unquote_string(value)
-                .map_err(|s| TokenizerError::new(py,
-                    (s.to_string(), py_pos(py, &token.start))))?;
+                .map_err(|s| TokenizerError::new_err(
+                    (s.to_string(), py_pos(&token.start))))?;
             Ok((tokens.sconst.clone_ref(py),
-                PyString::new(py, value),
-                PyString::new(py, &content).into_object()))
+                PyString::new(py, value).into(),
+                PyString::new(py, &content).into()))
         },
         BacktickName => {
             Ok((tokens.ident.clone_ref(py),
-                PyString::new(py, value),
+                PyString::new(py, value).into(),
                 PyString::new(py, &value[1..value.len()-1].replace("``", "`"))
-               .into_object()))
+               .into()))
+        }
+        Error | Whitespace | Comment => {
+            // only emitted by the stream in the recovery and lossless modes
+            Err(TokenizerError::new_err(
+                (format!("unexpected token {:?}", value),
+                 py_pos(&token.start))))
         }
         Ident | Keyword => {
             if value.len() > MAX_KEYWORD_LENGTH {
                 let val = PyString::new(py, value);
                 Ok((tokens.ident.clone_ref(py),
-                    val.clone_ref(py),
-                    val.into_object()))
+                    val.into(),
+                    val.into()))
             } else {
                 cache.keyword_buf.clear();
                 cache.keyword_buf.push_str(value);
@@ -590,15 +825,15 @@ fn convert(py: Python, tokens: &Tokens, cache: &mut Cache,
                         Some(tok_info) => {
                             debug_assert_eq!(tok_info.kind, token.kind);
                             Ok((tok_info.name.clone_ref(py),
-                                 PyString::new(py, value),
+                                 PyString::new(py, value).into(),
                                  py.None()))
                         }
                         None => {
                             debug_assert_eq!(token.kind, Kind::Ident);
                             let val = PyString::new(py, value);
                             Ok((tokens.ident.clone_ref(py),
-                                val.clone_ref(py),
-                                val.into_object()))
+                                val.into(),
+                                val.into()))
                         }
                     },
                 }
@@ -608,111 +843,9 @@ fn convert(py: Python, tokens: &Tokens, cache: &mut Cache,
 }
 
 pub fn get_unpickle_fn(py: Python) -> PyObject {
-    let tokens = unsafe { TOKENS.as_ref().expect("module initialized") };
-    return tokens.unpickle_token.clone_ref(py);
-}
-
-impl<'a, 'b: 'a> From<&'a SpannedToken<'b>> for CowToken<'b> {
-    fn from(t: &'a SpannedToken<'b>) -> CowToken<'b> {
-        CowToken {
-            kind: t.token.kind,
-            value: t.token.value.into(),
-            start: t.start,
-            end: t.end,
-        }
-    }
-}
-
-impl<'a> From<SpannedToken<'a>> for CowToken<'a> {
-    fn from(t: SpannedToken<'a>) -> CowToken<'a> {
-        CowToken::from(&t)
-    }
-}
-
-fn unquote_bytes<'a>(s: &'a str) -> Result<Vec<u8>, String> {
-    let mut res = Vec::with_capacity(s.len());
-    let mut bytes = s.as_bytes().iter();
-    while let Some(&c) = bytes.next() {
-        match c {
-            b'\\' => {
-                match *bytes.next().expect("slash cant be at the end") {
-                    c@b'"' | c@b'\\' | c@b'/' | c@b'\'' => res.push(c),
-                    b'b' => res.push(b'\x10'),
-                    b'f' => res.push(b'\x0C'),
-                    b'n' => res.push(b'\n'),
-                    b'r' => res.push(b'\r'),
-                    b't' => res.push(b'\t'),
-                    b'x' => {
-                        let tail = &s[s.len() - bytes.as_slice().len()..];
-                        let hex = tail.get(0..2);
-                        let code = hex.and_then(|s| {
-                            u8::from_str_radix(s, 16).ok()
-                        }).ok_or_else(|| {
-                            format!("invalid bytes literal: \
-                                invalid escape sequence '\\x{}'",
-                                hex.unwrap_or_else(|| tail).escape_debug())
-                        })?;
-                        res.push(code);
-                        bytes.nth(1);
-                    }
-                    b'\r' | b'\n' => {
-                        let nskip = bytes.as_slice()
-                            .iter()
-                            .take_while(|&&x| x.is_ascii_whitespace())
-                            .count();
-                        if nskip > 0 {
-                            bytes.nth(nskip-1);
-                        }
-                    }
-                    c => {
-                        let ch = if c < 0x7f {
-                            c as char
-                        } else {
-                            // recover the unicode byte
-                            s[s.len()-bytes.as_slice().len()-1..]
-                            .chars().next().unwrap()
-                        };
-                        return Err(format!("invalid bytes literal: \
-                            invalid escape sequence '\\{}'",
-                           ch.escape_debug()));
-                    }
-                }
-            }
-            c => res.push(c),
-        }
-    }
-
-    Ok(res)
-}
-
-#[test]
-fn simple_bytes() {
-    assert_eq!(unquote_bytes(r#"\x09"#).unwrap(), b"\x09");
-    assert_eq!(unquote_bytes(r#"\x0A"#).unwrap(), b"\x0A");
-    assert_eq!(unquote_bytes(r#"\x0D"#).unwrap(), b"\x0D");
-    assert_eq!(unquote_bytes(r#"\x20"#).unwrap(), b"\x20");
-}
-
-#[test]
-fn newline_escaping_bytes() {
-    assert_eq!(unquote_bytes(r"hello \
-                                world").unwrap(), b"hello world");
-
-    assert_eq!(unquote_bytes(r"bb\
-aa \
-            bb").unwrap(), b"bbaa bb");
-    assert_eq!(unquote_bytes(r"bb\
-
-        aa").unwrap(), b"bbaa");
-    assert_eq!(unquote_bytes(r"bb\
-        \
-        aa").unwrap(), b"bbaa");
-    assert_eq!(unquote_bytes("bb\\\r   aa").unwrap(), b"bbaa");
-    assert_eq!(unquote_bytes("bb\\\r\n   aa").unwrap(), b"bbaa");
+    return tokens(py).unpickle_token.clone_ref(py);
 }
 
-#[test]
-fn complex_bytes() {
-    assert_eq!(unquote_bytes(r#"\x09 hello \x0A there"#).unwrap(),
-        b"\x09 hello \x0A there");
+pub fn get_load_fn(py: Python) -> PyObject {
+    tokens(py).load_tokens.clone_ref(py)
 }
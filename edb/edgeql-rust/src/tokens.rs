@@ -0,0 +1,316 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str;
+
+use edgeql_parser::tokenizer::{Kind, SpannedToken, TokenStream};
+use edgeql_parser::position::Pos;
+
+const DUMP_VERSION: u8 = 1;
+/// Set in the kind byte when the end position is not stored, because it
+/// can be calculated from the start and the value of the token
+const IMPLIED_END: u8 = 0x80;
+
+/// All token kinds, index in this list is the kind byte of `dump_tokens`
+pub const KINDS: &[Kind] = &[
+    Kind::Assign,
+    Kind::SubAssign,
+    Kind::AddAssign,
+    Kind::Arrow,
+    Kind::Coalesce,
+    Kind::Namespace,
+    Kind::ForwardLink,
+    Kind::BackwardLink,
+    Kind::FloorDiv,
+    Kind::Concat,
+    Kind::GreaterEq,
+    Kind::LessEq,
+    Kind::NotEq,
+    Kind::NotDistinctFrom,
+    Kind::DistinctFrom,
+    Kind::Comma,
+    Kind::OpenParen,
+    Kind::CloseParen,
+    Kind::OpenBracket,
+    Kind::CloseBracket,
+    Kind::OpenBrace,
+    Kind::CloseBrace,
+    Kind::Dot,
+    Kind::Semicolon,
+    Kind::Colon,
+    Kind::Add,
+    Kind::Sub,
+    Kind::Mul,
+    Kind::Div,
+    Kind::Modulo,
+    Kind::Pow,
+    Kind::Less,
+    Kind::Greater,
+    Kind::Eq,
+    Kind::Ampersand,
+    Kind::Pipe,
+    Kind::At,
+    Kind::Argument,
+    Kind::DecimalConst,
+    Kind::FloatConst,
+    Kind::IntConst,
+    Kind::BigIntConst,
+    Kind::BinStr,
+    Kind::Str,
+    Kind::BacktickName,
+    Kind::Keyword,
+    Kind::Ident,
+    Kind::Error,
+    Kind::Whitespace,
+    Kind::Comment,
+];
+
+
+#[derive(Debug, Clone)]
+pub struct CowToken<'a> {
+    pub kind: Kind,
+    pub value: Cow<'a, str>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl<'a, 'b: 'a> From<&'a SpannedToken<'b>> for CowToken<'b> {
+    fn from(t: &'a SpannedToken<'b>) -> CowToken<'b> {
+        CowToken {
+            kind: t.token.kind,
+            value: t.token.value.into(),
+            start: t.start,
+            end: t.end,
+        }
+    }
+}
+
+impl<'a> From<SpannedToken<'a>> for CowToken<'a> {
+    fn from(t: SpannedToken<'a>) -> CowToken<'a> {
+        CowToken::from(&t)
+    }
+}
+
+/// Tokens decoded by `load_tokens`
+#[derive(Debug)]
+pub struct LoadedTokens<'a> {
+    /// Distinct token values concatenated, every token value is a slice
+    /// of this string
+    pub strings: &'a str,
+    pub tokens: Vec<CowToken<'a>>,
+    pub end_pos: Pos,
+}
+
+/// Encodes the tokens into a compact binary form
+///
+/// The layout is a version byte, the string table and then the tokens. The
+/// string table is a count of distinct token values, their byte lengths and
+/// then all the values concatenated. Each token is a kind byte (an index in
+/// `KINDS`), an index in the string table and the start and end positions.
+/// The end position is omitted if the token doesn't span lines and its
+/// value is the text of the token, which is true for all tokens except
+/// the ones produced by the normalizer. The end position of the whole text
+/// follows the tokens.
+///
+/// All numbers are LEB128 varints. Positions are stored as zigzag-encoded
+/// deltas of line, column and offset from the previous position, so most
+/// of them take a byte each.
+pub fn dump_tokens(tokens: &[CowToken], end_pos: Pos) -> Vec<u8> {
+    let mut indices = HashMap::new();
+    let mut strings = Vec::new();
+    let mut token_strings = Vec::with_capacity(tokens.len());
+    for tok in tokens {
+        let value = &tok.value[..];
+        let index = *indices.entry(value).or_insert_with(|| {
+            strings.push(value);
+            strings.len() - 1
+        });
+        token_strings.push(index);
+    }
+
+    let mut buf = Vec::new();
+    buf.push(DUMP_VERSION);
+    write_varint(&mut buf, strings.len() as u64);
+    for value in &strings {
+        write_varint(&mut buf, value.len() as u64);
+    }
+    for value in &strings {
+        buf.extend_from_slice(value.as_bytes());
+    }
+    write_varint(&mut buf, tokens.len() as u64);
+    let mut pos = Pos { line: 1, column: 1, offset: 0 };
+    for (tok, index) in tokens.iter().zip(token_strings) {
+        let implied = implied_end(&tok.start, &tok.value) == tok.end;
+        buf.push(tok.kind as u8 | if implied { IMPLIED_END } else { 0 });
+        write_varint(&mut buf, index as u64);
+        write_pos(&mut buf, &pos, &tok.start);
+        if !implied {
+            write_pos(&mut buf, &tok.start, &tok.end);
+        }
+        pos = tok.end;
+    }
+    write_pos(&mut buf, &pos, &end_pos);
+    buf
+}
+
+/// Decodes tokens encoded by `dump_tokens`
+///
+/// Token values borrow from `data`, nothing is copied. Values of literals
+/// are checked to be valid tokens of their kind, so the result can be
+/// converted like the output of the tokenizer.
+pub fn load_tokens(data: &[u8]) -> Result<LoadedTokens<'_>, String> {
+    let mut buf = data;
+    match take_byte(&mut buf)? {
+        DUMP_VERSION => {}
+        version => {
+            return Err(format!("unsupported token dump version {}",
+                               version));
+        }
+    }
+    let num_strings = take_len(&mut buf)?;
+    let mut lengths = Vec::with_capacity(num_strings.min(buf.len()));
+    let mut total = 0usize;
+    for _ in 0..num_strings {
+        let len = take_len(&mut buf)?;
+        total = total.checked_add(len)
+            .ok_or_else(|| "string table is too large".to_string())?;
+        lengths.push(len);
+    }
+    if buf.len() < total {
+        return Err("unexpected end of data".into());
+    }
+    let strings = str::from_utf8(&buf[..total])
+        .map_err(|e| format!("invalid string table: {}", e))?;
+    buf = &buf[total..];
+    let mut values = Vec::with_capacity(lengths.len());
+    let mut offset = 0;
+    for len in lengths {
+        let value = strings.get(offset..offset+len)
+            .ok_or_else(|| "string is not at a char boundary".to_string())?;
+        values.push(value);
+        offset += len;
+    }
+
+    let num_tokens = take_len(&mut buf)?;
+    let mut tokens = Vec::with_capacity(num_tokens.min(buf.len()));
+    let mut pos = Pos { line: 1, column: 1, offset: 0 };
+    for _ in 0..num_tokens {
+        let byte = take_byte(&mut buf)?;
+        let kind = byte & !IMPLIED_END;
+        let kind = *KINDS.get(kind as usize)
+            .ok_or_else(|| format!("invalid token kind {}", kind))?;
+        let index = take_len(&mut buf)?;
+        let value = *values.get(index)
+            .ok_or_else(|| format!("invalid string index {}", index))?;
+        check_value(kind, value)?;
+        let start = take_pos(&mut buf, &pos)?;
+        let end = if byte & IMPLIED_END != 0 {
+            implied_end(&start, value)
+        } else {
+            take_pos(&mut buf, &start)?
+        };
+        tokens.push(CowToken { kind, value: value.into(), start, end });
+        pos = end;
+    }
+    let end_pos = take_pos(&mut buf, &pos)?;
+    if !buf.is_empty() {
+        return Err(format!("{} bytes left after tokens", buf.len()));
+    }
+    Ok(LoadedTokens { strings, tokens, end_pos })
+}
+
+fn check_value(kind: Kind, value: &str) -> Result<(), String> {
+    use Kind::*;
+    match kind {
+        // unquoting and conversion slice these values assuming they come
+        // from the tokenizer, so the value must be exactly one such token
+        Str | BinStr | Argument | DecimalConst | BigIntConst | BacktickName
+        => {
+            let mut stream = TokenStream::new(value);
+            match ((&mut stream).next(), (&mut stream).next()) {
+                (Some(Ok(tok)), None)
+                if tok.token.kind == kind && tok.token.value == value
+                => Ok(()),
+                _ => Err(format!("invalid {:?} token {:?}", kind, value)),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn implied_end(start: &Pos, value: &str) -> Pos {
+    Pos {
+        line: start.line,
+        column: start.column.wrapping_add(value.chars().count()),
+        offset: start.offset.wrapping_add(value.len() as u64),
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_delta(buf: &mut Vec<u8>, prev: u64, value: u64) {
+    let delta = value.wrapping_sub(prev) as i64;
+    write_varint(buf, ((delta << 1) ^ (delta >> 63)) as u64);
+}
+
+fn write_pos(buf: &mut Vec<u8>, prev: &Pos, pos: &Pos) {
+    write_delta(buf, prev.line as u64, pos.line as u64);
+    write_delta(buf, prev.column as u64, pos.column as u64);
+    write_delta(buf, prev.offset, pos.offset);
+}
+
+fn take_byte(buf: &mut &[u8]) -> Result<u8, String> {
+    let (&byte, rest) = buf.split_first()
+        .ok_or_else(|| "unexpected end of data".to_string())?;
+    *buf = rest;
+    Ok(byte)
+}
+
+fn take_varint(buf: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take_byte(buf)?;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint is too long".into())
+}
+
+fn take_len(buf: &mut &[u8]) -> Result<usize, String> {
+    usize::try_from(take_varint(buf)?)
+        .map_err(|_| "length is out of range".to_string())
+}
+
+fn take_delta(buf: &mut &[u8], prev: u64, name: &str)
+    -> Result<u64, String>
+{
+    let value = take_varint(buf)?;
+    let delta = value >> 1;
+    let result = if value & 1 == 0 {
+        prev.checked_add(delta)
+    } else {
+        prev.checked_sub(delta + 1)
+    };
+    result.ok_or_else(|| format!("{} is out of range", name))
+}
+
+fn take_pos(buf: &mut &[u8], prev: &Pos) -> Result<Pos, String> {
+    let line = take_delta(buf, prev.line as u64, "line")?;
+    let column = take_delta(buf, prev.column as u64, "column")?;
+    let offset = take_delta(buf, prev.offset, "offset")?;
+    Ok(Pos {
+        line: usize::try_from(line)
+            .map_err(|_| "line is out of range".to_string())?,
+        column: usize::try_from(column)
+            .map_err(|_| "column is out of range".to_string())?,
+        offset,
+    })
+}
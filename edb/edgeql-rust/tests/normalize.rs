@@ -1,4 +1,11 @@
-use edgeql_rust::normalize::{normalize, Value, Variable};
+use edgeql_rust::normalize::{normalize, Error, Value, Variable};
+use edgeql_rust::normalize::{normalize_with_options, NormalizeOptions};
+use edgeql_rust::normalize::normalize_script;
+use edgeql_rust::normalize::{normalize_many, tokenize_many};
+use edgeql_rust::normalize::{denormalize, denormalize_key};
+use edgeql_rust::normalize::{serialize_extra, deserialize_extra, ArgType};
+use edgeql_rust::normalize::{Literal, Preserve};
+use edgeql_parser::position::Pos;
 
 
 #[test]
@@ -171,3 +178,516 @@ fn test_tuple_access() {
         },
     ]);
 }
+
+#[test]
+fn test_bytes() {
+    let entry = normalize(r###"
+        SELECT b"x\x01" ++ b'y\n'
+    "###).unwrap();
+    assert_eq!(entry.key,
+        "SELECT(<__std__::bytes>$0)++(<__std__::bytes>$1)");
+    assert_eq!(entry.variables, vec![
+        Variable {
+            value: Value::Bytes(b"x\x01".to_vec()),
+        },
+        Variable {
+            value: Value::Bytes(b"y\n".to_vec()),
+        }
+    ]);
+}
+
+fn extract_bool() -> NormalizeOptions {
+    let mut options = NormalizeOptions::default();
+    options.extract.insert(Literal::Bool);
+    options
+}
+
+#[test]
+fn test_bool() {
+    let entry = normalize(r###"
+        SELECT User { active := true } FILTER .flag = False
+    "###).unwrap();
+    assert_eq!(entry.key, "SELECT User{active:=true}FILTER.flag=False");
+    assert_eq!(entry.variables, vec![]);
+
+    let entry = normalize_with_options(r###"
+        SELECT User { active := true } FILTER .flag = False
+    "###, &extract_bool()).unwrap();
+    assert_eq!(entry.key,
+        "SELECT User{active:=(<__std__::bool>$0)}\
+         FILTER.flag=(<__std__::bool>$1)");
+    assert_eq!(entry.variables, vec![
+        Variable {
+            value: Value::Bool(true),
+        },
+        Variable {
+            value: Value::Bool(false),
+        }
+    ]);
+}
+
+#[test]
+fn test_constant_bool() {
+    let entry = normalize_with_options(r###"
+        SELECT 1 IF true ELSE 2
+    "###, &extract_bool()).unwrap();
+    assert_eq!(entry.key,
+        "SELECT(<__std__::int64>$0)IF true ELSE(<__std__::int64>$1)");
+    assert_eq!(entry.variables, vec![
+        Variable {
+            value: Value::Int(1),
+        },
+        Variable {
+            value: Value::Int(2),
+        }
+    ]);
+}
+
+#[test]
+fn test_if_not_bool() {
+    let entry = normalize(r###"
+        SELECT 1 IF 2 > x ELSE 3
+    "###).unwrap();
+    assert_eq!(entry.key,
+        "SELECT(<__std__::int64>$0)IF(<__std__::int64>$1)>x \
+         ELSE(<__std__::int64>$2)");
+    assert_eq!(entry.variables.len(), 3);
+    assert_eq!(entry.variables[1].value, Value::Int(2));
+
+    let entry = normalize(r###"
+        SELECT 'a' IF 'b' = x ELSE 'c'
+    "###).unwrap();
+    assert_eq!(entry.key,
+        "SELECT(<__std__::str>$0)IF(<__std__::str>$1)=x \
+         ELSE(<__std__::str>$2)");
+    assert_eq!(entry.variables[1].value, Value::Str("b".into()));
+}
+
+#[test]
+fn test_errors() {
+    let err = normalize("SELECT 1 + 9223372036854775809").unwrap_err();
+    assert_eq!(err, Error::IntOverflow {
+        start: Pos { line: 1, column: 12, offset: 11 },
+        end: Pos { line: 1, column: 31, offset: 30 },
+    });
+    assert_eq!(err.to_string(), "number is out of range for std::int64");
+
+    let err = normalize("SELECT\n  1e999").unwrap_err();
+    assert!(matches!(err, Error::FloatOutOfRange { .. }));
+    assert_eq!(err.start(), Pos { line: 2, column: 3, offset: 9 });
+    assert_eq!(err.end(), Pos { line: 2, column: 8, offset: 14 });
+    assert_eq!(err.to_string(), "number is out of range for std::float64");
+
+    assert!(matches!(normalize(r#"SELECT b"\q""#),
+                     Err(Error::InvalidEscape { .. })));
+    assert!(matches!(normalize("SELECT 'a' ! 1"),
+                     Err(Error::Tokenizer { .. })));
+}
+
+#[test]
+fn test_mixed_arguments() {
+    let err = normalize("SELECT $0 + $a + 1").unwrap_err();
+    assert_eq!(err, Error::MixedArguments {
+        start: Pos { line: 1, column: 13, offset: 12 },
+        end: Pos { line: 1, column: 15, offset: 14 },
+    });
+    assert_eq!(err.to_string(),
+               "cannot combine positional and named arguments");
+    assert!(matches!(normalize("SELECT $a + $0"),
+                     Err(Error::MixedArguments { .. })));
+    assert!(matches!(normalize("SELECT $18446744073709551615"),
+                     Err(Error::IntOverflow { .. })));
+
+    // arguments are not checked if nothing can be extracted
+    let options = NormalizeOptions {
+        extract: Default::default(),
+        .. NormalizeOptions::default()
+    };
+    let entry = normalize_with_options("SELECT $0 + $a + 1", &options)
+        .unwrap();
+    assert_eq!(entry.key, "SELECT$0+$a+1");
+    assert_eq!(entry.variables, vec![]);
+    let entry = normalize("CONFIGURE SYSTEM SET x := $0 + $a").unwrap();
+    assert_eq!(entry.key, "CONFIGURE SYSTEM SET x:=$0+$a");
+}
+
+#[test]
+fn test_options_extract() {
+    let options = NormalizeOptions {
+        extract: vec![Literal::Int].into_iter().collect(),
+        .. NormalizeOptions::default()
+    };
+    let entry = normalize_with_options(r###"
+        SELECT "x" ++ <str>1.5 ++ <str>2
+    "###, &options).unwrap();
+    assert_eq!(entry.key,
+        "SELECT \"x\"++<str>1.5++<str>(<__std__::int64>$0)");
+    assert_eq!(entry.variables, vec![
+        Variable {
+            value: Value::Int(2),
+        },
+    ]);
+    assert_eq!(Literal::from_type_name("float64"), Some(Literal::Float));
+    assert_eq!(Literal::from_type_name("float"), None);
+}
+
+#[test]
+fn test_options_preserve() {
+    let options = NormalizeOptions {
+        preserve: vec![Preserve::new("offset", None)],
+        .. NormalizeOptions::default()
+    };
+    let entry = normalize_with_options(r###"
+        SELECT User LIMIT 1 OFFSET 10
+    "###, &options).unwrap();
+    assert_eq!(entry.key, "SELECT User LIMIT(<__std__::int64>$0)OFFSET 10");
+    assert_eq!(entry.variables, vec![
+        Variable {
+            value: Value::Int(1),
+        },
+    ]);
+}
+
+#[test]
+fn test_options_preserve_extra() {
+    // what python `normalize(preserve=[...])` does, defaults are kept
+    let mut options = NormalizeOptions::default();
+    options.preserve.push(
+        Preserve::new("offset", None).with_kinds(&[Literal::Int]));
+    let entry = normalize_with_options(r###"
+        SELECT 'a' IF true ELSE 'b' LIMIT 1 OFFSET 10
+    "###, &options).unwrap();
+    assert_eq!(entry.key,
+        "SELECT(<__std__::str>$0)IF true ELSE(<__std__::str>$1)\
+         LIMIT 1 OFFSET 10");
+    let entry = normalize_with_options(r###"
+        SELECT 'a' IF 1 > 0 ELSE 'b' OFFSET 1.5
+    "###, &options).unwrap();
+    assert_eq!(entry.key,
+        "SELECT(<__std__::str>$0)IF(<__std__::int64>$1)>\
+         (<__std__::int64>$2)ELSE(<__std__::str>$3)\
+         OFFSET(<__std__::float64>$4)");
+}
+
+#[test]
+fn test_options_max_args() {
+    let options = NormalizeOptions {
+        max_args: Some(2),
+        .. NormalizeOptions::default()
+    };
+    let entry = normalize_with_options(r###"
+        SELECT {1, 2, 3}
+    "###, &options).unwrap();
+    assert_eq!(entry.key,
+        "SELECT{(<__std__::int64>$0),(<__std__::int64>$1),3}");
+    assert_eq!(entry.variables.len(), 2);
+}
+
+#[test]
+fn test_options_skip_statements() {
+    let options = NormalizeOptions {
+        skip_statements: vec!["INSERT".into()],
+        .. NormalizeOptions::default()
+    };
+    let entry = normalize_with_options(r###"
+        CONFIGURE SYSTEM SET some_setting := 7
+    "###, &options).unwrap();
+    assert_eq!(entry.key,
+        "CONFIGURE SYSTEM SET some_setting:=(<__std__::int64>$0)");
+    let entry = normalize_with_options(r###"
+        INSERT User { name := 'x' }
+    "###, &options).unwrap();
+    assert_eq!(entry.key, "INSERT User{name:='x'}");
+    assert_eq!(entry.variables, vec![]);
+}
+
+#[test]
+fn test_script() {
+    let text = "SELECT 1 + $0;\n  CONFIGURE SYSTEM SET x := 7;\
+                INSERT User { name := 'x' }; # comment\n";
+    let entries = normalize_script(text, &NormalizeOptions::default())
+        .unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].key, "SELECT(<__std__::int64>$1)+$0;");
+    assert_eq!(entries[0].first_arg, Some(1));
+    assert_eq!(entries[0].range, 0..14);
+    assert_eq!(entries[1].key, "CONFIGURE SYSTEM SET x:=7;");
+    assert_eq!(entries[1].variables, vec![]);
+    assert_eq!(entries[1].range, 14..45);
+    assert_eq!(entries[2].key, "INSERT User{name:=(<__std__::str>$0)};");
+    assert_eq!(entries[2].first_arg, Some(0));
+    assert_eq!(entries[2].variables, vec![
+        Variable {
+            value: Value::Str("x".into()),
+        },
+    ]);
+    assert_eq!(entries[2].range, 45..73);
+    assert_eq!(entries[2].tokens[0].start,
+               Pos { line: 2, column: 31, offset: 45 });
+
+    let entries = normalize_script("SELECT 1; SELECT (2",
+                                   &NormalizeOptions::default()).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].key, "SELECT((<__std__::int64>$0)");
+    assert_eq!(entries[1].range, 9..19);
+    assert_eq!(normalize_script(" # nothing", &NormalizeOptions::default())
+               .unwrap().len(), 0);
+}
+
+#[test]
+fn test_collapse_arrays() {
+    let options = NormalizeOptions {
+        collapse_arrays: true,
+        .. NormalizeOptions::default()
+    };
+    let entry = normalize_with_options(r###"
+        SELECT User FILTER .id IN array_unpack([1, 2, 3]) LIMIT 1
+    "###, &options).unwrap();
+    assert_eq!(entry.key,
+        "SELECT User FILTER.id IN \
+         array_unpack((<array<__std__::int64>>$0))LIMIT 1");
+    assert_eq!(entry.variables, vec![
+        Variable {
+            value: Value::Array(vec![
+                Value::Int(1), Value::Int(2), Value::Int(3),
+            ]),
+        },
+    ]);
+    let longer = normalize_with_options(r###"
+        SELECT User FILTER .id IN array_unpack([1, 2, 3, 4, 5]) LIMIT 1
+    "###, &options).unwrap();
+    assert_eq!(longer.key, entry.key);
+
+    let entry = normalize_with_options(r###"
+        SELECT (['a', 'b'], .names[1], [1, 'x'], [1.5])
+    "###, &options).unwrap();
+    assert_eq!(entry.key,
+        "SELECT((<array<__std__::str>>$0),.names[(<__std__::int64>$1)],\
+         [(<__std__::int64>$2),(<__std__::str>$3)],\
+         (<array<__std__::float64>>$4))");
+    assert_eq!(entry.variables[0], Variable {
+        value: Value::Array(vec![
+            Value::Str("a".into()), Value::Str("b".into()),
+        ]),
+    });
+    assert_eq!(entry.variables[4], Variable {
+        value: Value::Array(vec![Value::Float(1.5)]),
+    });
+
+    // arrays aren't collapsed by default
+    let entry = normalize("SELECT [1, 2]").unwrap();
+    assert_eq!(entry.key,
+        "SELECT[(<__std__::int64>$0),(<__std__::int64>$1)]");
+}
+
+#[test]
+fn test_denormalize() {
+    let entry = normalize(r###"
+        SELECT User { name } FILTER .name = 'a\\b"c' AND .age > 1_000
+            AND .score > 1.5 AND .big = 12n AND .dec = 1.25n
+            AND .raw = b'\x00"' AND .active = true AND .pos = ($1, $2).1
+        LIMIT 1  # comment
+    "###).unwrap();
+    assert_eq!(denormalize(&entry),
+        "SELECT User{name}FILTER.name=\"a\\\\b\\\"c\" AND.age>1000 \
+         AND.score>1.5 AND.big=12n AND.dec=1.25n \
+         AND.raw=b\"\\x00\\\"\" AND.active=true \
+         AND.pos=($1,$2).1 LIMIT 1");
+    let same = denormalize_key(&entry.key, &entry.variables,
+        entry.named_args, entry.first_arg).unwrap();
+    assert_eq!(same, denormalize(&entry));
+    let again = normalize(&same).unwrap();
+    assert_eq!(again.key, entry.key);
+    assert_eq!(again.variables, entry.variables);
+
+    let entry = normalize("SELECT $x + 2.0 + 1e100").unwrap();
+    assert_eq!(denormalize(&entry), "SELECT$x+2.0+1e100");
+
+    let options = NormalizeOptions {
+        collapse_arrays: true,
+        .. NormalizeOptions::default()
+    };
+    let entry = normalize_with_options("SELECT ['a', 'b'] ++ [1]",
+                                       &options).unwrap();
+    assert_eq!(denormalize(&entry), r#"SELECT["a","b"]++[1]"#);
+
+    let entry = normalize("CONFIGURE SYSTEM SET x := 7").unwrap();
+    assert_eq!(denormalize(&entry), entry.key);
+}
+
+#[test]
+fn test_fingerprint() {
+    let entry = normalize("SELECT User { name } FILTER .id = 1").unwrap();
+    let same = normalize(r###"
+        SELECT User {
+            name  # comment
+        } FILTER .id = 12345
+    "###).unwrap();
+    assert_eq!(entry.fingerprint, same.fingerprint);
+    let other = normalize("SELECT User { name } FILTER .id = 1.0").unwrap();
+    assert_ne!(entry.fingerprint, other.fingerprint);
+    // tokens `ab` `c` differ from `a` `bc`
+    assert_ne!(normalize("SELECT ab c").unwrap().fingerprint,
+               normalize("SELECT a bc").unwrap().fingerprint);
+    let hex = entry.fingerprint.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    assert_eq!(hex,
+        "97cbb98aa9dd6eb903eb2cd1a9f484019505eca8b0f4fede6f273d614f9ad9da");
+}
+
+fn roundtrip(values: Vec<Value>, types: &[ArgType]) {
+    let variables = values.into_iter()
+        .map(|value| Variable { value })
+        .collect::<Vec<_>>();
+    let blob = serialize_extra(&variables).unwrap();
+    let decoded = deserialize_extra(&blob, types).unwrap();
+    assert_eq!(decoded.len(), variables.len());
+    for (value, var) in decoded.iter().zip(&variables) {
+        assert_eq!(*value, var.value);
+    }
+}
+
+#[test]
+fn test_extra_roundtrip() {
+    use ArgType::{Scalar, Array};
+
+    roundtrip(vec![], &[]);
+    roundtrip(vec![
+        Value::Int(0),
+        Value::Int(i64::MIN),
+        Value::Float(-1.5e300),
+        Value::BigInt("-123456789012345678901234567890".parse().unwrap()),
+        Value::Decimal("12345.678900".parse().unwrap()),
+        Value::Str("".into()),
+        Value::Str("юникод \"quoted\"\n".into()),
+        Value::Bytes(b"\x00\xff".to_vec()),
+        Value::Bool(true),
+        Value::Array(vec![Value::Int(1), Value::Int(2)]),
+        Value::Array(vec![Value::Str("a".into())]),
+        Value::Array(vec![]),
+    ], &[
+        Scalar(Literal::Int), Scalar(Literal::Int), Scalar(Literal::Float),
+        Scalar(Literal::BigInt), Scalar(Literal::Decimal),
+        Scalar(Literal::Str), Scalar(Literal::Str), Scalar(Literal::Bytes),
+        Scalar(Literal::Bool), Array(Literal::Int), Array(Literal::Str),
+        Array(Literal::Bool),
+    ]);
+
+    // pseudo-random values of every scalar kind
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..100 {
+        let num = next();
+        let len = (num % 17) as usize;
+        let bytes = (0..len).map(|_| next() as u8).collect::<Vec<_>>();
+        let text = (0..len)
+            .map(|_| std::char::from_u32((next() % 0x800) as u32)
+                     .unwrap_or('?'))
+            .collect::<String>();
+        roundtrip(vec![
+            Value::Int(num as i64),
+            Value::Float(f64::from_bits(num >> 2)),
+            Value::BigInt(((num >> 1) as i64).into()),
+            Value::Str(text),
+            Value::Bytes(bytes),
+            Value::Bool(num % 2 == 0),
+            Value::Array((0..len).map(|i| Value::Int(i as i64)).collect()),
+        ], &[
+            Scalar(Literal::Int), Scalar(Literal::Float),
+            Scalar(Literal::BigInt), Scalar(Literal::Str),
+            Scalar(Literal::Bytes), Scalar(Literal::Bool),
+            Array(Literal::Int),
+        ]);
+    }
+}
+
+#[test]
+fn test_extra_from_normalize() {
+    let options = NormalizeOptions {
+        collapse_arrays: true,
+        .. extract_bool()
+    };
+    let entry = normalize_with_options(
+        "SELECT (1, 'x', [1.5, 2.5], b'y', false, 10n, 1.5n)",
+        &options).unwrap();
+    let blob = serialize_extra(&entry.variables).unwrap();
+    let values = deserialize_extra(&blob, &[
+        ArgType::Scalar(Literal::Int),
+        ArgType::Scalar(Literal::Str),
+        ArgType::Array(Literal::Float),
+        ArgType::Scalar(Literal::Bytes),
+        ArgType::Scalar(Literal::Bool),
+        ArgType::Scalar(Literal::BigInt),
+        ArgType::Scalar(Literal::Decimal),
+    ]).unwrap();
+    // this is what `Entry.arg_types()` returns to python
+    let names = entry.variables.iter()
+        .map(|var| var.value.arg_type().unwrap().type_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["int64", "str", "array<float64>", "bytes", "bool",
+                       "bigint", "decimal"]);
+    let types = names.iter()
+        .map(|name| ArgType::from_type_name(name).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(deserialize_extra(&blob, &types).unwrap(), values);
+    let expected = entry.variables.into_iter()
+        .map(|var| var.value)
+        .collect::<Vec<_>>();
+    assert_eq!(values, expected);
+    assert_eq!(ArgType::from_type_name("array<x>"), None);
+    assert_eq!(ArgType::from_type_name("array<int64"), None);
+    assert_eq!(Value::Array(vec![]).arg_type(), None);
+}
+
+#[test]
+fn test_extra_errors() {
+    let blob = serialize_extra(&[
+        Variable { value: Value::Int(1) },
+        Variable { value: Value::Str("abc".into()) },
+    ]).unwrap();
+    assert_eq!(deserialize_extra(&blob, &[ArgType::Scalar(Literal::Int)])
+               .unwrap_err(),
+               "7 bytes left after the last value");
+    assert_eq!(deserialize_extra(&blob[..18], &[
+                    ArgType::Scalar(Literal::Int),
+                    ArgType::Scalar(Literal::Str),
+               ]).unwrap_err(),
+               "element of 3 bytes is truncated");
+    assert_eq!(deserialize_extra(&blob[..14], &[
+                    ArgType::Scalar(Literal::Int),
+                    ArgType::Scalar(Literal::Str),
+               ]).unwrap_err(),
+               "unexpected end of data");
+    assert!(deserialize_extra(&blob, &[
+                    ArgType::Array(Literal::Int),
+                    ArgType::Scalar(Literal::Str),
+               ]).is_err());
+}
+
+#[test]
+fn test_many() {
+    let texts = ["SELECT 1", "SELECT 'a", "SELECT 'x' ++ 'y'"];
+    for &parallel in &[false, true] {
+        let results = normalize_many(&texts, &NormalizeOptions::default(),
+                                     parallel);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().key,
+                   "SELECT(<__std__::int64>$0)");
+        assert!(matches!(results[1], Err(Error::Tokenizer { .. })));
+        assert_eq!(results[2].as_ref().unwrap().variables.len(), 2);
+
+        let results = tokenize_many(&texts, parallel);
+        assert_eq!(results.len(), 3);
+        let (tokens, end) = results[0].as_ref().unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(*end, Pos { line: 1, column: 9, offset: 8 });
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap().0.len(), 4);
+    }
+}
@@ -0,0 +1,164 @@
+use edgeql_parser::position::Pos;
+use edgeql_parser::tokenizer::{Kind, TokenStream};
+use edgeql_rust::normalize::{normalize_script, NormalizeOptions};
+use edgeql_rust::tokens::{dump_tokens, load_tokens, CowToken, KINDS};
+
+
+fn tokenize(text: &str) -> (Vec<CowToken<'_>>, Pos) {
+    let mut stream = TokenStream::new(text);
+    let tokens = (&mut stream)
+        .map(|res| res.map(CowToken::from))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    (tokens, stream.current_pos())
+}
+
+fn assert_same(a: &[CowToken], b: &[CowToken]) {
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.iter().zip(b) {
+        assert_eq!(a.kind, b.kind);
+        assert_eq!(a.value, b.value);
+        assert_eq!(a.start, b.start);
+        assert_eq!(a.end, b.end);
+    }
+}
+
+#[test]
+fn test_kinds() {
+    for (idx, kind) in KINDS.iter().enumerate() {
+        assert_eq!(*kind as usize, idx);
+    }
+}
+
+#[test]
+fn test_roundtrip() {
+    let text = "SELECT User { name, friends: { name } }\n\
+                FILTER .name = 'Ærøskøbing'\n  \
+                    AND .age > 1_000 LIMIT $limit;";
+    let (tokens, end_pos) = tokenize(text);
+    let data = dump_tokens(&tokens, end_pos);
+    // `name` and `{` are stored once
+    assert_eq!(data.iter().filter(|&&b| b == b'{').count(), 1);
+    assert!(data.len() < text.len() + tokens.len() * 5);
+
+    let loaded = load_tokens(&data).unwrap();
+    assert_same(&loaded.tokens, &tokens);
+    assert_eq!(loaded.end_pos, end_pos);
+    for tok in &loaded.tokens {
+        let range = tok.value.as_ptr() as usize
+            - loaded.strings.as_ptr() as usize;
+        assert!(range + tok.value.len() <= loaded.strings.len());
+    }
+}
+
+#[test]
+fn test_roundtrip_normalized() {
+    let text = "SELECT 1;\nSELECT 'x' ++ 'y';";
+    let entries = normalize_script(text, &NormalizeOptions::default())
+        .unwrap();
+    for entry in entries {
+        let data = dump_tokens(&entry.tokens, entry.end_pos);
+        let loaded = load_tokens(&data).unwrap();
+        assert_same(&loaded.tokens, &entry.tokens);
+        assert_eq!(loaded.end_pos, entry.end_pos);
+    }
+}
+
+#[test]
+fn test_empty() {
+    let (tokens, end_pos) = tokenize("  # comment\n");
+    let data = dump_tokens(&tokens, end_pos);
+    assert_eq!(data, b"\x01\x00\x00\x02\x00\x18");
+    let loaded = load_tokens(&data).unwrap();
+    assert!(loaded.tokens.is_empty());
+    assert_eq!(loaded.end_pos, Pos { line: 2, column: 1, offset: 12 });
+}
+
+#[test]
+fn test_errors() {
+    let (tokens, end_pos) = tokenize("SELECT 'x'");
+    let data = dump_tokens(&tokens, end_pos);
+    assert_eq!(load_tokens(&data[..0]).unwrap_err(),
+               "unexpected end of data");
+    assert_eq!(load_tokens(b"\x02").unwrap_err(),
+               "unsupported token dump version 2");
+    assert_eq!(load_tokens(&data[..data.len()-1]).unwrap_err(),
+               "unexpected end of data");
+    let mut extra = data.clone();
+    extra.push(0);
+    assert_eq!(load_tokens(&extra).unwrap_err(),
+               "1 bytes left after tokens");
+    assert_eq!(load_tokens(b"\x01\x01\x01\xff\x00").unwrap_err(),
+               "invalid string table: \
+                invalid utf-8 sequence of 1 bytes from index 0");
+    assert_eq!(load_tokens(b"\x01\x00\x01\x00\x00").unwrap_err(),
+               "invalid string index 0");
+    assert_eq!(load_tokens(b"\x01\x00\x01\x7f").unwrap_err(),
+               "invalid token kind 127");
+    assert_eq!(load_tokens(b"\x01\x00\x00\x03\x00\x00").unwrap_err(),
+               "line is out of range");
+    assert_eq!(load_tokens(b"\x01\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff")
+               .unwrap_err(),
+               "varint is too long");
+    // a single token with implied end referring to a single string
+    let dump = |kind: Kind, value: &str| {
+        let mut data = vec![1, 1, value.len() as u8];
+        data.extend_from_slice(value.as_bytes());
+        data.extend_from_slice(&[1, kind as u8 | 0x80, 0, 0, 0, 0, 0, 0, 0]);
+        data
+    };
+    assert_eq!(load_tokens(&dump(Kind::Str, "")).unwrap_err(),
+               r#"invalid Str token """#);
+    assert_eq!(load_tokens(&dump(Kind::Str, "'a")).unwrap_err(),
+               r#"invalid Str token "'a""#);
+    assert_eq!(load_tokens(&dump(Kind::Argument, "é")).unwrap_err(),
+               r#"invalid Argument token "é""#);
+    assert_eq!(load_tokens(&dump(Kind::Argument, "$")).unwrap_err(),
+               r#"invalid Argument token "$""#);
+    assert_eq!(load_tokens(&dump(Kind::Argument, "$a")).unwrap().tokens[0]
+               .value, "$a");
+}
+
+
+fn load_value(kind: Kind, value: &str) -> Result<(), String> {
+    let start = Pos { line: 1, column: 1, offset: 0 };
+    let token = CowToken { kind, value: value.into(), start, end: start };
+    load_tokens(&dump_tokens(&[token], start)).map(|_| ())
+}
+
+#[test]
+fn test_invalid_values() {
+    use Kind::*;
+
+    assert_eq!(load_value(Str, "").unwrap_err(), r#"invalid Str token """#);
+    assert_eq!(load_value(Str, "'x").unwrap_err(),
+               r#"invalid Str token "'x""#);
+    assert_eq!(load_value(Str, "r'é").unwrap_err(),
+               r#"invalid Str token "r'é""#);
+    assert_eq!(load_value(BinStr, "").unwrap_err(),
+               r#"invalid BinStr token """#);
+    assert_eq!(load_value(BinStr, r"b'\'").unwrap_err(),
+               r#"invalid BinStr token "b'\\'""#);
+    assert_eq!(load_value(Argument, "").unwrap_err(),
+               r#"invalid Argument token """#);
+    assert_eq!(load_value(Argument, "é").unwrap_err(),
+               r#"invalid Argument token "é""#);
+    assert_eq!(load_value(DecimalConst, "").unwrap_err(),
+               r#"invalid DecimalConst token """#);
+    assert_eq!(load_value(BigIntConst, "1é").unwrap_err(),
+               r#"invalid BigIntConst token "1é""#);
+    assert_eq!(load_value(BacktickName, "`").unwrap_err(),
+               r#"invalid BacktickName token "`""#);
+    // the value must be a single token of the same kind
+    assert_eq!(load_value(Str, "'x' 'y'").unwrap_err(),
+               r#"invalid Str token "'x' 'y'""#);
+    assert_eq!(load_value(Str, "1").unwrap_err(),
+               r#"invalid Str token "1""#);
+
+    load_value(Str, "r'x'").unwrap();
+    load_value(BinStr, r"b'\x00'").unwrap();
+    load_value(Argument, "$`a b`").unwrap();
+    load_value(DecimalConst, "1.5n").unwrap();
+    load_value(BigIntConst, "1n").unwrap();
+    load_value(BacktickName, "`x`").unwrap();
+}
@@ -19,30 +19,31 @@
 
 from __future__ import annotations
 
-from typing import Deque, Optional, Union, List
-from collections import deque
+from typing import Iterator, Optional, Sequence, Union
 from edb._edgeql_rust import tokenize as _tokenize, Token
 
 
 class EdgeQLLexer(object):
     inputstr: str
-    tokens: Optional[Deque[Token]]
+    tokens: Optional[Iterator[Token]]
     filename: Optional[str]
     end_of_input: (int, int, int)
 
     def __init__(self):
         self.filename = None  # TODO
 
-    def setinputstr(self, text: Union[str, List[Token]]) -> None:
+    def setinputstr(self, text: Union[str, Sequence[Token]]) -> None:
         if isinstance(text, str):
             self.inputstr = text
-            self.tokens = deque(_tokenize(text))
-            self.end_of_input = self.tokens[-1].end()
+            tokens = _tokenize(text)
         else:
             self.inputstr = None
-            self.tokens = deque(text)
-            self.end_of_input = self.tokens[-1].end()
+            tokens = text
+        # `TokenList` creates tokens on access, so they are pulled
+        # one by one rather than copied into a deque
+        self.end_of_input = tokens[-1].end()
+        self.tokens = iter(tokens)
 
     def token(self) -> Token:
-        if self.tokens:
-            return self.tokens.popleft()
+        if self.tokens is not None:
+            return next(self.tokens, None)
@@ -1,5 +1,3 @@
-#[macro_use] extern crate cpython;
-
 mod pytoken;
 mod pyentry;
 mod pyerrors;
@@ -1,9 +1,11 @@
+import hashlib
 import re
 
-from typing import Optional, List, Tuple, Dict, Any
+from typing import Optional, List, Tuple, Dict, Any, Union
 
 from edb._edgeql_rust import tokenize as _tokenize, TokenizerError, Token
 from edb._edgeql_rust import normalize as _normalize, Entry
+from edb._edgeql_rust import normalize_many as _normalize_many
 
 from edb.common import debug
 from edb.errors import base as base_errors, EdgeQLSyntaxError
@@ -36,6 +38,15 @@ class Denormalized:
     def extra_blob(self) -> bytes:
         return b''
 
+    def range(self) -> Tuple[int, int]:
+        return (0, len(self._source.encode()))
+
+    def denormalize(self) -> str:
+        return self._source
+
+    def fingerprint(self) -> bytes:
+        return hashlib.sha256(self._source.encode()).digest()
+
 
 def tokenize(eql: bytes) -> List[Token]:
     try:
@@ -48,14 +59,14 @@ def tokenize(eql: bytes) -> List[Token]:
             message, position=position, hint=hint) from e
 
 
-def normalize(eql: bytes) -> List[Entry]:
+def normalize(eql: bytes, **options: Any) -> List[Entry]:
     if debug.flags.edgeql_disable_normalization:
         return Denormalized(eql.decode(), tokenize(eql))
     else:
         eql_str = eql.decode()
 
         try:
-            return _normalize(eql_str)
+            return _normalize(eql_str, **options)
         except TokenizerError as e:
             message, position = e.args
             hint = _derive_hint(eql_str, message, position)
@@ -63,6 +74,35 @@ def normalize(eql: bytes) -> List[Entry]:
                 message, position=position, hint=hint) from e
 
 
+def normalize_many(
+    queries: List[bytes],
+    *,
+    parallel: bool = False,
+    **options: Any,
+) -> List[Union[Entry, EdgeQLSyntaxError]]:
+    """Normalize a batch of queries, errors are returned instead of raised"""
+    if debug.flags.edgeql_disable_normalization:
+        result = []
+        for eql in queries:
+            try:
+                result.append(Denormalized(eql.decode(), tokenize(eql)))
+            except EdgeQLSyntaxError as e:
+                result.append(e)
+        return result
+
+    queries_str = [eql.decode() for eql in queries]
+    result = _normalize_many(queries_str, parallel=parallel, **options)
+    for i, (eql_str, entry) in enumerate(zip(queries_str, result)):
+        if isinstance(entry, TokenizerError):
+            message, position = entry.args
+            hint = _derive_hint(eql_str, message, position)
+            error = EdgeQLSyntaxError(
+                message, position=position, hint=hint)
+            error.__cause__ = entry
+            result[i] = error
+    return result
+
+
 def _derive_hint(
     input: str,
     message: str,
@@ -24,6 +24,7 @@ import pathlib
 import platform
 import shutil
 import subprocess
+import sysconfig
 import textwrap
 
 import distutils
@@ -51,7 +52,7 @@ RUNTIME_DEPS = [
     'psutil~=5.6.1',
     'Pygments~=2.3.0',
     'setproctitle~=1.1.10',
-    'setuptools-rust==0.10.3',
+    'setuptools-rust==0.12.1',
     'setuptools_scm~=3.2.0',
     'typing_inspect~=0.5.0',
     'uvloop~=0.14.0',
@@ -403,6 +404,17 @@ class build_postgres(setuptools.Command):
             build_contrib=self.build_contrib)
 
 
+def _rust_ext_fullpath(build_ext, ext):
+    path = pathlib.Path(build_ext.get_ext_fullpath(ext.name))
+    if ext.py_limited_api:
+        # abi3 modules are named `foo.abi3.so` rather than
+        # `foo.cpython-38-x86_64-linux-gnu.so`
+        suffix = sysconfig.get_config_var('EXT_SUFFIX')
+        path = path.with_name(
+            path.name[:-len(suffix)] + '.abi3' + pathlib.Path(suffix).suffix)
+    return path
+
+
 class build_ext(distutils_build_ext.build_ext):
 
     user_options = distutils_build_ext.build_ext.user_options + [
@@ -495,11 +507,9 @@ class build_ext(distutils_build_ext.build_ext):
                 for ext in self.distribution.rust_extensions:
                     # Always build in-place because later stages of the build
                     # may depend on the modules having been built
-                    dylib_path = pathlib.Path(
-                        build_ext.get_ext_fullpath(ext.name))
+                    dylib_path = _rust_ext_fullpath(build_ext, ext)
                     build_ext.inplace = True
-                    target_path = pathlib.Path(
-                        build_ext.get_ext_fullpath(ext.name))
+                    target_path = _rust_ext_fullpath(build_ext, ext)
                     build_ext.inplace = False
                     copy_list.append((dylib_path, target_path))
 
@@ -528,12 +538,14 @@ if setuptools_rust is not None:
         setuptools_rust.RustExtension(
             "edb._edgeql_rust",
             path="edb/edgeql-rust/Cargo.toml",
-            binding=setuptools_rust.Binding.RustCPython,
+            binding=setuptools_rust.Binding.PyO3,
+            py_limited_api=True,
         ),
         setuptools_rust.RustExtension(
             "edb._graphql_rewrite",
             path="edb/graphql-rewrite/Cargo.toml",
-            binding=setuptools_rust.Binding.RustCPython,
+            binding=setuptools_rust.Binding.PyO3,
+            py_limited_api=True,
         ),
     ]
 else: